@@ -0,0 +1,84 @@
+//! Compares the time to evaluate a formula for many arguments on the
+//! [`Vm`], on the [`StackVm`] and with [`Compiler::compile_to_fn`].
+//!
+//! Run with `cargo bench --bench eval`, optionally with the number of
+//! evaluations per formula, e.g. `cargo bench --bench eval -- 100000`.
+use std::{
+    hint::black_box,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use tiny_three_pass_compiler::Compiler;
+
+const FORMULAS: [&str; 3] = [
+    "[ x y ] (x + y) / 2",
+    "[ a b c ] a * a + b * b - (c + 1) * (c - 1) + 2 * a * b",
+    "[ x y ] let d = x - y in if d < 0 then (0 - d) * (x + 7) % 13 else d * d + x * y",
+];
+
+// Evaluates the function for every argument list and returns the
+// time per evaluation.
+fn measure(args: &[Vec<i64>], mut f: impl FnMut(&[i64]) -> i64) -> Duration {
+    let start = Instant::now();
+    for args in args {
+        black_box(f(black_box(args)));
+    }
+    start.elapsed().div_f64(args.len() as f64)
+}
+
+fn main() {
+    // Zero runs would leave nothing to divide the time by.
+    let runs = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<NonZeroUsize>().ok())
+        .map_or(1_000_000, NonZeroUsize::get);
+
+    println!("{runs} evaluations per formula, time per evaluation:\n");
+    println!(
+        "{:>10} {:>10} {:>10}  formula",
+        "vm", "stack vm", "closures"
+    );
+
+    for formula in FORMULAS {
+        let mut c = Compiler::new();
+        let ast = c.pass1(formula).unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        let ast = c.simplify(&ast);
+        let asm = c.pass3_typed(&ast);
+        let stack = c.pass3_stack(&ast);
+        let f = c.compile_to_fn(formula).unwrap();
+
+        // Pseudo-random arguments, the same for every evaluator.
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let args: Vec<Vec<i64>> = (0..runs)
+            .map(|_| {
+                (0..c.arg_names().len())
+                    .map(|_| {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 7;
+                        seed ^= seed << 17;
+                        (seed % 2001) as i64 - 1000
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let (vm, stack_vm) = (c.vm(), c.stack_vm());
+        for args in args.iter().take(1000) {
+            let result = vm.run(&asm, args).ok();
+            assert_eq!(stack_vm.run(&stack, args).ok(), result, "{formula}");
+            assert_eq!(f(args).ok(), result, "{formula}");
+        }
+
+        let times = [
+            measure(&args, |args| vm.run(&asm, args).unwrap()),
+            measure(&args, |args| stack_vm.run(&stack, args).unwrap()),
+            measure(&args, |args| f(args).unwrap()),
+        ];
+        println!(
+            "{:>10?} {:>10?} {:>10?}  {formula}",
+            times[0], times[1], times[2]
+        );
+    }
+}
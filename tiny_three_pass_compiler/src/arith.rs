@@ -0,0 +1,158 @@
+use crate::{ast::BinOp, error::ArithmeticError};
+
+/// How arithmetic behaves when a result does not fit into an `i64`.
+///
+/// The mode is chosen on the [`Compiler`](crate::Compiler) and used
+/// both for constant folding and by the [`Vm`](crate::Vm), so that
+/// folding never changes the result of a program. Division by zero
+/// is an error in every mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ArithmeticMode {
+    /// Overflow is an error.
+    Checked,
+    /// Results wrap around at the boundaries of `i64`.
+    #[default]
+    Wrapping,
+    /// Results are clamped to `i64::MIN` and `i64::MAX`.
+    Saturating,
+}
+
+impl ArithmeticMode {
+    /// Applies the operator to both operands.
+    ///
+    /// Division truncates towards zero and the remainder has the
+    /// sign of the dividend. A negative exponent is evaluated like
+    /// `1 / lhs ^ -rhs`, i.e., it is zero unless `lhs` is 1 or -1.
+    pub fn apply(self, op: BinOp, lhs: i64, rhs: i64) -> Result<i64, ArithmeticError> {
+        match op {
+            BinOp::Div | BinOp::Rem if rhs == 0 => return Err(ArithmeticError::DivisionByZero),
+            BinOp::Pow if lhs == 0 && rhs < 0 => return Err(ArithmeticError::DivisionByZero),
+            // The remainder of `i64::MIN % -1` is 0 and cannot overflow.
+            BinOp::Rem => return Ok(lhs.wrapping_rem(rhs)),
+            BinOp::Pow if lhs == -1 => return Ok(if rhs % 2 == 0 { 1 } else { -1 }),
+            BinOp::Pow if rhs < 0 => return Ok(1 / lhs),
+            BinOp::Eq => return Ok((lhs == rhs) as i64),
+            BinOp::Ne => return Ok((lhs != rhs) as i64),
+            BinOp::Lt => return Ok((lhs < rhs) as i64),
+            BinOp::Le => return Ok((lhs <= rhs) as i64),
+            BinOp::Gt => return Ok((lhs > rhs) as i64),
+            BinOp::Ge => return Ok((lhs >= rhs) as i64),
+            _ => {}
+        }
+
+        // Exponents beyond `u32::MAX` overflow for any base other
+        // than 0, 1 and -1, unless wrapping around.
+        let exp = u32::try_from(rhs).unwrap_or(u32::MAX - 1);
+
+        let result = match self {
+            Self::Checked => match op {
+                BinOp::Add => lhs.checked_add(rhs),
+                BinOp::Sub => lhs.checked_sub(rhs),
+                BinOp::Mul => lhs.checked_mul(rhs),
+                BinOp::Div => lhs.checked_div(rhs),
+                BinOp::Pow => lhs.checked_pow(exp),
+                _ => unreachable!(),
+            },
+            Self::Wrapping => Some(match op {
+                BinOp::Add => lhs.wrapping_add(rhs),
+                BinOp::Sub => lhs.wrapping_sub(rhs),
+                BinOp::Mul => lhs.wrapping_mul(rhs),
+                BinOp::Div => lhs.wrapping_div(rhs),
+                BinOp::Pow => wrapping_pow(lhs, rhs as u64),
+                _ => unreachable!(),
+            }),
+            Self::Saturating => Some(match op {
+                BinOp::Add => lhs.saturating_add(rhs),
+                BinOp::Sub => lhs.saturating_sub(rhs),
+                BinOp::Mul => lhs.saturating_mul(rhs),
+                BinOp::Div => lhs.saturating_div(rhs),
+                // The sign of the result depends on the parity of the exponent.
+                BinOp::Pow => lhs.saturating_pow(exp - exp % 2 + (rhs % 2) as u32),
+                _ => unreachable!(),
+            }),
+        };
+
+        result.ok_or(ArithmeticError::Overflow)
+    }
+}
+
+// Exponentiation by squaring, which is exact modulo 2^64.
+fn wrapping_pow(mut base: i64, mut exp: u64) -> i64 {
+    let mut acc: i64 = 1;
+
+    while exp > 0 {
+        if exp % 2 == 1 {
+            acc = acc.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp /= 2;
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ArithmeticMode::*;
+
+    #[test]
+    fn apply() {
+        assert_eq!(
+            Checked.apply(BinOp::Add, i64::MAX, 1),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(Wrapping.apply(BinOp::Add, i64::MAX, 1), Ok(i64::MIN));
+        assert_eq!(Saturating.apply(BinOp::Add, i64::MAX, 1), Ok(i64::MAX));
+
+        assert_eq!(
+            Checked.apply(BinOp::Div, i64::MIN, -1),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(Wrapping.apply(BinOp::Div, i64::MIN, -1), Ok(i64::MIN));
+        assert_eq!(Saturating.apply(BinOp::Div, i64::MIN, -1), Ok(i64::MAX));
+
+        assert_eq!(
+            Checked.apply(BinOp::Pow, 3, 40),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(
+            Wrapping.apply(BinOp::Pow, 3, 40),
+            Ok(3_i64.wrapping_pow(40))
+        );
+        assert_eq!(Saturating.apply(BinOp::Pow, -3, 41), Ok(i64::MIN));
+        assert_eq!(Saturating.apply(BinOp::Pow, -2, 1 << 40), Ok(i64::MAX));
+        assert_eq!(
+            Saturating.apply(BinOp::Pow, -2, (1 << 40) + 1),
+            Ok(i64::MIN)
+        );
+        assert_eq!(
+            Wrapping.apply(BinOp::Pow, 3, 1 << 40),
+            Ok(wrapping_pow(3, 1 << 40))
+        );
+
+        for mode in [Checked, Wrapping, Saturating] {
+            assert_eq!(mode.apply(BinOp::Sub, 1, 2), Ok(-1));
+            assert_eq!(mode.apply(BinOp::Div, -7, 2), Ok(-3));
+            assert_eq!(mode.apply(BinOp::Rem, -7, 2), Ok(-1));
+            assert_eq!(mode.apply(BinOp::Rem, i64::MIN, -1), Ok(0));
+            assert_eq!(mode.apply(BinOp::Pow, 2, 10), Ok(1024));
+            assert_eq!(mode.apply(BinOp::Pow, 0, 0), Ok(1));
+            assert_eq!(mode.apply(BinOp::Pow, 2, -1), Ok(0));
+            assert_eq!(mode.apply(BinOp::Pow, -1, -3), Ok(-1));
+            assert_eq!(mode.apply(BinOp::Pow, -1, -2), Ok(1));
+            assert_eq!(mode.apply(BinOp::Pow, -1, i64::MAX - 1), Ok(1));
+            assert_eq!(mode.apply(BinOp::Lt, -1, 2), Ok(1));
+            assert_eq!(mode.apply(BinOp::Ge, -1, 2), Ok(0));
+            assert_eq!(mode.apply(BinOp::Ne, 3, 3), Ok(0));
+
+            for op in [BinOp::Div, BinOp::Rem] {
+                assert_eq!(mode.apply(op, 1, 0), Err(ArithmeticError::DivisionByZero));
+            }
+            assert_eq!(
+                mode.apply(BinOp::Pow, 0, -1),
+                Err(ArithmeticError::DivisionByZero)
+            );
+        }
+    }
+}
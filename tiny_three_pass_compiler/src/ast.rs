@@ -0,0 +1,361 @@
+use std::fmt::{self, Display};
+
+use crate::{
+    arith::ArithmeticMode,
+    dot,
+    error::{CompileError, EvalError, JsonError},
+    eval, json,
+    number::{Domain, Number},
+    unparse,
+};
+
+/// A binary operator. Comparisons evaluate to 1 if they hold and to 0 otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    /// Returns the operator for its source symbol, e.g. `"+"`.
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            "%" => Some(Self::Rem),
+            "^" => Some(Self::Pow),
+            "==" => Some(Self::Eq),
+            "!=" => Some(Self::Ne),
+            "<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            ">" => Some(Self::Gt),
+            ">=" => Some(Self::Ge),
+            _ => None,
+        }
+    }
+
+    /// Returns the source symbol of the operator.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Pow => "^",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    /// Returns the operator that gives the same result with swapped
+    /// operands, i.e., `b mirrored a == a op b`, if there is one.
+    pub fn mirrored(&self) -> Option<Self> {
+        match self {
+            Self::Add | Self::Mul | Self::Eq | Self::Ne => Some(*self),
+            Self::Lt => Some(Self::Gt),
+            Self::Le => Some(Self::Ge),
+            Self::Gt => Some(Self::Lt),
+            Self::Ge => Some(Self::Le),
+            Self::Sub | Self::Div | Self::Rem | Self::Pow => None,
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Formats the AST with [`Ast::to_source`], naming the arguments `a`, `b`, etc.
+impl Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_source(&[]))
+    }
+}
+
+/// The abstract syntax tree of a compiled function body.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ast {
+    /// An immediate (constant) value.
+    Imm(i64),
+    /// A reference to the n-th function argument.
+    Arg(usize),
+    /// A binary operation on two subexpressions.
+    BinOp(BinOp, Box<Self>, Box<Self>),
+    /// Evaluates the first expression into a local slot and then
+    /// evaluates the second expression, which may refer to it.
+    Let(Box<Self>, Box<Self>),
+    /// A reference to the local slot of the n-th enclosing [`Ast::Let`],
+    /// counting from the outermost one.
+    Local(usize),
+    /// Evaluates the second expression if the first one is
+    /// non-zero, and the third expression otherwise.
+    If(Box<Self>, Box<Self>, Box<Self>),
+    /// A call of the n-th function of the [`Program`](crate::Program)
+    /// with the given arguments.
+    Call(usize, Vec<Self>),
+}
+
+// Constructors mirror the operator names of the source language
+// rather than implementing `std::ops`, which would require
+// the traits to be in scope at every call site.
+#[allow(clippy::should_implement_trait)]
+impl Ast {
+    pub fn imm(n: i64) -> Self {
+        Self::Imm(n)
+    }
+
+    pub fn arg(idx: usize) -> Self {
+        Self::Arg(idx)
+    }
+
+    pub fn bin_op(op: BinOp, lhs: Self, rhs: Self) -> Self {
+        Self::BinOp(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn add(lhs: Self, rhs: Self) -> Self {
+        Self::bin_op(BinOp::Add, lhs, rhs)
+    }
+
+    pub fn sub(lhs: Self, rhs: Self) -> Self {
+        Self::bin_op(BinOp::Sub, lhs, rhs)
+    }
+
+    pub fn mul(lhs: Self, rhs: Self) -> Self {
+        Self::bin_op(BinOp::Mul, lhs, rhs)
+    }
+
+    pub fn div(lhs: Self, rhs: Self) -> Self {
+        Self::bin_op(BinOp::Div, lhs, rhs)
+    }
+
+    pub fn let_in(value: Self, body: Self) -> Self {
+        Self::Let(Box::new(value), Box::new(body))
+    }
+
+    pub fn local(slot: usize) -> Self {
+        Self::Local(slot)
+    }
+
+    pub fn if_else(cond: Self, then: Self, otherwise: Self) -> Self {
+        Self::If(Box::new(cond), Box::new(then), Box::new(otherwise))
+    }
+
+    pub fn call(function: usize, args: Vec<Self>) -> Self {
+        Self::Call(function, args)
+    }
+}
+
+impl Ast {
+    /// Returns the value if this is an immediate.
+    pub fn as_imm(&self) -> Option<i64> {
+        match self {
+            Self::Imm(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the argument index if this is an argument reference.
+    pub fn as_arg(&self) -> Option<usize> {
+        match self {
+            Self::Arg(idx) => Some(*idx),
+            _ => None,
+        }
+    }
+
+    /// Returns the operator and both operands if this is a binary operation.
+    pub fn as_bin_op(&self) -> Option<(BinOp, &Self, &Self)> {
+        match self {
+            Self::BinOp(op, lhs, rhs) => Some((*op, lhs, rhs)),
+            _ => None,
+        }
+    }
+
+    /// Returns the bound value and the body if this is a binding.
+    pub fn as_let(&self) -> Option<(&Self, &Self)> {
+        match self {
+            Self::Let(value, body) => Some((value, body)),
+            _ => None,
+        }
+    }
+
+    /// Returns the slot if this is a reference to a local.
+    pub fn as_local(&self) -> Option<usize> {
+        match self {
+            Self::Local(slot) => Some(*slot),
+            _ => None,
+        }
+    }
+
+    /// Returns the condition and both branches if this is a conditional.
+    pub fn as_if(&self) -> Option<(&Self, &Self, &Self)> {
+        match self {
+            Self::If(cond, then, otherwise) => Some((cond, then, otherwise)),
+            _ => None,
+        }
+    }
+
+    /// Returns the function index and the arguments if this is a call.
+    pub fn as_call(&self) -> Option<(usize, &[Self])> {
+        match self {
+            Self::Call(function, args) => Some((*function, args)),
+            _ => None,
+        }
+    }
+
+    /// Prints the AST as a function in source text, e.g. `[ x ] x * 2`,
+    /// with the given argument names. Parentheses are only inserted
+    /// where needed, so that the text parses back to an equal AST.
+    ///
+    /// Arguments without a name are named `a`, `b`, etc. Calls are
+    /// printed by function index, use [`Program::to_source`](crate::Program::to_source)
+    /// to print them by name.
+    pub fn to_source(&self, args: &[String]) -> String {
+        unparse::function(self, args, &[])
+    }
+
+    /// Serializes the AST in the JSON format of the kata, e.g.
+    /// `{"op":"+","a":{"op":"arg","n":0},"b":{"op":"imm","n":2}}`.
+    ///
+    /// Bindings, conditionals and calls, which the kata does not have,
+    /// are written as `{"op":"let","a":value,"b":body}`, `{"op":"local","n":slot}`,
+    /// `{"op":"if","a":cond,"b":then,"c":otherwise}` and
+    /// `{"op":"call","n":function,"args":[...]}`.
+    pub fn to_json(&self) -> String {
+        json::to_json(self)
+    }
+
+    /// Reads an AST in the format written by [`Ast::to_json`].
+    pub fn from_json(json: &str) -> Result<Self, JsonError> {
+        json::from_json(json)
+    }
+
+    /// Renders the AST as a Graphviz digraph, with one node per
+    /// operator, binding, conditional, call and leaf.
+    pub fn to_dot(&self) -> String {
+        dot::digraph(self, &[])
+    }
+
+    /// Evaluates the AST for the given arguments with wrapping
+    /// arithmetic, see [`Ast::eval_with`].
+    pub fn eval(&self, args: &[i64]) -> Result<i64, EvalError> {
+        self.eval_with(args, ArithmeticMode::default())
+    }
+
+    /// Evaluates the AST for the given arguments by walking the tree.
+    ///
+    /// This is the reference semantics of the language: compiling the
+    /// AST and running it gives the same result, or fails as well.
+    /// Calls cannot be resolved outside of a [`Program`](crate::Program)
+    /// and fail with [`EvalError::UnresolvedCall`].
+    pub fn eval_with(&self, args: &[i64], mode: ArithmeticMode) -> Result<i64, EvalError> {
+        eval::eval(self, args.to_vec(), mode, Domain::Integer)
+    }
+
+    /// Evaluates the AST like [`Ast::eval_with`], computing with numbers
+    /// of the given domain. The arguments are converted into the domain.
+    pub fn eval_numbers(
+        &self,
+        args: &[Number],
+        mode: ArithmeticMode,
+        domain: Domain,
+    ) -> Result<Number, EvalError> {
+        eval::eval_numbers(self, args, mode, domain)
+    }
+
+    /// Returns true for immediates, argument and local references.
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, Self::Imm(_) | Self::Arg(_) | Self::Local(_))
+    }
+
+    // Simplifies the AST by applying constant folding,
+    // i.e., evaluating binary expression where both
+    // inputs are constants. Folding is applied
+    // bottom-up, requiring only one pass over the AST.
+    //
+    // Constants are immediates and, outside of the integer domain,
+    // quotients of immediates, e.g. `3 / 2` for 1.5, which is how
+    // results that are not integers are written back.
+    //
+    // In checked mode, a constant integer operation that overflows or
+    // divides by zero is reported as an error. Otherwise, the
+    // operation is left for the `Vm` to evaluate (and report).
+    //
+    // A conditional with a constant condition is replaced by the
+    // branch that is taken, the other branch is dropped unfolded.
+    // Otherwise, a branch that fails to fold is left unfolded, as it
+    // may never be taken: its error is reported at runtime.
+    pub(crate) fn fold(&self, mode: ArithmeticMode, domain: Domain) -> Result<Ast, CompileError> {
+        match self {
+            Self::BinOp(op, lhs, rhs) => {
+                let lhs = lhs.fold(mode, domain)?;
+                let rhs = rhs.fold(mode, domain)?;
+
+                let (Some(a), Some(b)) = (domain.constant(&lhs), domain.constant(&rhs)) else {
+                    return Ok(Self::bin_op(*op, lhs, rhs));
+                };
+                match domain.apply(mode, *op, a, b) {
+                    Ok(n) => Ok(n.to_ast().unwrap_or_else(|| Self::bin_op(*op, lhs, rhs))),
+                    Err(error) if mode == ArithmeticMode::Checked && domain == Domain::Integer => {
+                        Err(CompileError::ConstantArithmetic {
+                            error,
+                            op: *op,
+                            lhs: lhs.as_imm().unwrap(),
+                            rhs: rhs.as_imm().unwrap(),
+                        })
+                    }
+                    Err(_) => Ok(Self::bin_op(*op, lhs, rhs)),
+                }
+            }
+            Self::Let(value, body) => Ok(Self::let_in(
+                value.fold(mode, domain)?,
+                body.fold(mode, domain)?,
+            )),
+            Self::If(cond, then, otherwise) => {
+                let cond = cond.fold(mode, domain)?;
+                match domain.constant(&cond) {
+                    Some(n) if n.is_zero() => otherwise.fold(mode, domain),
+                    Some(_) => then.fold(mode, domain),
+                    None => {
+                        let fold = |branch: &Ast| {
+                            branch.fold(mode, domain).unwrap_or_else(|_| branch.clone())
+                        };
+                        Ok(Self::if_else(cond, fold(then), fold(otherwise)))
+                    }
+                }
+            }
+            Self::Call(function, args) => Ok(Self::call(
+                *function,
+                args.iter()
+                    .map(|arg| arg.fold(mode, domain))
+                    .collect::<Result<_, _>>()?,
+            )),
+            leaf => Ok(leaf.clone()),
+        }
+    }
+}
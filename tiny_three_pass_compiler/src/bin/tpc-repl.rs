@@ -0,0 +1,254 @@
+//! `tpc-repl`, an interactive session for compiling and
+//! evaluating expressions, e.g.
+//!
+//! ```text
+//! > :args x=4 y=2
+//! > (x + y) * 2
+//! 12
+//! > :asm
+//! ```
+use std::io::{self, BufRead, Write};
+
+use tiny_three_pass_compiler::{disassemble, Ast, Compiler, Instruction};
+
+const HELP: &str = "\
+Type an expression over the declared arguments to evaluate it,
+or a program with its own argument list, e.g. `[ a ] a * a`.
+
+commands:
+  :args x=4 y=2  declare arguments with their values, `:args` lists them
+  :ast           print the folded tree of the last expression
+  :asm           print the assembly of the last expression
+  :history       list the entered expressions
+  !n             evaluate the n-th expression of the history again
+  :help          print this message
+  :quit          end the session
+";
+
+#[derive(Debug, Default)]
+struct Session {
+    args: Vec<(String, i64)>,
+    history: Vec<String>,
+    // The argument names, folded tree and assembly of the last expression.
+    last: Option<(Vec<String>, Ast, Vec<Instruction>)>,
+}
+
+impl Session {
+    // Handles a line of input and returns the output, or `None`
+    // if the session ends.
+    fn handle(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        let out = match command {
+            "" => String::new(),
+            ":quit" | ":q" => return None,
+            ":help" => HELP.to_string(),
+            ":args" if rest.trim().is_empty() => self
+                .args
+                .iter()
+                .map(|(name, value)| format!("{name} = {value}\n"))
+                .collect(),
+            ":args" => self.declare(rest),
+            ":ast" => match &self.last {
+                Some((names, ast, _)) => ast.to_source(names) + "\n",
+                None => "error: nothing evaluated yet\n".to_string(),
+            },
+            ":asm" => match &self.last {
+                Some((_, _, asm)) => disassemble(asm),
+                None => "error: nothing evaluated yet\n".to_string(),
+            },
+            ":history" => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(n, line)| format!("{:>3}  {line}\n", n + 1))
+                .collect(),
+            _ if command.starts_with(':') => {
+                format!("error: unknown command `{command}`, see `:help`\n")
+            }
+            _ if line.starts_with('!') => {
+                match line[1..]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| self.history.get(n.checked_sub(1)?))
+                {
+                    Some(line) => {
+                        let line = line.clone();
+                        self.evaluate(&line)
+                    }
+                    None => format!("error: no history entry `{}`\n", &line[1..]),
+                }
+            }
+            _ => self.evaluate(line),
+        };
+
+        Some(out)
+    }
+
+    fn declare(&mut self, declarations: &str) -> String {
+        let mut args = vec![];
+
+        for declaration in declarations.split_whitespace() {
+            let (name, value) = declaration.split_once('=').unwrap_or((declaration, "0"));
+            let Ok(value) = value.parse() else {
+                return format!("error: invalid value `{value}` for `{name}`\n");
+            };
+            if args.iter().any(|(arg, _)| arg == name) {
+                return format!("error: duplicate argument `{name}`\n");
+            }
+            args.push((name.to_string(), value));
+        }
+
+        self.args = args;
+        String::new()
+    }
+
+    // Compiles and runs an expression over the declared arguments,
+    // or a program with an argument list of its own, whose arguments
+    // take the values declared under the same name.
+    fn evaluate(&mut self, line: &str) -> String {
+        self.history.push(line.to_string());
+
+        let program = if line.starts_with('[') {
+            line.to_string()
+        } else {
+            let names: Vec<&str> = self.args.iter().map(|(name, _)| name.as_str()).collect();
+            format!("[ {} ] {line}", names.join(" "))
+        };
+
+        let mut c = Compiler::new();
+        let compiled = c.pass1(&program).and_then(|ast| {
+            let ast = c.pass2(&ast)?;
+            Ok(c.simplify(&ast))
+        });
+        let ast = match compiled {
+            Ok(ast) => ast,
+            Err(e) => return e.render(&program),
+        };
+        let asm = c.pass3_typed(&ast);
+
+        let mut values = vec![];
+        for name in c.arg_names() {
+            match self.args.iter().find(|(arg, _)| arg == name) {
+                Some((_, value)) => values.push(*value),
+                None => return format!("error: no value for `{name}`, see `:args`\n"),
+            }
+        }
+
+        let result = c.vm().run(&asm, &values);
+        self.last = Some((c.arg_names().to_vec(), ast, asm));
+
+        match result {
+            Ok(result) => format!("{result}\n"),
+            Err(e) => format!("error: {e}\n"),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut session = Session::default();
+    let mut stdout = io::stdout();
+    let mut lines = io::stdin().lock().lines();
+
+    loop {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            writeln!(stdout)?;
+            return Ok(());
+        };
+        match session.handle(&line) {
+            Some(out) => write!(stdout, "{out}")?,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(lines: &[&str]) -> (Session, Vec<String>) {
+        let mut session = Session::default();
+        let out = lines
+            .iter()
+            .map(|line| session.handle(line).unwrap())
+            .collect();
+        (session, out)
+    }
+
+    #[test]
+    fn evaluation() {
+        let (session, out) = session(&[
+            ":args x=4 y=2",
+            "(x + y) * 2",
+            ":ast",
+            ":asm",
+            "[ y ] y * (1 + 2)",
+            ":ast",
+            ":args",
+        ]);
+
+        assert_eq!(
+            out,
+            [
+                "",
+                "12\n",
+                "[ x y ] (x + y) * 2\n",
+                "AR 1\nSW\nAR 0\nAD\nSW\nIM 2\nMU\n",
+                "6\n",
+                "[ y ] y * 3\n",
+                "x = 4\ny = 2\n",
+            ]
+        );
+        assert_eq!(session.history, ["(x + y) * 2", "[ y ] y * (1 + 2)"]);
+    }
+
+    #[test]
+    fn history() {
+        let (_, out) = session(&[":args a=3", "a * a", ":args a=5", "!1", ":history", "!3"]);
+
+        assert_eq!(
+            out,
+            [
+                "",
+                "9\n",
+                "",
+                "25\n",
+                "  1  a * a\n  2  a * a\n",
+                "error: no history entry `3`\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let (mut session, out) = session(&[
+            ":ast",
+            ":args x=1 x=2",
+            ":args x=one",
+            ":args x",
+            "x + y",
+            "[ z ] z",
+            "1 / x",
+            ":frobnicate",
+        ]);
+
+        assert_eq!(
+            out,
+            [
+                "error: nothing evaluated yet\n",
+                "error: duplicate argument `x`\n",
+                "error: invalid value `one` for `x`\n",
+                "",
+                "error: undeclared variable `y`\n --> 1:11\n  |\n1 | [ x ] x + y\n  |           ^\n",
+                "error: no value for `z`, see `:args`\n",
+                "error: division by zero at 3\n",
+                "error: unknown command `:frobnicate`, see `:help`\n",
+            ]
+        );
+        assert_eq!(session.handle(":quit"), None);
+    }
+}
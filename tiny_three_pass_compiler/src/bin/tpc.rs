@@ -0,0 +1,387 @@
+//! `tpc`, the command-line driver of the compiler.
+//!
+//! Reads a program from a file or stdin, compiles it and prints the
+//! result of the requested stage, e.g.
+//!
+//! ```text
+//! echo '[ x y ] (x + y) / 2' | tpc --emit asm --run 4,6
+//! ```
+use std::{
+    fmt::{self, Display},
+    io::Read,
+    process::ExitCode,
+};
+
+use tiny_three_pass_compiler::{
+    disassemble, peephole, Ast, CompileError, Compiler, Instruction, VmError,
+};
+
+const USAGE: &str = "\
+usage: tpc [options] [file]
+
+Compiles the program in `file`, or reads it from stdin if `file`
+is missing or `-`.
+
+options:
+  --emit <stage>  print the result of a stage:
+                    ast     the parsed program
+                    folded  the program after the selected passes
+                    asm     the generated assembly (default)
+                    json    the tree after the selected passes as JSON
+                    dot     the trees before and after the selected passes as a Graphviz digraph
+  --run <args>    run the program on the VM with comma-separated
+                  arguments, e.g. `--run 4,6,2`, and print the result
+  -O0             no optimizations
+  -O1             constant folding and simplification (default)
+  -O2             also common-subexpression elimination and peephole
+                  optimization of the assembly
+  -h, --help      print this message
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Ast,
+    Folded,
+    Asm,
+    Json,
+    Dot,
+}
+
+impl Emit {
+    const ALL: [Self; 5] = [Self::Ast, Self::Folded, Self::Asm, Self::Json, Self::Dot];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ast => "ast",
+            Self::Folded => "folded",
+            Self::Asm => "asm",
+            Self::Json => "json",
+            Self::Dot => "dot",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    input: Option<String>,
+    emit: Option<Emit>,
+    run: Option<Vec<i64>>,
+    level: u8,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            input: None,
+            emit: None,
+            run: None,
+            level: 1,
+        }
+    }
+}
+
+impl Options {
+    // Returns `None` if help was requested.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |flag: &str| {
+                inline
+                    .map(str::to_string)
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("`{flag}` requires a value"))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--emit" => {
+                    let stage = value("--emit")?;
+                    match Emit::ALL.into_iter().find(|emit| emit.name() == stage) {
+                        Some(emit) => options.emit = Some(emit),
+                        None => return Err(format!("unknown stage `{stage}`")),
+                    }
+                }
+                "--run" => {
+                    options.run = Some(
+                        value("--run")?
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|arg| !arg.is_empty())
+                            .map(|arg| arg.parse().map_err(|_| format!("invalid argument `{arg}`")))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                "-O0" => options.level = 0,
+                "-O1" => options.level = 1,
+                "-O2" => options.level = 2,
+                "-" => options.input = None,
+                _ if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+                _ if options.input.is_some() => return Err(format!("unexpected argument `{arg}`")),
+                _ => options.input = Some(arg),
+            }
+        }
+
+        Ok(Some(options))
+    }
+}
+
+#[derive(Debug)]
+enum Failure {
+    Compile(CompileError),
+    Run(VmError),
+    Unsupported(Emit),
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(e) => write!(f, "{e}"),
+            Self::Run(e) => write!(f, "{e}"),
+            Self::Unsupported(emit) => write!(
+                f,
+                "`--emit {}` is not supported for programs of several functions",
+                emit.name()
+            ),
+        }
+    }
+}
+
+impl From<CompileError> for Failure {
+    fn from(e: CompileError) -> Self {
+        Self::Compile(e)
+    }
+}
+
+// Compiles the source as requested and returns the output. Sources
+// starting with `def` are compiled as programs of several functions.
+fn compile(options: &Options, source: &str) -> Result<String, Failure> {
+    let mut c = Compiler::new();
+    let emit = match (options.emit, &options.run) {
+        (Some(emit), _) => Some(emit),
+        (None, Some(_)) => None,
+        (None, None) => Some(Emit::Asm),
+    };
+
+    let mut out = String::new();
+
+    let asm = if source.trim_start().starts_with("def") {
+        let parsed = c.pass1_program(source)?;
+        let mut program = parsed.clone();
+        for function in &mut program.functions {
+            function.body = optimize(&mut c, &function.body, options.level)?;
+        }
+        let asm = c.link(&program);
+
+        match emit {
+            Some(Emit::Ast) => out = parsed.to_source() + "\n",
+            Some(Emit::Folded) => out = program.to_source() + "\n",
+            Some(emit @ (Emit::Json | Emit::Dot)) => return Err(Failure::Unsupported(emit)),
+            _ => {}
+        }
+        asm
+    } else {
+        let parsed = c.pass1(source)?;
+        let ast = optimize(&mut c, &parsed, options.level)?;
+
+        match emit {
+            Some(Emit::Ast) => out = c.to_source(&parsed) + "\n",
+            Some(Emit::Folded) => out = c.to_source(&ast) + "\n",
+            Some(Emit::Json) => out = ast.to_json() + "\n",
+            Some(Emit::Dot) => out = c.passes_to_dot(&parsed, &ast),
+            _ => {}
+        }
+        c.pass3_typed(&ast)
+    };
+
+    let asm: Vec<Instruction> = if options.level >= 2 {
+        peephole(&asm)
+    } else {
+        asm
+    };
+
+    if emit == Some(Emit::Asm) {
+        out = disassemble(&asm);
+    }
+
+    if let Some(args) = &options.run {
+        let result = c.vm().run(&asm, args).map_err(Failure::Run)?;
+        out.push_str(&format!("{result}\n"));
+    }
+
+    Ok(out)
+}
+
+fn optimize(c: &mut Compiler, ast: &Ast, level: u8) -> Result<Ast, CompileError> {
+    if level == 0 {
+        return Ok(ast.clone());
+    }
+    let ast = c.pass2(ast)?;
+    let ast = c.simplify(&ast);
+    Ok(if level >= 2 { c.cse(&ast) } else { ast })
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprint!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let source = match &options.input {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|e| format!("cannot read `{path}`: {e}"))
+        }
+        None => {
+            let mut source = String::new();
+            std::io::stdin()
+                .read_to_string(&mut source)
+                .map(|_| source)
+                .map_err(|e| format!("cannot read stdin: {e}"))
+        }
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(message) => {
+            eprintln!("error: {message}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match compile(&options, &source) {
+        Ok(out) => {
+            print!("{out}");
+            ExitCode::SUCCESS
+        }
+        Err(Failure::Compile(e)) => {
+            eprint!("{}", e.render(&source));
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        parse(args).unwrap().unwrap()
+    }
+
+    #[test]
+    fn options_parsing() {
+        assert_eq!(options(&[]), Options::default());
+        assert_eq!(
+            options(&["--emit", "json", "-O2", "prog.tpc", "--run=4, 6,-2"]),
+            Options {
+                input: Some("prog.tpc".to_string()),
+                emit: Some(Emit::Json),
+                run: Some(vec![4, 6, -2]),
+                level: 2,
+            }
+        );
+        assert_eq!(options(&["--emit=dot", "-"]).emit, Some(Emit::Dot));
+        assert_eq!(options(&["--run", ""]).run, Some(vec![]));
+        assert_eq!(parse(&["-O1", "--help"]), Ok(None));
+
+        assert_eq!(
+            parse(&["--emit", "ir"]),
+            Err("unknown stage `ir`".to_string())
+        );
+        assert_eq!(
+            parse(&["--emit"]),
+            Err("`--emit` requires a value".to_string())
+        );
+        assert_eq!(
+            parse(&["--run", "1,x"]),
+            Err("invalid argument `x`".to_string())
+        );
+        assert_eq!(parse(&["-O3"]), Err("unknown option `-O3`".to_string()));
+        assert_eq!(
+            parse(&["a.tpc", "b.tpc"]),
+            Err("unexpected argument `b.tpc`".to_string())
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let source = "[ x y ] x * (2 + 1) - y * 1";
+        let emit = |emit, level| {
+            compile(
+                &Options {
+                    emit: Some(emit),
+                    level,
+                    ..Options::default()
+                },
+                source,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(emit(Emit::Ast, 1), "[ x y ] x * (2 + 1) - y * 1\n");
+        assert_eq!(emit(Emit::Folded, 0), "[ x y ] x * (2 + 1) - y * 1\n");
+        assert_eq!(emit(Emit::Folded, 1), "[ x y ] x * 3 - y\n");
+        assert_eq!(
+            emit(Emit::Json, 1),
+            Ast::sub(Ast::mul(Ast::arg(0), Ast::imm(3)), Ast::arg(1)).to_json() + "\n"
+        );
+        assert!(emit(Emit::Dot, 1).starts_with("digraph passes {"));
+        assert_eq!(emit(Emit::Asm, 1), "IM 3\nSW\nAR 0\nMU\nSW\nAR 1\nSW\nSU\n");
+        assert!(emit(Emit::Asm, 2).lines().count() < emit(Emit::Asm, 0).lines().count());
+    }
+
+    #[test]
+    fn run() {
+        let run = |source, args: &[i64], emit| {
+            compile(
+                &Options {
+                    emit,
+                    run: Some(args.to_vec()),
+                    ..Options::default()
+                },
+                source,
+            )
+        };
+
+        assert_eq!(run("[ x y ] (x + y) / 2", &[4, 6], None).unwrap(), "5\n");
+        assert_eq!(
+            run("[ x ] x + 1", &[2], Some(Emit::Asm)).unwrap(),
+            "IM 1\nSW\nAR 0\nAD\n3\n"
+        );
+        assert_eq!(
+            run("def sq [ a ] a * a; def main [ x ] sq(x) + 1", &[3], None).unwrap(),
+            "10\n"
+        );
+        assert!(matches!(
+            run("[ x ] 1 / x", &[0], None),
+            Err(Failure::Run(VmError::DivisionByZero { .. }))
+        ));
+        assert!(matches!(
+            run("[ x ] x + y", &[0], None),
+            Err(Failure::Compile(CompileError::UndeclaredVariable { .. }))
+        ));
+        assert!(matches!(
+            run("def main [ x ] x", &[0], Some(Emit::Json)),
+            Err(Failure::Unsupported(Emit::Json))
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    codegen::slots,
+    error::EvalError,
+};
+
+// Compiles an AST into a tree of closures, one per operation, so
+// that evaluating it for many arguments does not dispatch on the
+// shape of the tree or on the operator again and again.
+//
+// Each operation is specialized on its operator and the arithmetic
+// mode when the closure is built. Leaves are not closures of their
+// own: operands are an `Operand`, whose leaf variants are read
+// inline by the operation using them.
+//
+// Locals bound by `Let` are stored in an array of slots, addressed
+// like the slots of the target machine. It lives on the stack unless
+// the tree needs more than `INLINE_SLOTS` of them.
+pub(crate) fn compile(
+    ast: &Ast,
+    mode: ArithmeticMode,
+) -> impl Fn(&[i64]) -> Result<i64, EvalError> + Send + Sync {
+    let slots = slots(ast);
+    let root = operand(ast, 0, mode);
+
+    move |args| {
+        if slots <= INLINE_SLOTS {
+            root.eval(args, &mut [0; INLINE_SLOTS])
+        } else {
+            root.eval(args, &mut vec![0; slots])
+        }
+    }
+}
+
+const INLINE_SLOTS: usize = 8;
+
+type Node = Box<dyn Fn(&[i64], &mut [i64]) -> Result<i64, EvalError> + Send + Sync>;
+
+enum Operand {
+    Imm(i64),
+    Arg(usize),
+    Local(usize),
+    Node(Node),
+}
+
+impl Operand {
+    #[inline(always)]
+    fn eval(&self, args: &[i64], locals: &mut [i64]) -> Result<i64, EvalError> {
+        match self {
+            Self::Imm(n) => Ok(*n),
+            Self::Arg(n) => args.get(*n).copied().ok_or(EvalError::ArgumentOutOfRange {
+                index: *n,
+                len: args.len(),
+            }),
+            Self::Local(slot) => locals
+                .get(*slot)
+                .copied()
+                .ok_or(EvalError::UnboundLocal { slot: *slot }),
+            Self::Node(node) => node(args, locals),
+        }
+    }
+}
+
+fn operand(ast: &Ast, depth: usize, mode: ArithmeticMode) -> Operand {
+    match ast {
+        Ast::Imm(n) => Operand::Imm(*n),
+        Ast::Arg(n) => Operand::Arg(*n),
+        Ast::Local(slot) if *slot < depth => Operand::Local(*slot),
+        // The slot may exist for a binding elsewhere, whose value
+        // must not be read here.
+        &Ast::Local(slot) => {
+            Operand::Node(Box::new(move |_, _| Err(EvalError::UnboundLocal { slot })))
+        }
+        Ast::Let(value, body) => {
+            let value = operand(value, depth, mode);
+            let body = operand(body, depth + 1, mode);
+            Operand::Node(Box::new(move |args, locals| {
+                locals[depth] = value.eval(args, locals)?;
+                body.eval(args, locals)
+            }))
+        }
+        Ast::If(cond, then, otherwise) => {
+            let cond = operand(cond, depth, mode);
+            let then = operand(then, depth, mode);
+            let otherwise = operand(otherwise, depth, mode);
+            Operand::Node(Box::new(move |args, locals| {
+                if cond.eval(args, locals)? != 0 {
+                    then.eval(args, locals)
+                } else {
+                    otherwise.eval(args, locals)
+                }
+            }))
+        }
+        &Ast::Call(function, _) => Operand::Node(Box::new(move |_, _| {
+            Err(EvalError::UnresolvedCall { function })
+        })),
+        Ast::BinOp(op, lhs, rhs) => bin_op(
+            *op,
+            mode,
+            operand(lhs, depth, mode),
+            operand(rhs, depth, mode),
+        ),
+    }
+}
+
+// Builds the closure of an operation, inlining the operator for
+// comparisons and for `+`, `-` and `*`. Others take the general
+// path of `ArithmeticMode::apply`.
+fn bin_op(op: BinOp, mode: ArithmeticMode, lhs: Operand, rhs: Operand) -> Operand {
+    use ArithmeticMode::*;
+
+    match (op, mode) {
+        (BinOp::Add, Wrapping) => node(lhs, rhs, |a, b| Ok(a.wrapping_add(b))),
+        (BinOp::Sub, Wrapping) => node(lhs, rhs, |a, b| Ok(a.wrapping_sub(b))),
+        (BinOp::Mul, Wrapping) => node(lhs, rhs, |a, b| Ok(a.wrapping_mul(b))),
+        (BinOp::Add, Saturating) => node(lhs, rhs, |a, b| Ok(a.saturating_add(b))),
+        (BinOp::Sub, Saturating) => node(lhs, rhs, |a, b| Ok(a.saturating_sub(b))),
+        (BinOp::Mul, Saturating) => node(lhs, rhs, |a, b| Ok(a.saturating_mul(b))),
+        (BinOp::Add, Checked) => node(lhs, rhs, |a, b| a.checked_add(b).ok_or(EvalError::Overflow)),
+        (BinOp::Sub, Checked) => node(lhs, rhs, |a, b| a.checked_sub(b).ok_or(EvalError::Overflow)),
+        (BinOp::Mul, Checked) => node(lhs, rhs, |a, b| a.checked_mul(b).ok_or(EvalError::Overflow)),
+        (BinOp::Eq, _) => node(lhs, rhs, |a, b| Ok((a == b) as i64)),
+        (BinOp::Ne, _) => node(lhs, rhs, |a, b| Ok((a != b) as i64)),
+        (BinOp::Lt, _) => node(lhs, rhs, |a, b| Ok((a < b) as i64)),
+        (BinOp::Le, _) => node(lhs, rhs, |a, b| Ok((a <= b) as i64)),
+        (BinOp::Gt, _) => node(lhs, rhs, |a, b| Ok((a > b) as i64)),
+        (BinOp::Ge, _) => node(lhs, rhs, |a, b| Ok((a >= b) as i64)),
+        _ => node(lhs, rhs, move |a, b| Ok(mode.apply(op, a, b)?)),
+    }
+}
+
+fn node<F>(lhs: Operand, rhs: Operand, f: F) -> Operand
+where
+    F: Fn(i64, i64) -> Result<i64, EvalError> + Send + Sync + 'static,
+{
+    Operand::Node(Box::new(move |args, locals| {
+        f(lhs.eval(args, locals)?, rhs.eval(args, locals)?)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::cross_check;
+
+    #[test]
+    fn evaluation() {
+        // let a = x * 2 in if a > y then a else y % 3
+        let ast = Ast::let_in(
+            Ast::mul(Ast::arg(0), Ast::imm(2)),
+            Ast::if_else(
+                Ast::bin_op(BinOp::Gt, Ast::local(0), Ast::arg(1)),
+                Ast::local(0),
+                Ast::bin_op(BinOp::Rem, Ast::arg(1), Ast::imm(3)),
+            ),
+        );
+        let f = compile(&ast, ArithmeticMode::Checked);
+
+        assert_eq!(f(&[7, 3]), Ok(14));
+        assert_eq!(f(&[1, 5]), Ok(2));
+        assert_eq!(f(&[i64::MAX, 5]), Err(EvalError::Overflow));
+        assert_eq!(
+            f(&[1]),
+            Err(EvalError::ArgumentOutOfRange { index: 1, len: 1 })
+        );
+
+        let f = compile(
+            &Ast::div(Ast::imm(1), Ast::arg(0)),
+            ArithmeticMode::Wrapping,
+        );
+        assert_eq!(f(&[0]), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            compile(&Ast::call(2, vec![]), ArithmeticMode::Wrapping)(&[]),
+            Err(EvalError::UnresolvedCall { function: 2 })
+        );
+        assert_eq!(
+            compile(&Ast::local(3), ArithmeticMode::Wrapping)(&[]),
+            Err(EvalError::UnboundLocal { slot: 3 })
+        );
+        // slot 0 is bound in the left operand only
+        let ast = Ast::add(Ast::let_in(Ast::imm(1), Ast::local(0)), Ast::local(0));
+        assert_eq!(ast.eval(&[]), Err(EvalError::UnboundLocal { slot: 0 }));
+        assert_eq!(
+            compile(&ast, ArithmeticMode::Wrapping)(&[]),
+            Err(EvalError::UnboundLocal { slot: 0 })
+        );
+    }
+
+    #[test]
+    fn random_programs() {
+        cross_check(23, |ast, mode| {
+            let f = compile(ast, mode);
+            move |args: &[i64]| f(args).ok()
+        });
+    }
+}
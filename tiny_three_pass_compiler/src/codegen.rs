@@ -0,0 +1,335 @@
+use crate::{
+    ast::Ast,
+    instruction::Instruction,
+    program::{self, Link, Program},
+};
+
+// Generates instructions for the target machine, see
+// `Instruction` for their semantics.
+//
+// The evaluation order of binary operations is chosen using
+// Sethi–Ullman labeling: every subtree is labeled with the number
+// of registers it needs to be evaluated without spilling to the
+// stack. With only two registers, every operation whose operands
+// are both operations has to spill one of them. Evaluating the
+// operand with the higher need first keeps the stack shallow.
+//
+// For an operation `lhs op rhs`, the machine expects lhs in R0
+// and rhs in R1. Leaves are loaded straight into R0, so they are
+// evaluated last. For operations with a mirrored counterpart
+// (`a + b == b + a`, `a < b == b > a`) the operands may end up in
+// either register, which saves a swap.
+//
+// Values bound by `Let` are stored in local slots. All slots are
+// reserved at the bottom of the stack before evaluation starts;
+// a binding nested in `n` other bindings uses the n-th slot.
+//
+// A conditional evaluates its condition into R0 and jumps over
+// the first branch if it is zero:
+//
+//     cond; JZ else; then; JMP end; else: otherwise; end:
+//
+// Labels are resolved to absolute offsets by patching each jump
+// once the code it skips has been emitted.
+//
+// A call pushes its arguments in order and leaves the result in
+// R0, clobbering R1. Its target is the index of the function until
+// the program is linked.
+pub(crate) fn generate(ast: &Ast) -> Vec<Instruction> {
+    let mut asm = vec![Instruction::Pu; slots(ast)];
+    emit(ast, 0, &mut asm);
+    asm
+}
+
+// Returns the number of local slots needed by the AST.
+pub(crate) fn slots(ast: &Ast) -> usize {
+    match ast {
+        Ast::Imm(_) | Ast::Arg(_) | Ast::Local(_) => 0,
+        Ast::BinOp(_, lhs, rhs) => slots(lhs).max(slots(rhs)),
+        Ast::Let(value, body) => slots(value).max(slots(body) + 1),
+        Ast::If(cond, then, otherwise) => slots(cond).max(slots(then)).max(slots(otherwise)),
+        Ast::Call(_, args) => args.iter().map(slots).max().unwrap_or(0),
+    }
+}
+
+// Returns the number of arguments the AST refers to,
+// i.e., one more than the highest argument index.
+pub(crate) fn arity(ast: &Ast) -> usize {
+    match ast {
+        Ast::Arg(n) => n + 1,
+        Ast::Imm(_) | Ast::Local(_) => 0,
+        Ast::BinOp(_, lhs, rhs) | Ast::Let(lhs, rhs) => arity(lhs).max(arity(rhs)),
+        Ast::If(cond, then, otherwise) => arity(cond).max(arity(then)).max(arity(otherwise)),
+        Ast::Call(_, args) => args.iter().map(arity).max().unwrap_or(0),
+    }
+}
+
+// Returns the Sethi–Ullman number of the AST.
+pub(crate) fn need(ast: &Ast) -> usize {
+    match ast {
+        Ast::Imm(_) | Ast::Arg(_) | Ast::Local(_) => 1,
+        Ast::Let(value, body) => need(value).max(need(body)),
+        Ast::If(cond, then, otherwise) => need(cond).max(need(then)).max(need(otherwise)),
+        Ast::Call(_, args) => args.iter().map(need).max().unwrap_or(0).max(2),
+        Ast::BinOp(_, lhs, rhs) => {
+            let (l, r) = (need(lhs), need(rhs));
+            if l == r {
+                l + 1
+            } else {
+                l.max(r)
+            }
+        }
+    }
+}
+
+fn emit(ast: &Ast, depth: usize, asm: &mut Vec<Instruction>) {
+    match ast {
+        Ast::Imm(n) => asm.push(Instruction::Im(*n)),
+        Ast::Arg(n) => asm.push(Instruction::Ar(*n)),
+        Ast::Local(slot) => asm.push(Instruction::Ld(*slot)),
+        Ast::Let(value, body) => {
+            emit(value, depth, asm);
+            asm.push(Instruction::St(depth));
+            emit(body, depth + 1, asm);
+        }
+        Ast::If(cond, then, otherwise) => {
+            emit(cond, depth, asm);
+            let jz = asm.len();
+            asm.push(Instruction::Jz(0));
+            emit(then, depth, asm);
+            let jmp = asm.len();
+            asm.push(Instruction::Jmp(0));
+            asm[jz] = Instruction::Jz(asm.len());
+            emit(otherwise, depth, asm);
+            asm[jmp] = Instruction::Jmp(asm.len());
+        }
+        Ast::Call(function, args) => {
+            for arg in args {
+                emit(arg, depth, asm);
+                asm.push(Instruction::Pu);
+            }
+            asm.push(Instruction::Call(*function, args.len()));
+        }
+        Ast::BinOp(op, lhs, rhs) => {
+            let mirrored = op.mirrored();
+
+            let op = match (lhs.is_leaf(), rhs.is_leaf(), mirrored) {
+                // R0 = lhs, R1 = rhs
+                (true, _, _) => {
+                    emit(rhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    emit(lhs, depth, asm);
+                    *op
+                }
+                // R0 = rhs, R1 = lhs
+                (false, true, Some(mirrored)) => {
+                    emit(lhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    emit(rhs, depth, asm);
+                    mirrored
+                }
+                // R0 = lhs, R1 = rhs
+                (false, true, None) => {
+                    emit(lhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    emit(rhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    *op
+                }
+                // R0 = rhs, R1 = lhs
+                (false, false, Some(mirrored)) if need(rhs) > need(lhs) => {
+                    emit(rhs, depth, asm);
+                    asm.push(Instruction::Pu);
+                    emit(lhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    asm.push(Instruction::Po);
+                    mirrored
+                }
+                // R0 = lhs, R1 = rhs
+                (false, false, _) => {
+                    emit(lhs, depth, asm);
+                    asm.push(Instruction::Pu);
+                    emit(rhs, depth, asm);
+                    asm.push(Instruction::Sw);
+                    asm.push(Instruction::Po);
+                    *op
+                }
+            };
+
+            asm.push(Instruction::from_bin_op(op));
+        }
+    }
+}
+
+impl Link for Instruction {
+    fn relocate(self, start: usize) -> Self {
+        match self {
+            Self::Jz(target) => Self::Jz(start + target),
+            Self::Jmp(target) => Self::Jmp(start + target),
+            ins => ins,
+        }
+    }
+
+    fn resolve(&mut self, starts: &[usize]) {
+        if let Self::Call(function, _) = self {
+            *function = starts[*function];
+        }
+    }
+
+    fn ret() -> Self {
+        Self::Ret
+    }
+}
+
+// Links the functions of a program into one, see `program::link`.
+pub(crate) fn link(program: &Program) -> Vec<Instruction> {
+    program::link(program, generate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::BinOp, vm::Vm};
+    use Instruction::*;
+
+    #[test]
+    fn labels() {
+        assert_eq!(need(&Ast::arg(0)), 1);
+        assert_eq!(need(&Ast::add(Ast::arg(0), Ast::imm(1))), 2);
+        assert_eq!(
+            need(&Ast::add(
+                Ast::mul(Ast::arg(0), Ast::arg(1)),
+                Ast::mul(Ast::arg(2), Ast::arg(3))
+            )),
+            3
+        );
+    }
+
+    #[test]
+    fn preserves_operand_order() {
+        // x * 2 - 3
+        let ast = Ast::sub(Ast::mul(Ast::arg(0), Ast::imm(2)), Ast::imm(3));
+        let asm = generate(&ast);
+
+        assert_eq!(asm, vec![Im(2), Sw, Ar(0), Mu, Sw, Im(3), Sw, Su]);
+        assert_eq!(Vm::new().run(&asm, &[5]), Ok(7));
+
+        // x * 12 / (x - 1)
+        let ast = Ast::div(
+            Ast::mul(Ast::arg(0), Ast::imm(12)),
+            Ast::sub(Ast::arg(0), Ast::imm(1)),
+        );
+        assert_eq!(Vm::new().run(&generate(&ast), &[5]), Ok(15));
+
+        // x * 2 < 3 evaluates 3 > x * 2
+        let ast = Ast::bin_op(BinOp::Lt, Ast::mul(Ast::arg(0), Ast::imm(2)), Ast::imm(3));
+        let asm = generate(&ast);
+
+        assert_eq!(asm, vec![Im(2), Sw, Ar(0), Mu, Sw, Im(3), Gt]);
+        assert_eq!(Vm::new().run(&asm, &[1]), Ok(1));
+        assert_eq!(Vm::new().run(&asm, &[2]), Ok(0));
+    }
+
+    #[test]
+    fn resolves_jump_targets() {
+        // (if x < 0 then 0 - x else x) + 1
+        let ast = Ast::add(
+            Ast::if_else(
+                Ast::bin_op(BinOp::Lt, Ast::arg(0), Ast::imm(0)),
+                Ast::sub(Ast::imm(0), Ast::arg(0)),
+                Ast::arg(0),
+            ),
+            Ast::imm(1),
+        );
+        let asm = generate(&ast);
+
+        assert_eq!(
+            asm,
+            vec![
+                Im(0),
+                Sw,
+                Ar(0),
+                Lt,
+                Jz(10),
+                Ar(0),
+                Sw,
+                Im(0),
+                Su,
+                Jmp(11),
+                Ar(0),
+                Sw,
+                Im(1),
+                Ad
+            ]
+        );
+        assert_eq!(Vm::new().run(&asm, &[-5]), Ok(6));
+        assert_eq!(Vm::new().run(&asm, &[5]), Ok(6));
+    }
+
+    #[test]
+    fn links_functions() {
+        use crate::program::Function;
+
+        // def sq [a] a * a; def main [x] sq(x + 1) - 1
+        let program = Program {
+            functions: vec![
+                Function {
+                    name: "sq".to_string(),
+                    args: vec!["a".to_string()],
+                    body: Ast::mul(Ast::arg(0), Ast::arg(0)),
+                },
+                Function {
+                    name: "main".to_string(),
+                    args: vec!["x".to_string()],
+                    body: Ast::sub(
+                        Ast::call(0, vec![Ast::add(Ast::arg(0), Ast::imm(1))]),
+                        Ast::imm(1),
+                    ),
+                },
+            ],
+        };
+        let asm = link(&program);
+
+        assert_eq!(
+            asm,
+            vec![
+                Im(1),
+                Sw,
+                Ar(0),
+                Ad,
+                Pu,
+                Call(11, 1),
+                Sw,
+                Im(1),
+                Sw,
+                Su,
+                Ret,
+                Ar(0),
+                Sw,
+                Ar(0),
+                Mu,
+                Ret
+            ]
+        );
+        assert_eq!(Vm::new().run(&asm, &[4]), Ok(24));
+    }
+
+    #[test]
+    fn evaluates_needier_operand_first() {
+        // a * b + (c * d + e * f)
+        let ast = Ast::add(
+            Ast::mul(Ast::arg(0), Ast::arg(1)),
+            Ast::add(
+                Ast::mul(Ast::arg(2), Ast::arg(3)),
+                Ast::mul(Ast::arg(4), Ast::arg(5)),
+            ),
+        );
+        let asm = generate(&ast);
+
+        assert_eq!(asm.iter().filter(|ins| **ins == Pu).count(), 2);
+        assert_eq!(
+            Vm::new().with_stack_limit(1).run(&asm, &[1, 2, 3, 4, 5, 6]),
+            Ok(44)
+        );
+    }
+}
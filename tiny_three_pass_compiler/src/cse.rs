@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use crate::ast::{Ast, BinOp};
+
+// A node of the hash-consed DAG, children are referenced by id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Imm(i64),
+    Arg(usize),
+    Local(usize),
+    BinOp(BinOp, usize, usize),
+    Let(usize, usize),
+    If(usize, usize, usize),
+    Call(usize, Vec<usize>),
+}
+
+#[derive(Default)]
+struct Dag {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, usize>,
+}
+
+impl Dag {
+    // Adds the AST to the DAG and returns the id of its root.
+    // Structurally equal subtrees are mapped to the same id and
+    // children always have a smaller id than their parents.
+    fn intern(&mut self, ast: &Ast) -> usize {
+        let node = match ast {
+            Ast::Imm(n) => Node::Imm(*n),
+            Ast::Arg(n) => Node::Arg(*n),
+            Ast::Local(slot) => Node::Local(*slot),
+            Ast::BinOp(op, lhs, rhs) => Node::BinOp(*op, self.intern(lhs), self.intern(rhs)),
+            Ast::Let(value, body) => Node::Let(self.intern(value), self.intern(body)),
+            Ast::If(cond, then, otherwise) => {
+                Node::If(self.intern(cond), self.intern(then), self.intern(otherwise))
+            }
+            Ast::Call(function, args) => {
+                Node::Call(*function, args.iter().map(|arg| self.intern(arg)).collect())
+            }
+        };
+
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        match &self.nodes[id] {
+            Node::Imm(_) | Node::Arg(_) | Node::Local(_) => vec![],
+            Node::BinOp(_, lhs, rhs) => vec![*lhs, *rhs],
+            Node::Let(value, body) => vec![*value, *body],
+            Node::If(cond, then, otherwise) => vec![*cond, *then, *otherwise],
+            Node::Call(_, args) => args.clone(),
+        }
+    }
+
+    // Returns the children that are evaluated whenever the node is,
+    // i.e., all but the branches of a conditional.
+    fn strict_children(&self, id: usize) -> Vec<usize> {
+        match self.nodes[id] {
+            Node::If(cond, _, _) => vec![cond],
+            _ => self.children(id),
+        }
+    }
+}
+
+// Eliminates common subexpressions by hash-consing the AST into a
+// DAG. Every operation that is referenced more than once in the DAG
+// is bound to a local slot by an outer `Let` and replaced by a
+// reference to that slot, so that it is evaluated only once, e.g.
+//
+// (x + y) * (x + y)  =>  let t = x + y in t * t
+//
+// Subexpressions that contain bindings or refer to locals stay in
+// place, as their slots depend on their position in the tree.
+//
+// Only subexpressions that are evaluated unconditionally are bound,
+// so that nothing is hoisted out of the branch of a conditional,
+// which might trap or do work that the original program skips.
+pub(crate) fn eliminate(ast: &Ast) -> Ast {
+    let mut dag = Dag::default();
+    let root = dag.intern(ast);
+
+    let mut uses = vec![0; dag.nodes.len()];
+    let mut movable = vec![true; dag.nodes.len()];
+
+    for id in 0..dag.nodes.len() {
+        for child in dag.children(id) {
+            uses[child] += 1;
+            movable[id] &= movable[child];
+        }
+        if let Node::Local(_) | Node::Let(..) = dag.nodes[id] {
+            movable[id] = false;
+        }
+    }
+
+    // Parents have larger ids than their children, so a reverse
+    // scan visits every node after all of its parents.
+    let mut strict = vec![false; dag.nodes.len()];
+    strict[root] = true;
+
+    for id in (0..dag.nodes.len()).rev() {
+        if strict[id] {
+            for child in dag.strict_children(id) {
+                strict[child] = true;
+            }
+        }
+    }
+
+    let mut slots = vec![None; dag.nodes.len()];
+    let mut shared = vec![];
+
+    for id in 0..dag.nodes.len() {
+        if let Node::BinOp(..) | Node::If(..) | Node::Call(..) = dag.nodes[id] {
+            if movable[id] && strict[id] && uses[id] > 1 {
+                slots[id] = Some(shared.len());
+                shared.push(id);
+            }
+        }
+    }
+
+    let rebuild = |id| {
+        Rebuild {
+            dag: &dag,
+            slots: &slots,
+            offset: shared.len(),
+        }
+        .ast(id, true)
+    };
+
+    shared
+        .iter()
+        .rev()
+        .fold(rebuild(root), |body, &id| Ast::let_in(rebuild(id), body))
+}
+
+struct Rebuild<'a> {
+    dag: &'a Dag,
+    slots: &'a [Option<usize>],
+    // Number of bindings introduced around the original AST.
+    offset: usize,
+}
+
+impl Rebuild<'_> {
+    fn ast(&self, id: usize, definition: bool) -> Ast {
+        if let (Some(slot), false) = (self.slots[id], definition) {
+            return Ast::local(slot);
+        }
+
+        match &self.dag.nodes[id] {
+            Node::Imm(n) => Ast::imm(*n),
+            Node::Arg(n) => Ast::arg(*n),
+            Node::Local(slot) => Ast::local(slot + self.offset),
+            Node::BinOp(op, lhs, rhs) => {
+                Ast::bin_op(*op, self.ast(*lhs, false), self.ast(*rhs, false))
+            }
+            Node::Let(value, body) => Ast::let_in(self.ast(*value, false), self.ast(*body, false)),
+            Node::If(cond, then, otherwise) => Ast::if_else(
+                self.ast(*cond, false),
+                self.ast(*then, false),
+                self.ast(*otherwise, false),
+            ),
+            Node::Call(function, args) => Ast::call(
+                *function,
+                args.iter().map(|&arg| self.ast(arg, false)).collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn x_plus_y() -> Ast {
+        Ast::add(Ast::arg(0), Ast::arg(1))
+    }
+
+    #[test]
+    fn binds_repeated_subexpressions() {
+        // (x + y) * (x + y) + (x + y) / z
+        let ast = Ast::add(
+            Ast::mul(x_plus_y(), x_plus_y()),
+            Ast::div(x_plus_y(), Ast::arg(2)),
+        );
+
+        assert_eq!(
+            eliminate(&ast),
+            Ast::let_in(
+                x_plus_y(),
+                Ast::add(
+                    Ast::mul(Ast::local(0), Ast::local(0)),
+                    Ast::div(Ast::local(0), Ast::arg(2))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn binds_nested_subexpressions_once() {
+        // (x + y) * 2 - (x + y) * 2 / (x + y)
+        let twice = Ast::mul(x_plus_y(), Ast::imm(2));
+        let ast = Ast::sub(twice.clone(), Ast::div(twice, x_plus_y()));
+
+        assert_eq!(
+            eliminate(&ast),
+            Ast::let_in(
+                x_plus_y(),
+                Ast::let_in(
+                    Ast::mul(Ast::local(0), Ast::imm(2)),
+                    Ast::sub(Ast::local(1), Ast::div(Ast::local(1), Ast::local(0)))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_branches_lazy() {
+        // if z then (x + y) * (x + y) else 0
+        let ast = Ast::if_else(Ast::arg(2), Ast::mul(x_plus_y(), x_plus_y()), Ast::imm(0));
+        assert_eq!(eliminate(&ast), ast);
+
+        // if x + y then (x + y) / z else 0
+        let ast = Ast::if_else(x_plus_y(), Ast::div(x_plus_y(), Ast::arg(2)), Ast::imm(0));
+        assert_eq!(
+            eliminate(&ast),
+            Ast::let_in(
+                x_plus_y(),
+                Ast::if_else(
+                    Ast::local(0),
+                    Ast::div(Ast::local(0), Ast::arg(2)),
+                    Ast::imm(0)
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_existing_bindings() {
+        // let a = x + y in a * (x + y)
+        let ast = Ast::let_in(x_plus_y(), Ast::mul(Ast::local(0), x_plus_y()));
+
+        assert_eq!(
+            eliminate(&ast),
+            Ast::let_in(
+                x_plus_y(),
+                Ast::let_in(Ast::local(0), Ast::mul(Ast::local(1), Ast::local(0)))
+            )
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Write;
+
+use crate::{arith::ArithmeticMode, ast::Ast, number::Domain};
+
+// Renders ASTs as Graphviz digraphs, one node per AST node with
+// an edge to each operand, e.g. for `x + 2`:
+//
+//     digraph ast {
+//       node [shape=box];
+//       n0 [label="+"];
+//       n1 [label="x"];
+//       n0 -> n1;
+//       n2 [label="2"];
+//       n0 -> n2;
+//     }
+//
+// Operands are emitted left to right, which Graphviz keeps as the
+// order of siblings. Edges into the parts of a binding or
+// conditional are labeled, since their order is less obvious.
+pub(crate) fn digraph(ast: &Ast, args: &[String]) -> String {
+    let mut out = String::from("digraph ast {\n  node [shape=box];\n");
+    Graph::new("n", "  ", args, &mut out).node(ast, false, None);
+    out.push_str("}\n");
+    out
+}
+
+// Renders the trees before and after constant folding side by side,
+// as the clusters `pass1` and `pass2`. Subtrees of the first tree
+// that are folded into a constant are filled gray.
+pub(crate) fn passes(
+    before: &Ast,
+    after: &Ast,
+    args: &[String],
+    mode: ArithmeticMode,
+    domain: Domain,
+) -> String {
+    let mut out = String::from("digraph passes {\n  node [shape=box];\n");
+
+    for (name, prefix, ast, mode) in [
+        ("pass1", "a", before, Some((mode, domain))),
+        ("pass2", "b", after, None),
+    ] {
+        writeln!(out, "  subgraph cluster_{name} {{\n    label=\"{name}\";").unwrap();
+        let mut graph = Graph::new(prefix, "    ", args, &mut out);
+        graph.mode = mode;
+        graph.node(ast, false, None);
+        out.push_str("  }\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+struct Graph<'a> {
+    prefix: &'static str,
+    indent: &'static str,
+    args: &'a [String],
+    out: &'a mut String,
+    next: usize,
+    // Marks folded subtrees if set.
+    mode: Option<(ArithmeticMode, Domain)>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(
+        prefix: &'static str,
+        indent: &'static str,
+        args: &'a [String],
+        out: &'a mut String,
+    ) -> Self {
+        Self {
+            prefix,
+            indent,
+            args,
+            out,
+            next: 0,
+            mode: None,
+        }
+    }
+
+    // Emits the node, the edge from its parent and its operands.
+    // `folded` is set inside a subtree that folds to a constant.
+    fn node(&mut self, ast: &Ast, folded: bool, edge: Option<(usize, &str)>) {
+        let id = self.next;
+        self.next += 1;
+
+        let folded = folded
+            || self.mode.is_some_and(|(mode, domain)| {
+                domain.constant(ast).is_none()
+                    && ast
+                        .fold(mode, domain)
+                        .is_ok_and(|ast| domain.constant(&ast).is_some())
+            });
+
+        let indent = self.indent;
+        write!(
+            self.out,
+            "{indent}{}{id} [label=\"{}\"",
+            self.prefix,
+            self.label(ast)
+        )
+        .unwrap();
+        if folded {
+            self.out.push_str(", style=filled, fillcolor=lightgray");
+        }
+        self.out.push_str("];\n");
+
+        if let Some((parent, label)) = edge {
+            write!(
+                self.out,
+                "{indent}{}{parent} -> {}{id}",
+                self.prefix, self.prefix
+            )
+            .unwrap();
+            if !label.is_empty() {
+                write!(self.out, " [label=\"{label}\"]").unwrap();
+            }
+            self.out.push_str(";\n");
+        }
+
+        match ast {
+            Ast::Imm(_) | Ast::Arg(_) | Ast::Local(_) => {}
+            Ast::BinOp(_, lhs, rhs) => {
+                self.node(lhs, folded, Some((id, "")));
+                self.node(rhs, folded, Some((id, "")));
+            }
+            Ast::Let(value, body) => {
+                self.node(value, folded, Some((id, "value")));
+                self.node(body, folded, Some((id, "body")));
+            }
+            Ast::If(cond, then, otherwise) => {
+                self.node(cond, folded, Some((id, "cond")));
+                self.node(then, folded, Some((id, "then")));
+                self.node(otherwise, folded, Some((id, "else")));
+            }
+            Ast::Call(_, args) => {
+                for arg in args {
+                    self.node(arg, folded, Some((id, "")));
+                }
+            }
+        }
+    }
+
+    fn label(&self, ast: &Ast) -> String {
+        match ast {
+            Ast::Imm(n) => n.to_string(),
+            Ast::Arg(n) => match self.args.get(*n) {
+                Some(name) => name.clone(),
+                None => format!("arg {n}"),
+            },
+            Ast::Local(slot) => format!("local {slot}"),
+            Ast::BinOp(op, _, _) => op.symbol().to_string(),
+            Ast::Let(..) => "let".to_string(),
+            Ast::If(..) => "if".to_string(),
+            Ast::Call(function, _) => format!("call {function}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let ast = Ast::let_in(
+            Ast::add(Ast::arg(0), Ast::imm(2)),
+            Ast::if_else(Ast::local(0), Ast::call(1, vec![Ast::arg(1)]), Ast::imm(-1)),
+        );
+
+        assert_eq!(
+            digraph(&ast, &["x".to_string()]),
+            "digraph ast {
+  node [shape=box];
+  n0 [label=\"let\"];
+  n1 [label=\"+\"];
+  n0 -> n1 [label=\"value\"];
+  n2 [label=\"x\"];
+  n1 -> n2;
+  n3 [label=\"2\"];
+  n1 -> n3;
+  n4 [label=\"if\"];
+  n0 -> n4 [label=\"body\"];
+  n5 [label=\"local 0\"];
+  n4 -> n5 [label=\"cond\"];
+  n6 [label=\"call 1\"];
+  n4 -> n6 [label=\"then\"];
+  n7 [label=\"arg 1\"];
+  n6 -> n7;
+  n8 [label=\"-1\"];
+  n4 -> n8 [label=\"else\"];
+}
+"
+        );
+    }
+
+    #[test]
+    fn folded_subtrees() {
+        // x * (2 + 3)
+        let before = Ast::mul(Ast::arg(0), Ast::add(Ast::imm(2), Ast::imm(3)));
+        let after = before
+            .fold(ArithmeticMode::default(), Domain::default())
+            .unwrap();
+
+        assert_eq!(
+            passes(
+                &before,
+                &after,
+                &[],
+                ArithmeticMode::default(),
+                Domain::default()
+            ),
+            "digraph passes {
+  node [shape=box];
+  subgraph cluster_pass1 {
+    label=\"pass1\";
+    a0 [label=\"*\"];
+    a1 [label=\"arg 0\"];
+    a0 -> a1;
+    a2 [label=\"+\", style=filled, fillcolor=lightgray];
+    a0 -> a2;
+    a3 [label=\"2\", style=filled, fillcolor=lightgray];
+    a2 -> a3;
+    a4 [label=\"3\", style=filled, fillcolor=lightgray];
+    a2 -> a4;
+  }
+  subgraph cluster_pass2 {
+    label=\"pass2\";
+    b0 [label=\"*\"];
+    b1 [label=\"arg 0\"];
+    b0 -> b1;
+    b2 [label=\"5\"];
+    b0 -> b2;
+  }
+}
+"
+        );
+    }
+}
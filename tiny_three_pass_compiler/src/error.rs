@@ -0,0 +1,419 @@
+use std::fmt::{self, Display};
+
+use crate::{ast::BinOp, number::Domain};
+
+/// A half-open range of byte offsets into the source program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// An error in the source program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    UnexpectedToken {
+        found: String,
+        expected: &'static str,
+        span: Span,
+    },
+    UnexpectedEof {
+        expected: &'static str,
+        span: Span,
+    },
+    UndeclaredVariable {
+        name: String,
+        span: Span,
+    },
+    DuplicateArgument {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// A call of a function that is not defined.
+    UndefinedFunction {
+        name: String,
+        span: Span,
+    },
+    DuplicateFunction {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// A call with a different number of arguments than
+    /// the function declares.
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A `let` binding reusing the name of an argument or
+    /// of an enclosing binding, declared at `shadowed`.
+    ShadowedBinding {
+        name: String,
+        span: Span,
+        shadowed: Span,
+    },
+    InvalidNumber {
+        literal: String,
+        span: Span,
+    },
+    /// A comparison used as an operand of another comparison
+    /// without parentheses, e.g. `a < b < c`.
+    ChainedComparison {
+        span: Span,
+    },
+    /// A constant subexpression that cannot be evaluated
+    /// in [`ArithmeticMode::Checked`](crate::ArithmeticMode::Checked).
+    ConstantArithmetic {
+        error: ArithmeticError,
+        op: BinOp,
+        lhs: i64,
+        rhs: i64,
+    },
+    /// A backend that only computes on integers, used with
+    /// another [`Domain`](crate::Domain).
+    UnsupportedDomain {
+        domain: Domain,
+    },
+}
+
+impl CompileError {
+    /// Returns the location of the error in the source program,
+    /// if the error can be attributed to one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::UnexpectedToken { span, .. }
+            | Self::UnexpectedEof { span, .. }
+            | Self::UndeclaredVariable { span, .. }
+            | Self::DuplicateArgument { span, .. }
+            | Self::ShadowedBinding { span, .. }
+            | Self::UndefinedFunction { span, .. }
+            | Self::DuplicateFunction { span, .. }
+            | Self::ArityMismatch { span, .. }
+            | Self::InvalidNumber { span, .. }
+            | Self::ChainedComparison { span } => Some(*span),
+            Self::ConstantArithmetic { .. } | Self::UnsupportedDomain { .. } => None,
+        }
+    }
+
+    // Renders the error together with the offending source line
+    // and a caret underline below the span, e.g.
+    //
+    // error: undeclared variable `y`
+    //  --> 1:11
+    //   |
+    // 1 | [ x ] x + y
+    //   |           ^
+    //
+    // Errors without a span are rendered as the message only.
+    pub fn render(&self, source: &str) -> String {
+        let Some(span) = self.span() else {
+            return format!("error: {self}\n");
+        };
+        let start = span.start.min(source.len());
+        let end = span.end.clamp(start, source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_no = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+        let width = source[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = " ".repeat(line_no.to_string().len());
+
+        format!(
+            "error: {self}\n{gutter}--> {line_no}:{column}\n{gutter} |\n{line_no} | {}\n{gutter} | {}{}\n",
+            &source[line_start..line_end],
+            " ".repeat(column - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken {
+                found, expected, ..
+            } => write!(f, "unexpected token `{found}`, expected {expected}"),
+            Self::UnexpectedEof { expected, .. } => {
+                write!(f, "unexpected end of input, expected {expected}")
+            }
+            Self::UndeclaredVariable { name, .. } => write!(f, "undeclared variable `{name}`"),
+            Self::DuplicateArgument { name, .. } => write!(f, "duplicate argument `{name}`"),
+            Self::UndefinedFunction { name, .. } => write!(f, "undefined function `{name}`"),
+            Self::DuplicateFunction { name, .. } => write!(f, "duplicate function `{name}`"),
+            Self::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "function `{name}` takes {expected} arguments, but {found} were given"
+            ),
+            Self::ShadowedBinding { name, .. } => {
+                write!(f, "binding `{name}` shadows a variable in scope")
+            }
+            Self::InvalidNumber { literal, .. } => write!(f, "invalid number `{literal}`"),
+            Self::ChainedComparison { .. } => {
+                f.write_str("comparison operators cannot be chained, use parentheses")
+            }
+            Self::ConstantArithmetic {
+                error,
+                op,
+                lhs,
+                rhs,
+            } => write!(f, "{error} in constant expression `{lhs} {op} {rhs}`"),
+            Self::UnsupportedDomain { domain } => {
+                write!(f, "{domain} numbers are not supported by this backend")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// An error evaluating an arithmetic operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    DivisionByZero,
+    /// A rational raised to a power that is not an integer,
+    /// see [`Domain::Rational`](crate::Domain::Rational).
+    NonIntegerExponent,
+}
+
+impl Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => f.write_str("arithmetic overflow"),
+            Self::DivisionByZero => f.write_str("division by zero"),
+            Self::NonIntegerExponent => f.write_str("exponent is not an integer"),
+        }
+    }
+}
+
+impl std::error::Error for ArithmeticError {}
+
+/// An error evaluating a program without compiling it to
+/// instructions, see [`Compiler::compile_to_fn`](crate::Compiler::compile_to_fn).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalError {
+    Overflow,
+    DivisionByZero,
+    NonIntegerExponent,
+    ArgumentOutOfRange {
+        index: usize,
+        len: usize,
+    },
+    /// An argument that cannot be represented in the
+    /// [`Domain`](crate::Domain) of the evaluation.
+    ArgumentNotInDomain {
+        index: usize,
+    },
+    /// A reference to a slot that no enclosing binding defines.
+    UnboundLocal {
+        slot: usize,
+    },
+    /// A call in a tree evaluated on its own, outside of a program.
+    UnresolvedCall {
+        function: usize,
+    },
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => f.write_str("arithmetic overflow"),
+            Self::DivisionByZero => f.write_str("division by zero"),
+            Self::NonIntegerExponent => f.write_str("exponent is not an integer"),
+            Self::ArgumentOutOfRange { index, len } => {
+                write!(f, "argument {index} out of range, {len} arguments given")
+            }
+            Self::ArgumentNotInDomain { index } => {
+                write!(f, "argument {index} is not in the domain")
+            }
+            Self::UnboundLocal { slot } => write!(f, "local {slot} is not bound"),
+            Self::UnresolvedCall { function } => {
+                write!(f, "call of function {function} outside of a program")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<ArithmeticError> for EvalError {
+    fn from(e: ArithmeticError) -> Self {
+        match e {
+            ArithmeticError::Overflow => Self::Overflow,
+            ArithmeticError::DivisionByZero => Self::DivisionByZero,
+            ArithmeticError::NonIntegerExponent => Self::NonIntegerExponent,
+        }
+    }
+}
+
+/// An error reading an [`Ast`](crate::Ast) from JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    /// The input is not valid JSON or contains unknown fields.
+    Syntax(String),
+    UnknownOp(String),
+    MissingField {
+        op: String,
+        field: &'static str,
+    },
+    /// A negative argument, slot or function index.
+    InvalidIndex {
+        op: String,
+        n: i64,
+    },
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(message) => write!(f, "invalid JSON: {message}"),
+            Self::UnknownOp(op) => write!(f, "unknown op `{op}`"),
+            Self::MissingField { op, field } => write!(f, "`{op}` requires field `{field}`"),
+            Self::InvalidIndex { op, n } => write!(f, "invalid index {n} for `{op}`"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// An error parsing a single assembly instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstructionError {
+    UnknownMnemonic(String),
+    MissingOperand(String),
+    InvalidOperand(String),
+    UnexpectedOperand(String),
+}
+
+impl Display for InstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic `{mnemonic}`"),
+            Self::MissingOperand(mnemonic) => write!(f, "`{mnemonic}` requires an operand"),
+            Self::InvalidOperand(operand) => write!(f, "invalid operand `{operand}`"),
+            Self::UnexpectedOperand(operand) => write!(f, "unexpected operand `{operand}`"),
+        }
+    }
+}
+
+impl std::error::Error for InstructionError {}
+
+/// An error assembling a listing, with the 1-based line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub error: InstructionError,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for AssembleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// An error raised while executing a program on the [`Vm`](crate::Vm).
+/// `pc` is the index of the failing instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    StackUnderflow {
+        pc: usize,
+    },
+    StackOverflow {
+        pc: usize,
+        limit: usize,
+    },
+    ArgumentOutOfRange {
+        pc: usize,
+        index: usize,
+        len: usize,
+    },
+    /// An argument that cannot be represented in the
+    /// [`Domain`](crate::Domain) of the machine.
+    ArgumentNotInDomain {
+        index: usize,
+    },
+    SlotOutOfRange {
+        pc: usize,
+        slot: usize,
+    },
+    JumpOutOfRange {
+        pc: usize,
+        target: usize,
+    },
+    DivisionByZero {
+        pc: usize,
+    },
+    Overflow {
+        pc: usize,
+    },
+    NonIntegerExponent {
+        pc: usize,
+    },
+    BudgetExhausted {
+        budget: usize,
+    },
+}
+
+impl VmError {
+    pub(crate) fn arithmetic(error: ArithmeticError, pc: usize) -> Self {
+        match error {
+            ArithmeticError::Overflow => Self::Overflow { pc },
+            ArithmeticError::DivisionByZero => Self::DivisionByZero { pc },
+            ArithmeticError::NonIntegerExponent => Self::NonIntegerExponent { pc },
+        }
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackUnderflow { pc } => write!(f, "stack underflow at {pc}"),
+            Self::StackOverflow { pc, limit } => {
+                write!(f, "stack overflow at {pc}, limit is {limit}")
+            }
+            Self::ArgumentOutOfRange { pc, index, len } => write!(
+                f,
+                "argument {index} out of range at {pc}, {len} arguments given"
+            ),
+            Self::ArgumentNotInDomain { index } => {
+                write!(f, "argument {index} is not in the domain")
+            }
+            Self::SlotOutOfRange { pc, slot } => write!(f, "slot {slot} out of range at {pc}"),
+            Self::JumpOutOfRange { pc, target } => {
+                write!(f, "jump target {target} out of range at {pc}")
+            }
+            Self::DivisionByZero { pc } => write!(f, "division by zero at {pc}"),
+            Self::Overflow { pc } => write!(f, "arithmetic overflow at {pc}"),
+            Self::NonIntegerExponent { pc } => write!(f, "exponent is not an integer at {pc}"),
+            Self::BudgetExhausted { budget } => {
+                write!(f, "instruction budget of {budget} exhausted")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
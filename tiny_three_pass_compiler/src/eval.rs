@@ -0,0 +1,225 @@
+use crate::{
+    arith::ArithmeticMode,
+    ast::Ast,
+    error::EvalError,
+    number::{convert_args, Domain, Number, Value},
+};
+
+// Evaluates an AST directly by walking the tree, which defines the
+// semantics of the language that every pass and backend preserves:
+//
+// - operands of a binary operation are evaluated left to right and
+//   combined by `ArithmeticMode::apply`, or by the arithmetic of
+//   the domain outside of the integer domain,
+// - a conditional evaluates its condition and then only the branch
+//   that is taken, the `then` branch for any nonzero value,
+// - a binding evaluates its value once, before its body.
+//
+// The first error aborts the evaluation. Calls cannot be resolved
+// without the program they belong to and are an error.
+pub(crate) fn eval<N: Value>(
+    ast: &Ast,
+    args: Vec<N>,
+    mode: ArithmeticMode,
+    domain: Domain,
+) -> Result<N, EvalError> {
+    Evaluator {
+        args,
+        mode,
+        domain,
+        locals: vec![],
+    }
+    .eval(ast)
+}
+
+pub(crate) fn eval_numbers(
+    ast: &Ast,
+    args: &[Number],
+    mode: ArithmeticMode,
+    domain: Domain,
+) -> Result<Number, EvalError> {
+    let args =
+        convert_args(domain, args).map_err(|index| EvalError::ArgumentNotInDomain { index })?;
+    eval(ast, args, mode, domain)
+}
+
+struct Evaluator<N> {
+    args: Vec<N>,
+    mode: ArithmeticMode,
+    domain: Domain,
+    locals: Vec<N>,
+}
+
+impl<N: Value> Evaluator<N> {
+    fn eval(&mut self, ast: &Ast) -> Result<N, EvalError> {
+        match ast {
+            &Ast::Imm(n) => Ok(N::number(self.domain, n)),
+            &Ast::Arg(index) => {
+                self.args
+                    .get(index)
+                    .copied()
+                    .ok_or(EvalError::ArgumentOutOfRange {
+                        index,
+                        len: self.args.len(),
+                    })
+            }
+            &Ast::Local(slot) => self
+                .locals
+                .get(slot)
+                .copied()
+                .ok_or(EvalError::UnboundLocal { slot }),
+            Ast::BinOp(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                Ok(N::apply(self.domain, self.mode, *op, lhs, rhs)?)
+            }
+            Ast::Let(value, body) => {
+                let value = self.eval(value)?;
+                self.locals.push(value);
+                let result = self.eval(body);
+                self.locals.pop();
+                result
+            }
+            Ast::If(cond, then, otherwise) => {
+                if !self.eval(cond)?.is_zero() {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+            &Ast::Call(function, _) => Err(EvalError::UnresolvedCall { function }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::BinOp,
+        codegen, cse,
+        peephole::peephole,
+        simplify::simplify,
+        stack,
+        stack::StackVm,
+        testing::{cross_check, cross_check_numbers},
+        vm::Vm,
+    };
+
+    #[test]
+    fn evaluation() {
+        // let a = x * 2 in if a > y then a else y % 3
+        let ast = Ast::let_in(
+            Ast::mul(Ast::arg(0), Ast::imm(2)),
+            Ast::if_else(
+                Ast::bin_op(BinOp::Gt, Ast::local(0), Ast::arg(1)),
+                Ast::local(0),
+                Ast::bin_op(BinOp::Rem, Ast::arg(1), Ast::imm(3)),
+            ),
+        );
+
+        assert_eq!(ast.eval(&[7, 3]), Ok(14));
+        assert_eq!(ast.eval(&[1, 5]), Ok(2));
+        assert_eq!(ast.eval(&[i64::MAX, 5]), Ok(5 % 3));
+        assert_eq!(
+            ast.eval_with(&[i64::MAX, 5], ArithmeticMode::Saturating),
+            Ok(i64::MAX)
+        );
+        assert_eq!(
+            ast.eval_with(&[i64::MAX, 5], ArithmeticMode::Checked),
+            Err(EvalError::Overflow)
+        );
+        assert_eq!(
+            ast.eval(&[1]),
+            Err(EvalError::ArgumentOutOfRange { index: 1, len: 1 })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        // The branch that is not taken is not evaluated.
+        let ast = Ast::if_else(
+            Ast::arg(0),
+            Ast::div(Ast::imm(1), Ast::arg(0)),
+            Ast::div(Ast::imm(1), Ast::imm(0)),
+        );
+        assert_eq!(ast.eval(&[1]), Ok(1));
+        assert_eq!(ast.eval(&[0]), Err(EvalError::DivisionByZero));
+
+        // Operands are evaluated left to right.
+        let ast = Ast::add(Ast::arg(2), Ast::div(Ast::imm(1), Ast::imm(0)));
+        assert_eq!(
+            ast.eval(&[]),
+            Err(EvalError::ArgumentOutOfRange { index: 2, len: 0 })
+        );
+
+        assert_eq!(
+            Ast::let_in(Ast::imm(1), Ast::local(1)).eval(&[]),
+            Err(EvalError::UnboundLocal { slot: 1 })
+        );
+        assert_eq!(
+            Ast::call(2, vec![]).eval(&[]),
+            Err(EvalError::UnresolvedCall { function: 2 })
+        );
+    }
+
+    #[test]
+    fn passes_preserve_semantics() {
+        cross_check(24, |ast, mode| {
+            let folded = ast.fold(mode, Domain::Integer);
+            move |args: &[i64]| match &folded {
+                Ok(folded) => folded.eval_with(args, mode).ok(),
+                // A constant error outside of a conditional always occurs.
+                Err(_) => None,
+            }
+        });
+        cross_check(24, |ast, mode| {
+            let simplified = simplify(ast, mode, Domain::Integer);
+            move |args: &[i64]| simplified.eval_with(args, mode).ok()
+        });
+        cross_check(24, |ast, mode| {
+            let shared = cse::eliminate(&simplify(ast, mode, Domain::Integer));
+            move |args: &[i64]| shared.eval_with(args, mode).ok()
+        });
+    }
+
+    #[test]
+    fn backends_agree() {
+        cross_check(124, |ast, mode| {
+            let (vm, asm) = (Vm::new().with_arithmetic(mode), codegen::generate(ast));
+            move |args: &[i64]| vm.run(&asm, args).ok()
+        });
+        cross_check(124, |ast, mode| {
+            let vm = Vm::new().with_arithmetic(mode);
+            let asm = peephole(&codegen::generate(ast));
+            move |args: &[i64]| vm.run(&asm, args).ok()
+        });
+    }
+
+    #[test]
+    fn domains() {
+        for domain in [Domain::Float, Domain::Rational] {
+            let fold = move |ast: &Ast, mode| ast.fold(mode, domain).unwrap();
+            let simplify = move |ast: &Ast, mode| simplify(&fold(ast, mode), mode, domain);
+            let eval = move |ast: Ast, mode| {
+                move |args: &[Number]| ast.eval_numbers(args, mode, domain).ok()
+            };
+
+            cross_check_numbers(25, domain, |ast, mode| eval(fold(ast, mode), mode));
+            cross_check_numbers(25, domain, |ast, mode| eval(simplify(ast, mode), mode));
+            cross_check_numbers(25, domain, |ast, mode| {
+                eval(cse::eliminate(&simplify(ast, mode)), mode)
+            });
+            cross_check_numbers(25, domain, |ast, mode| {
+                let vm = Vm::new().with_arithmetic(mode).with_domain(domain);
+                let asm = peephole(&codegen::generate(&cse::eliminate(&simplify(ast, mode))));
+                move |args: &[Number]| vm.run_numbers(&asm, args).ok()
+            });
+            cross_check_numbers(25, domain, |ast, mode| {
+                let vm = StackVm::new().with_arithmetic(mode).with_domain(domain);
+                let asm = stack::generate(&fold(ast, mode));
+                move |args: &[Number]| vm.run_numbers(&asm, args).ok()
+            });
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use crate::{
+    ast::BinOp,
+    error::{AssembleError, InstructionError},
+};
+
+/// An instruction of the target machine, which has two
+/// registers, R0 and R1, a stack, and a stack of call frames.
+/// Arguments are read from the current frame and local slots
+/// are addressed from the bottom of the frame's part of the stack.
+///
+/// Jump targets are absolute instruction offsets. Jumping
+/// to the offset past the last instruction ends the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `IM n`: load the constant value n into R0
+    Im(i64),
+    /// `AR n`: load the n-th input argument into R0
+    Ar(usize),
+    /// `SW`: swap R0 and R1
+    Sw,
+    /// `PU`: push R0 onto the stack
+    Pu,
+    /// `PO`: pop the top value off of the stack into R0
+    Po,
+    /// `LD n`: load the n-th local slot into R0
+    Ld(usize),
+    /// `ST n`: store R0 into the n-th local slot
+    St(usize),
+    /// `AD`: add R1 to R0 and put the result in R0
+    Ad,
+    /// `SU`: subtract R1 from R0 and put the result in R0
+    Su,
+    /// `MU`: multiply R0 by R1 and put the result in R0
+    Mu,
+    /// `DI`: divide R0 by R1 and put the result in R0
+    Di,
+    /// `MO`: put the remainder of dividing R0 by R1 in R0
+    Mo,
+    /// `PW`: raise R0 to the power of R1 and put the result in R0
+    Pw,
+    /// `EQ`: put 1 in R0 if R0 is equal to R1, else 0
+    Eq,
+    /// `NE`: put 1 in R0 if R0 is not equal to R1, else 0
+    Ne,
+    /// `LT`: put 1 in R0 if R0 is less than R1, else 0
+    Lt,
+    /// `LE`: put 1 in R0 if R0 is less than or equal to R1, else 0
+    Le,
+    /// `GT`: put 1 in R0 if R0 is greater than R1, else 0
+    Gt,
+    /// `GE`: put 1 in R0 if R0 is greater than or equal to R1, else 0
+    Ge,
+    /// `JZ n`: jump to the n-th instruction if R0 is zero
+    Jz(usize),
+    /// `JMP n`: jump to the n-th instruction
+    Jmp(usize),
+    /// `CALL n m`: pop m values off of the stack as the arguments
+    /// of a new frame, pushed in order, and jump to the n-th instruction,
+    /// keeping R0 and R1
+    Call(usize, usize),
+    /// `RET`: drop the current frame and return to the instruction
+    /// after its `CALL`, or end the program if there is none, keeping
+    /// R0 and R1
+    Ret,
+}
+
+impl Instruction {
+    /// Returns the mnemonic, e.g. `"IM"`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Im(_) => "IM",
+            Self::Ar(_) => "AR",
+            Self::Sw => "SW",
+            Self::Pu => "PU",
+            Self::Po => "PO",
+            Self::Ld(_) => "LD",
+            Self::St(_) => "ST",
+            Self::Ad => "AD",
+            Self::Su => "SU",
+            Self::Mu => "MU",
+            Self::Di => "DI",
+            Self::Mo => "MO",
+            Self::Pw => "PW",
+            Self::Eq => "EQ",
+            Self::Ne => "NE",
+            Self::Lt => "LT",
+            Self::Le => "LE",
+            Self::Gt => "GT",
+            Self::Ge => "GE",
+            Self::Jz(_) => "JZ",
+            Self::Jmp(_) => "JMP",
+            Self::Call(..) => "CALL",
+            Self::Ret => "RET",
+        }
+    }
+
+    /// Returns the instruction applying the operator to R0 and R1.
+    pub fn from_bin_op(op: BinOp) -> Self {
+        match op {
+            BinOp::Add => Self::Ad,
+            BinOp::Sub => Self::Su,
+            BinOp::Mul => Self::Mu,
+            BinOp::Div => Self::Di,
+            BinOp::Rem => Self::Mo,
+            BinOp::Pow => Self::Pw,
+            BinOp::Eq => Self::Eq,
+            BinOp::Ne => Self::Ne,
+            BinOp::Lt => Self::Lt,
+            BinOp::Le => Self::Le,
+            BinOp::Gt => Self::Gt,
+            BinOp::Ge => Self::Ge,
+        }
+    }
+
+    /// Returns the target if this is a jump or a call.
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Self::Jz(target) | Self::Jmp(target) | Self::Call(target, _) => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// Returns the operator if this instruction applies one to R0 and R1.
+    pub fn bin_op(&self) -> Option<BinOp> {
+        match self {
+            Self::Ad => Some(BinOp::Add),
+            Self::Su => Some(BinOp::Sub),
+            Self::Mu => Some(BinOp::Mul),
+            Self::Di => Some(BinOp::Div),
+            Self::Mo => Some(BinOp::Rem),
+            Self::Pw => Some(BinOp::Pow),
+            Self::Eq => Some(BinOp::Eq),
+            Self::Ne => Some(BinOp::Ne),
+            Self::Lt => Some(BinOp::Lt),
+            Self::Le => Some(BinOp::Le),
+            Self::Gt => Some(BinOp::Gt),
+            Self::Ge => Some(BinOp::Ge),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Im(n) => write!(f, "{} {}", self.mnemonic(), n),
+            Self::Ar(n) | Self::Ld(n) | Self::St(n) | Self::Jz(n) | Self::Jmp(n) => {
+                write!(f, "{} {}", self.mnemonic(), n)
+            }
+            Self::Call(target, argc) => write!(f, "{} {} {}", self.mnemonic(), target, argc),
+            _ => f.write_str(self.mnemonic()),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = InstructionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ws = s.split_whitespace();
+        let mnemonic = ws.next().unwrap_or_default();
+
+        let ins = match mnemonic {
+            "IM" => Self::Im(operand(mnemonic, ws.next())?),
+            "AR" => Self::Ar(operand(mnemonic, ws.next())?),
+            "SW" => Self::Sw,
+            "PU" => Self::Pu,
+            "PO" => Self::Po,
+            "LD" => Self::Ld(operand(mnemonic, ws.next())?),
+            "ST" => Self::St(operand(mnemonic, ws.next())?),
+            "AD" => Self::Ad,
+            "SU" => Self::Su,
+            "MU" => Self::Mu,
+            "DI" => Self::Di,
+            "MO" => Self::Mo,
+            "PW" => Self::Pw,
+            "EQ" => Self::Eq,
+            "NE" => Self::Ne,
+            "LT" => Self::Lt,
+            "LE" => Self::Le,
+            "GT" => Self::Gt,
+            "GE" => Self::Ge,
+            "JZ" => Self::Jz(operand(mnemonic, ws.next())?),
+            "JMP" => Self::Jmp(operand(mnemonic, ws.next())?),
+            "CALL" => Self::Call(operand(mnemonic, ws.next())?, operand(mnemonic, ws.next())?),
+            "RET" => Self::Ret,
+            _ => return Err(InstructionError::UnknownMnemonic(mnemonic.to_string())),
+        };
+
+        match ws.next() {
+            Some(extra) => Err(InstructionError::UnexpectedOperand(extra.to_string())),
+            None => Ok(ins),
+        }
+    }
+}
+
+pub(crate) fn operand<T: FromStr>(
+    mnemonic: &str,
+    operand: Option<&str>,
+) -> Result<T, InstructionError> {
+    match operand {
+        Some(n) => n
+            .parse()
+            .map_err(|_| InstructionError::InvalidOperand(n.to_string())),
+        None => Err(InstructionError::MissingOperand(mnemonic.to_string())),
+    }
+}
+
+/// Parses a textual listing with one instruction per line.
+/// Blank lines are ignored.
+pub fn assemble(listing: &str) -> Result<Vec<Instruction>, AssembleError> {
+    listing
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            line.parse().map_err(|error| AssembleError {
+                line: idx + 1,
+                error,
+            })
+        })
+        .collect()
+}
+
+/// Writes the instructions as a textual listing with one instruction per line.
+/// Works for the instructions of either target, see [`Target`](crate::Target).
+pub fn disassemble<I: Display>(program: &[I]) -> String {
+    program.iter().map(|ins| format!("{ins}\n")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let program = vec![
+            Instruction::Im(-10),
+            Instruction::Sw,
+            Instruction::Ar(0),
+            Instruction::Ad,
+            Instruction::St(1),
+            Instruction::Ld(1),
+            Instruction::Le,
+            Instruction::Jz(9),
+            Instruction::Jmp(3),
+            Instruction::Call(12, 2),
+            Instruction::Ret,
+        ];
+
+        let listing = disassemble(&program);
+
+        assert_eq!(
+            listing,
+            "IM -10\nSW\nAR 0\nAD\nST 1\nLD 1\nLE\nJZ 9\nJMP 3\nCALL 12 2\nRET\n"
+        );
+        assert_eq!(assemble(&listing), Ok(program));
+    }
+
+    #[test]
+    fn malformed_input() {
+        assert_eq!(
+            assemble("IM 1\n\nSW\nXX\n"),
+            Err(AssembleError {
+                line: 4,
+                error: InstructionError::UnknownMnemonic("XX".to_string())
+            })
+        );
+        assert_eq!(
+            assemble("AR"),
+            Err(AssembleError {
+                line: 1,
+                error: InstructionError::MissingOperand("AR".to_string())
+            })
+        );
+        assert_eq!(
+            assemble("SW\nIM x"),
+            Err(AssembleError {
+                line: 2,
+                error: InstructionError::InvalidOperand("x".to_string())
+            })
+        );
+        assert_eq!(
+            assemble("CALL 1"),
+            Err(AssembleError {
+                line: 1,
+                error: InstructionError::MissingOperand("CALL".to_string())
+            })
+        );
+        assert_eq!(
+            assemble("PU 1"),
+            Err(AssembleError {
+                line: 1,
+                error: InstructionError::UnexpectedOperand("1".to_string())
+            })
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::{Ast, BinOp},
+    error::JsonError,
+};
+
+// The JSON representation of an AST used by the kata, e.g.
+//
+//     {"op":"+","a":{"op":"arg","n":0},"b":{"op":"imm","n":2}}
+//
+// Binary operations are keyed by their source symbol with the
+// operands in `a` and `b`. Immediates and arguments carry their
+// value or index in `n`.
+//
+// The kata has no bindings, conditionals or calls, they are
+// encoded in the same style:
+//
+//     {"op":"let","a":value,"b":body}
+//     {"op":"local","n":slot}
+//     {"op":"if","a":cond,"b":then,"c":otherwise}
+//     {"op":"call","n":function,"args":[...]}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Node {
+    op: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    a: Option<Box<Node>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    b: Option<Box<Node>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    c: Option<Box<Node>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    args: Option<Vec<Node>>,
+}
+
+impl Node {
+    fn new(op: &str) -> Self {
+        Self {
+            op: op.to_string(),
+            n: None,
+            a: None,
+            b: None,
+            c: None,
+            args: None,
+        }
+    }
+
+    fn with_n(mut self, n: i64) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    fn with_operands(mut self, operands: &[&Ast]) -> Self {
+        let mut operands = operands.iter().map(|ast| Some(Box::new(Self::from(*ast))));
+        self.a = operands.next().flatten();
+        self.b = operands.next().flatten();
+        self.c = operands.next().flatten();
+        self
+    }
+
+    fn n(&self) -> Result<i64, JsonError> {
+        self.n.ok_or_else(|| self.missing("n"))
+    }
+
+    fn index(&self) -> Result<usize, JsonError> {
+        let n = self.n()?;
+        usize::try_from(n).map_err(|_| JsonError::InvalidIndex {
+            op: self.op.clone(),
+            n,
+        })
+    }
+
+    fn operand(&self, field: &'static str) -> Result<Ast, JsonError> {
+        let node = match field {
+            "a" => &self.a,
+            "b" => &self.b,
+            _ => &self.c,
+        };
+        match node {
+            Some(node) => Ast::try_from(node.as_ref()),
+            None => Err(self.missing(field)),
+        }
+    }
+
+    fn missing(&self, field: &'static str) -> JsonError {
+        JsonError::MissingField {
+            op: self.op.clone(),
+            field,
+        }
+    }
+}
+
+impl From<&Ast> for Node {
+    fn from(ast: &Ast) -> Self {
+        match ast {
+            Ast::Imm(n) => Node::new("imm").with_n(*n),
+            Ast::Arg(n) => Node::new("arg").with_n(*n as i64),
+            Ast::Local(slot) => Node::new("local").with_n(*slot as i64),
+            Ast::BinOp(op, lhs, rhs) => Node::new(op.symbol()).with_operands(&[lhs, rhs]),
+            Ast::Let(value, body) => Node::new("let").with_operands(&[value, body]),
+            Ast::If(cond, then, otherwise) => {
+                Node::new("if").with_operands(&[cond, then, otherwise])
+            }
+            Ast::Call(function, args) => {
+                let mut node = Node::new("call").with_n(*function as i64);
+                node.args = Some(args.iter().map(Node::from).collect());
+                node
+            }
+        }
+    }
+}
+
+impl TryFrom<&Node> for Ast {
+    type Error = JsonError;
+
+    fn try_from(node: &Node) -> Result<Self, Self::Error> {
+        match node.op.as_str() {
+            "imm" => Ok(Ast::imm(node.n()?)),
+            "arg" => Ok(Ast::arg(node.index()?)),
+            "local" => Ok(Ast::local(node.index()?)),
+            "let" => Ok(Ast::let_in(node.operand("a")?, node.operand("b")?)),
+            "if" => Ok(Ast::if_else(
+                node.operand("a")?,
+                node.operand("b")?,
+                node.operand("c")?,
+            )),
+            "call" => Ok(Ast::call(
+                node.index()?,
+                node.args
+                    .as_deref()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(Ast::try_from)
+                    .collect::<Result<_, _>>()?,
+            )),
+            symbol => match BinOp::from_symbol(symbol) {
+                Some(op) => Ok(Ast::bin_op(op, node.operand("a")?, node.operand("b")?)),
+                None => Err(JsonError::UnknownOp(symbol.to_string())),
+            },
+        }
+    }
+}
+
+pub(crate) fn to_json(ast: &Ast) -> String {
+    serde_json::to_string(&Node::from(ast)).expect("AST nodes serialize to JSON")
+}
+
+pub(crate) fn from_json(json: &str) -> Result<Ast, JsonError> {
+    let node: Node = serde_json::from_str(json).map_err(|e| JsonError::Syntax(e.to_string()))?;
+    Ast::try_from(&node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    #[test]
+    fn kata_format() {
+        let ast = Ast::add(Ast::arg(0), Ast::imm(2));
+        let json = r#"{"op":"+","a":{"op":"arg","n":0},"b":{"op":"imm","n":2}}"#;
+
+        assert_eq!(to_json(&ast), json);
+        assert_eq!(from_json(json), Ok(ast));
+
+        // whitespace and field order are irrelevant
+        assert_eq!(
+            from_json(r#"{ "b": {"n": -3, "op": "imm"}, "op": "/", "a": {"op": "arg", "n": 1} }"#),
+            Ok(Ast::div(Ast::arg(1), Ast::imm(-3)))
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let ast = Ast::let_in(
+            Ast::call(1, vec![Ast::arg(0), Ast::imm(1)]),
+            Ast::if_else(
+                Ast::bin_op(BinOp::Le, Ast::local(0), Ast::imm(0)),
+                Ast::imm(0),
+                Ast::local(0),
+            ),
+        );
+        let json = concat!(
+            r#"{"op":"let","#,
+            r#""a":{"op":"call","n":1,"args":[{"op":"arg","n":0},{"op":"imm","n":1}]},"#,
+            r#""b":{"op":"if","#,
+            r#""a":{"op":"<=","a":{"op":"local","n":0},"b":{"op":"imm","n":0}},"#,
+            r#""b":{"op":"imm","n":0},"#,
+            r#""c":{"op":"local","n":0}}}"#
+        );
+
+        assert_eq!(to_json(&ast), json);
+        assert_eq!(from_json(json), Ok(ast));
+        assert_eq!(
+            from_json(r#"{"op":"call","n":0}"#),
+            Ok(Ast::call(0, vec![]))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            from_json(r#"{"op":"<>","a":{"op":"imm","n":1},"b":{"op":"imm","n":2}}"#),
+            Err(JsonError::UnknownOp("<>".to_string()))
+        );
+        assert_eq!(
+            from_json(r#"{"op":"-","a":{"op":"imm","n":1}}"#),
+            Err(JsonError::MissingField {
+                op: "-".to_string(),
+                field: "b"
+            })
+        );
+        assert_eq!(
+            from_json(r#"{"op":"arg"}"#),
+            Err(JsonError::MissingField {
+                op: "arg".to_string(),
+                field: "n"
+            })
+        );
+        assert_eq!(
+            from_json(r#"{"op":"arg","n":-1}"#),
+            Err(JsonError::InvalidIndex {
+                op: "arg".to_string(),
+                n: -1
+            })
+        );
+        assert!(matches!(
+            from_json(r#"{"op":"imm","n":1"#),
+            Err(JsonError::Syntax(_))
+        ));
+        assert!(matches!(
+            from_json(r#"{"op":"imm","value":1}"#),
+            Err(JsonError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::new(16);
+
+        for _ in 0..200 {
+            let ast = rng.ast(5, 3);
+            assert_eq!(from_json(&to_json(&ast)), Ok(ast));
+        }
+    }
+}
@@ -1,319 +1,434 @@
-#![allow(dead_code)]
-use std::{collections::HashMap, iter::Peekable, vec::IntoIter};
+//! A compiler for a tiny arithmetic language, solving the
+//! [Tiny Three-Pass Compiler](https://www.codewars.com/kata/5265b0885fda8eac5900093b) kata.
+//!
+//! A program declares its arguments followed by an expression:
+//!
+//! ```text
+//! [ x y ] ( x + y ) / 2
+//! ```
+//!
+//! Compilation happens in three passes:
+//!
+//! 1. [`Compiler::pass1`] parses the program into an [`Ast`],
+//! 2. [`Compiler::pass2`] folds constant subexpressions and
+//!    [`Compiler::simplify`] applies algebraic simplifications,
+//! 3. [`Compiler::pass3`] emits assembly for a two-register stack machine,
+//!    which can be executed on the [`Vm`], or for a pure stack machine
+//!    executed on the [`StackVm`], see [`Compiler::with_target`].
+//!
+//! Programs compute on integers, or on floats or exact rationals
+//! with decimal literals such as `1.5`, see [`Compiler::with_domain`].
+//!
+//! Programs of several functions calling each other are compiled
+//! with [`Compiler::compile_program`].
+//!
+//! [`Ast::eval`] evaluates an AST directly and defines the meaning
+//! of a program, which every pass and target preserves.
+//!
+//! ASTs can be exchanged with other implementations of the kata
+//! in its JSON format, see [`Ast::to_json`] and [`Ast::from_json`].
+//!
+//! ```
+//! use tiny_three_pass_compiler::Compiler;
+//!
+//! let mut compiler = Compiler::new();
+//! let asm = compiler.compile("[ x ] x + 2*5").unwrap();
+//!
+//! assert_eq!(asm, vec!["IM 10", "SW", "AR 0", "AD"]);
+//! ```
+mod arith;
+mod ast;
+mod closure;
+mod codegen;
+mod cse;
+mod dot;
+mod error;
+mod eval;
+mod instruction;
+mod json;
+mod number;
+mod parser;
+mod peephole;
+mod program;
+mod simplify;
+mod stack;
+#[cfg(test)]
+mod testing;
+mod unparse;
+mod vm;
+mod wat;
+mod x86;
+
+pub use arith::ArithmeticMode;
+pub use ast::{Ast, BinOp};
+pub use error::{
+    ArithmeticError, AssembleError, CompileError, EvalError, InstructionError, JsonError, Span,
+    VmError,
+};
+pub use instruction::{assemble, disassemble, Instruction};
+pub use number::{Domain, Number, Rational};
+pub use peephole::peephole;
+pub use program::{Function, Program};
+pub use stack::{StackInstruction, StackVm};
+pub use vm::Vm;
+
+use parser::{tokenize, Parser, TokenStream};
 
-#[derive(Debug, PartialEq)]
-enum Ast {
-    UnOp(String, usize),
-    BinOp(String, Box<Self>, Box<Self>),
+/// The machine [`Compiler::pass3`] generates code for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Target {
+    /// The machine of the kata with two registers and a stack,
+    /// see [`Instruction`] and [`Vm`].
+    #[default]
+    Register,
+    /// A machine without registers, see [`StackInstruction`]
+    /// and [`StackVm`].
+    Stack,
 }
 
-impl Ast {
-    // Simplifies the AST by applying constant folding,
-    // i.e., evaluating binary expression where both
-    // inputs are immediate values. Folding is applied
-    // bottom-up, requiring only one pass over the AST.
-    fn fold(&self) -> Ast {
-        match self {
-            Self::BinOp(op, lhs, rhs) => {
-                let lhs = lhs.fold();
-                let rhs = rhs.fold();
-
-                match (&lhs, &rhs) {
-                    (Self::UnOp(cmd_lhs, n_lhs), Self::UnOp(cmd_rhs, n_rhs))
-                        if cmd_lhs == "imm" && cmd_rhs == "imm" =>
-                    {
-                        let n = match op.as_str() {
-                            "+" => n_lhs + n_rhs,
-                            "-" => n_lhs - n_rhs,
-                            "*" => n_lhs * n_rhs,
-                            "/" => n_lhs / n_rhs,
-                            _ => unreachable!(),
-                        };
-
-                        Self::UnOp("imm".to_string(), n)
-                    }
-                    _ => Self::BinOp(op.clone(), Box::new(lhs), Box::new(rhs)),
-                }
-            }
-            Self::UnOp(op, n) => Self::UnOp(op.clone(), *n),
-        }
+#[derive(Debug, Default)]
+pub struct Compiler {
+    mode: ArithmeticMode,
+    domain: Domain,
+    target: Target,
+    cse: bool,
+    dot: bool,
+    // Argument names of the last program parsed by `pass1`.
+    args: Vec<String>,
+    // Digraph of the last program compiled with `dot` set.
+    passes: Option<String>,
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::default()
     }
 
-    // Transforms the AST into the following assembly language
-    //
-    // "IM n"     // load the constant value n into R0
-    // "AR n"     // load the n-th input argument into R0
-    // "SW"       // swap R0 and R1
-    // "PU"       // push R0 onto the stack
-    // "PO"       // pop the top value off of the stack into R0
-    // "AD"       // add R1 to R0 and put the result in R0
-    // "SU"       // subtract R1 from R0 and put the result in R0
-    // "MU"       // multiply R0 by R1 and put the result in R0
-    // "DI"       // divide R0 by R1 and put the result in R0
-    fn transform(&self, asm: &mut Vec<String>) {
-        match self {
-            Self::BinOp(op, lhs, rhs) => {
-                match (&**lhs, &**rhs) {
-                    (Ast::UnOp(_, _), _) => {
-                        rhs.transform(asm);
-                        asm.push("SW".to_string());
-                        lhs.transform(asm);
-                    }
-                    (Ast::BinOp(_, _, _), Ast::UnOp(_, _)) => {
-                        lhs.transform(asm);
-                        asm.push("SW".to_string());
-                        rhs.transform(asm);
-                    }
-                    (Ast::BinOp(_, _, _), Ast::BinOp(_, _, _)) => {
-                        lhs.transform(asm);
-                        asm.push("PU".to_string());
-                        rhs.transform(asm);
-                        asm.push("SW".to_string());
-                        asm.push("PO".to_string());
-                    }
-                }
+    /// Sets the overflow behaviour of the arithmetic of programs.
+    ///
+    /// The mode is used for constant folding, and by the machines of
+    /// [`Compiler::vm`] and [`Compiler::stack_vm`], the closures of
+    /// [`Compiler::compile_to_fn`], and the code of [`Compiler::pass3_wat`]
+    /// and [`Compiler::pass3_x86`], so that all of them compute the same
+    /// results. It also decides which rewrites [`Compiler::simplify`]
+    /// may apply: constants are only combined across chains of `+` and
+    /// `*` in [`ArithmeticMode::Wrapping`], where reassociating them
+    /// cannot change the result.
+    pub fn with_arithmetic(mut self, mode: ArithmeticMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-                let op = match op.as_str() {
-                    "*" => "MU".to_string(),
-                    "/" => "DI".to_string(),
-                    "+" => "AD".to_string(),
-                    "-" => "SU".to_string(),
-                    _ => unreachable!(),
-                };
+    /// Sets the numbers programs compute with, used for number
+    /// literals and constant folding. Execute the code generated
+    /// for a domain other than [`Domain::Integer`] with
+    /// [`Vm::run_numbers`] or [`StackVm::run_numbers`].
+    ///
+    /// The [`Ast`] has no node for decimal literals: `1.5` is parsed
+    /// as the quotient `15 / 10` of two integers, which is exact in
+    /// [`Domain::Rational`], and [`Compiler::to_source`] prints it as
+    /// such. Folding writes a constant that is not an integer the same
+    /// way, e.g. `1.5` as `3 / 2`.
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        self.domain = domain;
+        self
+    }
 
-                asm.push(op);
-            }
-            Self::UnOp(op, n) => {
-                if op == "imm" {
-                    asm.push(format!("IM {}", n));
-                } else {
-                    asm.push(format!("AR {}", n));
-                }
-            }
-        }
+    /// Sets the machine to generate code for in [`Compiler::pass3`],
+    /// [`Compiler::compile`] and [`Compiler::compile_program`].
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
     }
-}
 
-type TokenStream = Peekable<IntoIter<String>>;
+    /// Enables common-subexpression elimination in [`Compiler::compile`].
+    pub fn with_cse(mut self, cse: bool) -> Self {
+        self.cse = cse;
+        self
+    }
 
-trait Nom<T> {
-    fn nom(&mut self) -> T;
-}
+    /// Records the trees before and after [`Compiler::pass2`] in
+    /// [`Compiler::compile`], see [`Compiler::dot`].
+    pub fn with_dot(mut self, dot: bool) -> Self {
+        self.dot = dot;
+        self
+    }
 
-impl Nom<String> for TokenStream {
-    fn nom(&mut self) -> String {
-        self.next().unwrap()
+    pub fn arithmetic(&self) -> ArithmeticMode {
+        self.mode
     }
-}
 
-struct Parser {
-    tokens: TokenStream,
-    args: HashMap<String, usize>,
-}
+    pub fn domain(&self) -> Domain {
+        self.domain
+    }
 
-impl Parser {
-    fn new(tokens: TokenStream) -> Self {
-        Self {
-            tokens,
-            args: HashMap::new(),
-        }
+    pub fn target(&self) -> Target {
+        self.target
     }
 
-    // Grammar
-    // -------
-    // function   ::= '[' arg-list ']' expression
-    //
-    // arg-list   ::= /* nothing */
-    //              | variable arg-list
-    //
-    // expression ::= term
-    //              | expression '+' term
-    //              | expression '-' term
-    //
-    // term       ::= factor
-    //              | term '*' factor
-    //              | term '/' factor
-    //
-    // factor     ::= number
-    //              | variable
-    //              | '(' expression ')'
-    fn parse(&mut self) -> Ast {
-        self.args();
-        self.expression()
-    }
-
-    fn expression(&mut self) -> Ast {
-        let mut lhs = self.term();
-
-        while let Some(token) = self.tokens.peek() {
-            match token.as_str() {
-                "+" | "-" => {
-                    let op = self.tokens.nom();
-                    let rhs = self.term();
-                    lhs = Ast::BinOp(op, Box::new(lhs), Box::new(rhs));
-                }
-                _ => break,
-            }
+    /// Returns a [`Vm`] using the same [`ArithmeticMode`] and [`Domain`]
+    /// as the compiler.
+    pub fn vm(&self) -> Vm {
+        Vm::new()
+            .with_arithmetic(self.mode)
+            .with_domain(self.domain)
+    }
+
+    /// Returns a [`StackVm`] using the same [`ArithmeticMode`] and
+    /// [`Domain`] as the compiler.
+    pub fn stack_vm(&self) -> StackVm {
+        StackVm::new()
+            .with_arithmetic(self.mode)
+            .with_domain(self.domain)
+    }
+
+    /// Runs all three passes on the given program.
+    pub fn compile(&mut self, program: &str) -> Result<Vec<String>, CompileError> {
+        self.passes = None;
+        let parsed = self.pass1(program)?;
+        let ast = self.pass2(&parsed)?;
+        if self.dot {
+            self.passes = Some(self.passes_to_dot(&parsed, &ast));
         }
+        let ast = self.simplify(&ast);
+        let ast = if self.cse { self.cse(&ast) } else { ast };
+        Ok(self.pass3(&ast))
+    }
 
-        lhs
+    /// Returns the trees before and after [`Compiler::pass2`] of the
+    /// last program compiled with [`Compiler::compile`] as a Graphviz
+    /// digraph, if enabled with [`Compiler::with_dot`].
+    /// See [`Compiler::passes_to_dot`].
+    pub fn dot(&self) -> Option<&str> {
+        self.passes.as_deref()
     }
 
-    fn term(&mut self) -> Ast {
-        let mut lhs = self.factor();
+    /// Renders the trees before and after [`Compiler::pass2`] side by side
+    /// as a Graphviz digraph, with the argument names of the last program
+    /// parsed by [`Compiler::pass1`]. Subtrees of the first tree that are
+    /// folded into a constant are filled gray.
+    pub fn passes_to_dot(&self, pass1: &Ast, pass2: &Ast) -> String {
+        dot::passes(pass1, pass2, &self.args, self.mode, self.domain)
+    }
 
-        while let Some(token) = self.tokens.peek() {
-            match token.as_str() {
-                "*" | "/" => {
-                    let op = self.tokens.nom();
-                    let rhs = self.factor();
-                    lhs = Ast::BinOp(op, Box::new(lhs), Box::new(rhs));
-                }
-                _ => break,
-            }
+    /// Compiles a program of several functions, see [`Compiler::pass1_program`],
+    /// running all passes on every function and linking the result.
+    pub fn compile_program(&mut self, program: &str) -> Result<Vec<String>, CompileError> {
+        let mut program = self.pass1_program(program)?;
+
+        for function in &mut program.functions {
+            let ast = self.pass2(&function.body)?;
+            let ast = self.simplify(&ast);
+            function.body = if self.cse { self.cse(&ast) } else { ast };
         }
 
-        lhs
+        Ok(match self.target {
+            Target::Register => listing(&self.link(&program)),
+            Target::Stack => listing(&self.link_stack(&program)),
+        })
     }
 
-    fn factor(&mut self) -> Ast {
-        let mut bytes = self.tokens.peek().unwrap().bytes();
+    /// Compiles the program into a function evaluating it for the given
+    /// arguments, for programs evaluated many times.
+    ///
+    /// The program is folded and simplified like by [`Compiler::compile`]
+    /// and turned into nested closures, which is much faster than executing
+    /// its instructions on a [`Vm`]. Arithmetic follows the [`ArithmeticMode`]
+    /// of the compiler. Closures compute on integers only, any other
+    /// [`Domain`] is a [`CompileError::UnsupportedDomain`].
+    pub fn compile_to_fn(
+        &mut self,
+        program: &str,
+    ) -> Result<impl Fn(&[i64]) -> Result<i64, EvalError> + Send + Sync, CompileError> {
+        self.integers_only()?;
+        let ast = self.pass1(program)?;
+        let ast = self.pass2(&ast)?;
+        let ast = self.simplify(&ast);
+        let ast = if self.cse { self.cse(&ast) } else { ast };
+        Ok(closure::compile(&ast, self.mode))
+    }
 
-        match bytes.next().unwrap() {
-            // number
-            b'0'..=b'9' => Ast::UnOp("imm".to_string(), self.tokens.nom().parse().unwrap()),
-            // expression
-            b'(' => {
-                self.tokens.nom(); // opening paren
-                let e = self.expression();
-                self.tokens.nom(); // closing paren
-                e
-            }
-            // variable
-            _ => {
-                let var = self.tokens.nom();
-                let idx = self.args.get(&var).unwrap();
-                Ast::UnOp("arg".to_string(), *idx)
-            }
-        }
+    /// Parses the program into an [`Ast`].
+    pub fn pass1(&mut self, program: &str) -> Result<Ast, CompileError> {
+        let tokens = tokenize(program);
+        let eof = program.trim_end().len();
+        let mut parser = Parser::new(TokenStream::new(tokens, eof)).with_domain(self.domain);
+        let ast = parser.parse()?;
+        self.args = parser.arg_names();
+        Ok(ast)
     }
 
-    fn args(&mut self) {
-        let mut pos = 0;
+    /// Returns the argument names of the last program parsed by [`Compiler::pass1`].
+    pub fn arg_names(&self) -> &[String] {
+        &self.args
+    }
 
-        loop {
-            let next = self.tokens.nom();
+    /// Prints the [`Ast`] as source text with the argument names of the
+    /// last program parsed by [`Compiler::pass1`], e.g. to show the result
+    /// of folding and simplification. See [`Ast::to_source`].
+    pub fn to_source(&self, ast: &Ast) -> String {
+        ast.to_source(&self.args)
+    }
 
-            match next.as_str() {
-                "[" => continue,
-                "]" => break,
-                _ => {
-                    self.args.insert(next, pos);
-                    pos += 1;
-                }
-            }
-        }
+    /// Parses a program of one or more functions separated by `;`,
+    /// each introduced by `def` and a name, e.g.
+    ///
+    /// ```text
+    /// def f [ a b ] a * b; def g [ x ] f(x, 2) * x
+    /// ```
+    ///
+    /// Functions may call each other in any order. The last function
+    /// is the entry point.
+    pub fn pass1_program(&mut self, program: &str) -> Result<Program, CompileError> {
+        let tokens = tokenize(program);
+        let eof = program.trim_end().len();
+        Parser::new(TokenStream::new(tokens, eof))
+            .with_domain(self.domain)
+            .parse_program()
     }
-}
 
-struct Compiler;
+    /// Applies constant folding to the [`Ast`].
+    ///
+    /// In [`ArithmeticMode::Checked`], a constant integer subexpression
+    /// that overflows or divides by zero is reported as an error, unless
+    /// it is in a branch of a conditional that may not be taken.
+    ///
+    /// Outside of [`Domain::Integer`], a constant that is not an integer
+    /// is folded into the quotient of two immediates, e.g. `3 / 2`.
+    pub fn pass2(&mut self, ast: &Ast) -> Result<Ast, CompileError> {
+        ast.fold(self.mode, self.domain)
+    }
 
-impl Compiler {
-    fn new() -> Compiler {
-        Compiler {}
+    /// Applies algebraic simplifications to the [`Ast`], such as
+    /// removing identities (`x * 1`) and, in [`ArithmeticMode::Wrapping`]
+    /// on integers, combining constants across chains of `+` and `*`
+    /// (`2 * x * 3`).
+    pub fn simplify(&mut self, ast: &Ast) -> Ast {
+        simplify::simplify(ast, self.mode, self.domain)
     }
 
-    fn tokenize(&self, program: &str) -> Vec<String> {
-        let mut tokens: Vec<String> = vec![];
-        let mut iter = program.chars().peekable();
+    /// Binds subexpressions that occur more than once to locals,
+    /// so that each of them is evaluated only once.
+    pub fn cse(&mut self, ast: &Ast) -> Ast {
+        cse::eliminate(ast)
+    }
 
-        while let Some(&c) = iter.peek() {
-            match c {
-                'a'..='z' | 'A'..='Z' => {
-                    let mut tmp = String::new();
-                    while iter.peek().is_some() && iter.peek().unwrap().is_alphabetic() {
-                        tmp.push(iter.next().unwrap());
-                    }
-                    tokens.push(tmp);
-                }
-                '0'..='9' => {
-                    let mut tmp = String::new();
-                    while iter.peek().is_some() && iter.peek().unwrap().is_numeric() {
-                        tmp.push(iter.next().unwrap());
-                    }
-                    tokens.push(tmp);
-                }
-                ' ' => {
-                    iter.next();
-                }
-                _ => {
-                    tokens.push(iter.next().unwrap().to_string());
-                }
-            }
+    /// Generates assembly for the [`Ast`] on the [`Target`] of the
+    /// compiler, one instruction per entry.
+    pub fn pass3(&mut self, ast: &Ast) -> Vec<String> {
+        match self.target {
+            Target::Register => listing(&self.pass3_typed(ast)),
+            Target::Stack => listing(&self.pass3_stack(ast)),
         }
-
-        tokens
     }
 
-    fn compile(&mut self, program: &str) -> Vec<String> {
-        let ast = self.pass1(program);
-        let ast = self.pass2(&ast);
-        self.pass3(&ast)
+    /// Generates [`Instruction`]s for the [`Ast`].
+    pub fn pass3_typed(&mut self, ast: &Ast) -> Vec<Instruction> {
+        codegen::generate(ast)
     }
 
-    fn pass1(&mut self, program: &str) -> Ast {
-        let tokens = self.tokenize(program);
-        let iter = tokens.into_iter().peekable();
-        Parser::new(iter).parse()
+    /// Generates [`StackInstruction`]s for the [`Ast`].
+    pub fn pass3_stack(&mut self, ast: &Ast) -> Vec<StackInstruction> {
+        stack::generate(ast)
     }
 
-    fn pass2(&mut self, ast: &Ast) -> Ast {
-        ast.fold()
+    /// Generates a WebAssembly module in the text format for the [`Ast`].
+    ///
+    /// The module exports a function `main` taking the arguments of the
+    /// last program parsed by [`Compiler::pass1`] as `i64` params and
+    /// returning the result. Arithmetic follows the [`ArithmeticMode`]
+    /// of the compiler, errors trap. WebAssembly computes on integers
+    /// only, any other [`Domain`] is a [`CompileError::UnsupportedDomain`].
+    pub fn pass3_wat(&mut self, ast: &Ast) -> Result<String, CompileError> {
+        self.integers_only()?;
+        Ok(wat::module(&self.single("main", ast), self.mode))
     }
 
-    fn pass3(&mut self, ast: &Ast) -> Vec<String> {
-        let mut asm = vec![];
-        ast.transform(&mut asm);
-        asm
+    /// Generates a WebAssembly module in the text format for the program,
+    /// see [`Compiler::pass3_wat`]. Only the entry point is exported,
+    /// under its name.
+    pub fn link_wat(&mut self, program: &Program) -> Result<String, CompileError> {
+        self.integers_only()?;
+        Ok(wat::module(program, self.mode))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Generates x86-64 assembly for the [`Ast`] in the syntax of the
+    /// GNU assembler.
+    ///
+    /// The assembly defines a global function `name` following the
+    /// System V calling convention, which takes the arguments of the last
+    /// program parsed by [`Compiler::pass1`] as `int64_t` and returns the
+    /// result. Arithmetic follows the [`ArithmeticMode`] of the compiler,
+    /// errors trap with `SIGFPE` or `SIGILL`. The assembly computes on
+    /// integers only, any other [`Domain`] is a
+    /// [`CompileError::UnsupportedDomain`].
+    pub fn pass3_x86(&mut self, ast: &Ast, name: &str) -> Result<String, CompileError> {
+        self.integers_only()?;
+        Ok(x86::assembly(&self.single(name, ast), self.mode))
+    }
 
-    // Helper functions for building ASTs in tests
-    impl Ast {
-        fn imm(n: usize) -> Self {
-            Self::UnOp("imm".to_string(), n)
-        }
+    /// Generates x86-64 assembly for the program, see
+    /// [`Compiler::pass3_x86`]. Only the entry point is global,
+    /// under its name.
+    pub fn link_x86(&mut self, program: &Program) -> Result<String, CompileError> {
+        self.integers_only()?;
+        Ok(x86::assembly(program, self.mode))
+    }
 
-        fn arg(idx: usize) -> Self {
-            Self::UnOp("arg".to_string(), idx)
-        }
-        fn add(lhs: Self, rhs: Self) -> Self {
-            Self::BinOp("+".to_string(), Box::new(lhs), Box::new(rhs))
-        }
+    /// Generates [`Instruction`]s for every function of the program,
+    /// starting with the entry point, and resolves calls between them.
+    pub fn link(&mut self, program: &Program) -> Vec<Instruction> {
+        codegen::link(program)
+    }
 
-        fn sub(lhs: Self, rhs: Self) -> Self {
-            Self::BinOp("-".to_string(), Box::new(lhs), Box::new(rhs))
-        }
+    /// Generates [`StackInstruction`]s for every function of the program,
+    /// see [`Compiler::link`].
+    pub fn link_stack(&mut self, program: &Program) -> Vec<StackInstruction> {
+        stack::link(program)
+    }
 
-        fn mul(lhs: Self, rhs: Self) -> Self {
-            Self::BinOp("*".to_string(), Box::new(lhs), Box::new(rhs))
+    // Fails for backends that compute on integers only, unless the
+    // programs do.
+    fn integers_only(&self) -> Result<(), CompileError> {
+        if self.domain != Domain::Integer {
+            return Err(CompileError::UnsupportedDomain {
+                domain: self.domain,
+            });
         }
+        Ok(())
+    }
 
-        fn div(lhs: Self, rhs: Self) -> Self {
-            Self::BinOp("/".to_string(), Box::new(lhs), Box::new(rhs))
+    // Wraps the AST into a program of a single function, taking the
+    // arguments of the last program parsed.
+    fn single(&self, name: &str, ast: &Ast) -> Program {
+        let arity = self.args.len().max(codegen::arity(ast));
+        Program {
+            functions: vec![Function {
+                name: name.to_string(),
+                args: (0..arity)
+                    .map(|n| self.args.get(n).cloned().unwrap_or_default())
+                    .collect(),
+                body: ast.clone(),
+            }],
         }
     }
+}
+
+fn listing<I: ToString>(asm: &[I]) -> Vec<String> {
+    asm.iter().map(I::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MODES;
 
     #[test]
     fn test_pass1_1() {
         let input = "[ first second ] (first + second) / 2";
 
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
+        let ast = c.pass1(input).unwrap();
 
         assert_eq!(
             ast,
@@ -326,7 +441,7 @@ mod tests {
         let input = "[ x y z ] ( 2*3*x + 5*y - 3*z ) / (1 + 3 + 2*2)";
 
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
+        let ast = c.pass1(input).unwrap();
 
         assert_eq!(
             ast,
@@ -351,8 +466,8 @@ mod tests {
         let input = "[ x y z ] ( 2*3*x + 5*y - 3*z ) / (1 + 3 + 2*2)";
 
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
-        let ast = c.pass2(&ast);
+        let ast = c.pass1(input).unwrap();
+        let ast = c.pass2(&ast).unwrap();
 
         assert_eq!(
             ast,
@@ -369,12 +484,693 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_negation() {
+        let mut c = Compiler::new();
+
+        assert_eq!(
+            c.pass1("[ x ] -x * -2").unwrap(),
+            Ast::mul(Ast::sub(Ast::imm(0), Ast::arg(0)), Ast::imm(-2))
+        );
+        assert_eq!(
+            c.pass1("[ ] -9223372036854775808").unwrap(),
+            Ast::imm(i64::MIN)
+        );
+
+        let ast = c.pass1("[ x ] 1 - 2 - -(x + 3)").unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        assert_eq!(
+            ast,
+            Ast::sub(
+                Ast::imm(-1),
+                Ast::sub(Ast::imm(0), Ast::add(Ast::arg(0), Ast::imm(3)))
+            )
+        );
+        assert_eq!(Vm::new().run(&c.pass3_typed(&ast), &[4]), Ok(6));
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut c = Compiler::new();
+        let mut eval = |program: &str, args: &[i64]| {
+            let ast = c.pass1(program).unwrap();
+            Vm::new().run(&c.pass3_typed(&ast), args)
+        };
+
+        // `^` is right-associative and binds tighter than `-` and `*`
+        assert_eq!(eval("[ ] 2 ^ 3 ^ 2", &[]), Ok(512));
+        assert_eq!(eval("[ ] -2 ^ 2", &[]), Ok(-4));
+        assert_eq!(eval("[ ] 2 ^ -1", &[]), Ok(0));
+        assert_eq!(eval("[ x ] 2 * x ^ 2", &[3]), Ok(18));
+        // `%` has the precedence of `*` and is left-associative
+        assert_eq!(eval("[ x ] x % 7 % 4", &[20]), Ok(2));
+        assert_eq!(eval("[ x ] x * 3 % 7", &[4]), Ok(5));
+        assert_eq!(eval("[ x ] -x % 3", &[7]), Ok(-1));
+        // comparisons bind loosest and evaluate to 0 or 1
+        assert_eq!(eval("[ x y ] x + 1 < y * 2", &[3, 2]), Ok(0));
+        assert_eq!(eval("[ x y ] x + 1 <= y * 2", &[3, 2]), Ok(1));
+        assert_eq!(eval("[ x y ] (x == y) + (x != y)", &[1, 2]), Ok(1));
+        assert_eq!(eval("[ x y ] (x > y) * 10 + (x >= y)", &[2, 2]), Ok(1));
+
+        assert_eq!(
+            c.pass1("[ x ] x ^ 2 ^ 3").unwrap(),
+            Ast::bin_op(
+                BinOp::Pow,
+                Ast::arg(0),
+                Ast::bin_op(BinOp::Pow, Ast::imm(2), Ast::imm(3))
+            )
+        );
+        let ast = c.pass1("[ ] (1 < 2) + 7 % 3 + 3 ^ 2").unwrap();
+        assert_eq!(c.pass2(&ast), Ok(Ast::imm(11)));
+        assert_eq!(
+            c.pass1("[ a b c ] a < b < c"),
+            Err(CompileError::ChainedComparison {
+                span: Span::new(16, 17)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ a b c ] (a < b) < c").unwrap(),
+            Ast::bin_op(
+                BinOp::Lt,
+                Ast::bin_op(BinOp::Lt, Ast::arg(0), Ast::arg(1)),
+                Ast::arg(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let mut c = Compiler::new();
+
+        let ast = c.pass1("[ x y ] if x > y then x else y").unwrap();
+        assert_eq!(
+            ast,
+            Ast::if_else(
+                Ast::bin_op(BinOp::Gt, Ast::arg(0), Ast::arg(1)),
+                Ast::arg(0),
+                Ast::arg(1)
+            )
+        );
+        let asm = c.pass3_typed(&ast);
+        assert_eq!(Vm::new().run(&asm, &[4, 2]), Ok(4));
+        assert_eq!(Vm::new().run(&asm, &[2, 4]), Ok(4));
+
+        // piecewise, with nested conditionals in both positions
+        let ast = c
+            .pass1("[ x ] 2 * (if if x < 0 then 1 else x > 9 then 0 else x)")
+            .unwrap();
+        let asm = c.pass3_typed(&ast);
+        assert_eq!(Vm::new().run(&asm, &[-3]), Ok(0));
+        assert_eq!(Vm::new().run(&asm, &[5]), Ok(10));
+        assert_eq!(Vm::new().run(&asm, &[12]), Ok(0));
+
+        // constant conditions pick a branch, even in checked mode
+        let mut checked = Compiler::new().with_arithmetic(ArithmeticMode::Checked);
+        let ast = checked
+            .pass1("[ x ] if 2 < 1 then x / 0 else x + 1")
+            .unwrap();
+        assert_eq!(checked.pass2(&ast), Ok(Ast::add(Ast::arg(0), Ast::imm(1))));
+
+        assert_eq!(
+            c.pass1("[ x ] if x then 1"),
+            Err(CompileError::UnexpectedEof {
+                expected: "`else`",
+                span: Span::new(17, 17)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ if ] 1"),
+            Err(CompileError::UnexpectedToken {
+                found: "if".to_string(),
+                expected: "an argument name or `]`",
+                span: Span::new(2, 4)
+            })
+        );
+    }
+
+    #[test]
+    fn test_let_bindings() {
+        let mut c = Compiler::new();
+
+        let ast = c
+            .pass1("[ x y ] let s = x + y in let d = x - y in s * d + s")
+            .unwrap();
+        assert_eq!(
+            ast,
+            Ast::let_in(
+                Ast::add(Ast::arg(0), Ast::arg(1)),
+                Ast::let_in(
+                    Ast::sub(Ast::arg(0), Ast::arg(1)),
+                    Ast::add(Ast::mul(Ast::local(0), Ast::local(1)), Ast::local(0))
+                )
+            )
+        );
+        let asm = c.pass3_typed(&ast);
+        assert_eq!(asm.iter().filter(|ins| **ins == Instruction::Pu).count(), 2);
+        assert_eq!(Vm::new().run(&asm, &[5, 3]), Ok(24));
+
+        // a binding in the bound value does not outlive it
+        let ast = c
+            .pass1("[ x ] let a = (let b = x * 2 in b + b) in a * (let c = a in c)")
+            .unwrap();
+        assert_eq!(Vm::new().run(&c.pass3_typed(&ast), &[3]), Ok(144));
+
+        assert_eq!(
+            c.pass1("[ x ] (let a = 1 in a) + a"),
+            Err(CompileError::UndeclaredVariable {
+                name: "a".to_string(),
+                span: Span::new(25, 26)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] let a = a in 1"),
+            Err(CompileError::UndeclaredVariable {
+                name: "a".to_string(),
+                span: Span::new(14, 15)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] let x = 1 in x"),
+            Err(CompileError::ShadowedBinding {
+                name: "x".to_string(),
+                span: Span::new(10, 11),
+                shadowed: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ ] let a = 1 in let a = 2 in a"),
+            Err(CompileError::ShadowedBinding {
+                name: "a".to_string(),
+                span: Span::new(21, 22),
+                shadowed: Span::new(8, 9)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ ] let in = 1 in 2"),
+            Err(CompileError::UnexpectedToken {
+                found: "in".to_string(),
+                expected: "a variable name",
+                span: Span::new(8, 10)
+            })
+        );
+    }
+
+    #[test]
+    fn test_programs() {
+        let mut c = Compiler::new();
+        let run = |asm: Vec<String>, args: &[i64]| {
+            let asm = assemble(&asm.join("\n")).unwrap();
+            Vm::new().run(&asm, args)
+        };
+
+        let program = c
+            .pass1_program("def f [ a b ] a * b; def g [ x ] f(x, 2) * x")
+            .unwrap();
+        assert_eq!(
+            program,
+            Program {
+                functions: vec![
+                    Function {
+                        name: "f".to_string(),
+                        args: vec!["a".to_string(), "b".to_string()],
+                        body: Ast::mul(Ast::arg(0), Ast::arg(1)),
+                    },
+                    Function {
+                        name: "g".to_string(),
+                        args: vec!["x".to_string()],
+                        body: Ast::mul(Ast::call(0, vec![Ast::arg(0), Ast::imm(2)]), Ast::arg(0)),
+                    },
+                ]
+            }
+        );
+        assert_eq!(program.entry(), Some(1));
+        assert_eq!(program.functions[0].arity(), 2);
+        assert_eq!(
+            program.to_source(),
+            "def f [ a b ] a * b;\ndef g [ x ] f(x, 2) * x"
+        );
+        assert_eq!(c.pass1_program(&program.to_source()), Ok(program.clone()));
+        assert_eq!(program.function("f"), Some(0));
+        assert_eq!(Vm::new().run(&c.link(&program), &[3]), Ok(18));
+
+        // recursion and calls of functions defined later
+        let asm = c
+            .compile_program(
+                "def fact [ n ] if n < 2 then one() else n * fact(n - 1);
+                 def one [ ] 1;
+                 def main [ n ] fact(n) + one()",
+            )
+            .unwrap();
+        assert_eq!(run(asm.clone(), &[5]), Ok(121));
+        assert_eq!(run(asm, &[10]), Ok(3628801));
+
+        // locals live in the frame of the callee
+        let asm = c
+            .compile_program(
+                "def sq [ a ] let s = a * a in s;
+                 def main [ x y ] let t = x + y in sq(t) - sq(let u = t - 1 in u) + t",
+            )
+            .unwrap();
+        assert_eq!(run(asm, &[2, 3]), Ok(25 - 16 + 5));
+
+        // a program without functions has no entry point
+        let empty = Program { functions: vec![] };
+        assert_eq!(empty.entry(), None);
+        assert_eq!(c.link(&empty), vec![]);
+        assert_eq!(c.link_stack(&empty), vec![]);
+        assert!(!c.link_wat(&empty).unwrap().contains("export"));
+        assert!(!c.link_x86(&empty).unwrap().contains(".globl"));
+    }
+
+    #[test]
+    fn test_program_errors() {
+        let mut c = Compiler::new();
+
+        assert_eq!(
+            c.pass1_program("def f [ x ] g(x)"),
+            Err(CompileError::UndefinedFunction {
+                name: "g".to_string(),
+                span: Span::new(12, 13)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] f(x)"),
+            Err(CompileError::UndefinedFunction {
+                name: "f".to_string(),
+                span: Span::new(6, 7)
+            })
+        );
+        assert_eq!(
+            c.pass1_program("def f [ a b ] a; def g [ x ] f(x) + 1"),
+            Err(CompileError::ArityMismatch {
+                name: "f".to_string(),
+                expected: 2,
+                found: 1,
+                span: Span::new(29, 33)
+            })
+        );
+        assert_eq!(
+            c.pass1_program("def f [ ] 1; def f [ x ] x"),
+            Err(CompileError::DuplicateFunction {
+                name: "f".to_string(),
+                span: Span::new(17, 18),
+                first: Span::new(4, 5)
+            })
+        );
+        assert_eq!(
+            c.pass1_program("def f [ x ] x def g [ ] 1"),
+            Err(CompileError::UnexpectedToken {
+                found: "def".to_string(),
+                expected: "an operator, `;` or end of input",
+                span: Span::new(14, 17)
+            })
+        );
+        assert_eq!(
+            c.pass1_program("def f [ x ] f(x,)"),
+            Err(CompileError::UnexpectedToken {
+                found: ")".to_string(),
+                expected: "a number, a variable, `-` or `(`",
+                span: Span::new(16, 17)
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_source() {
+        let mut c = Compiler::new();
+
+        let ast = c
+            .pass1("[ width height ] let area = width * height in -(area - 2*3) / (2 - 1)")
+            .unwrap();
+        assert_eq!(c.arg_names(), ["width", "height"]);
+        assert_eq!(
+            c.to_source(&ast),
+            "[ width height ] let a = width * height in -(a - 2 * 3) / (2 - 1)"
+        );
+
+        let ast = c.pass2(&ast).unwrap();
+        let ast = c.simplify(&ast);
+        assert_eq!(
+            c.to_source(&ast),
+            "[ width height ] let a = width * height in -(a - 6)"
+        );
+        assert_eq!(c.pass1(&c.to_source(&ast)), Ok(ast.clone()));
+
+        assert_eq!(ast.to_string(), "[ a b ] let c = a * b in -(c - 6)");
+    }
+
+    #[test]
+    fn test_json() {
+        let mut c = Compiler::new();
+
+        // [ x y z ] ( 2*3*x + 5*y - 3*z ) / (1 + 3 + 2*2), as given by the kata
+        let json = r#"{"op":"/","a":{"op":"-","a":{"op":"+","a":{"op":"*","a":{"op":"*","a":{"op":"imm","n":2},"b":{"op":"imm","n":3}},"b":{"op":"arg","n":0}},"b":{"op":"*","a":{"op":"imm","n":5},"b":{"op":"arg","n":1}}},"b":{"op":"*","a":{"op":"imm","n":3},"b":{"op":"arg","n":2}}},"b":{"op":"+","a":{"op":"+","a":{"op":"imm","n":1},"b":{"op":"imm","n":3}},"b":{"op":"*","a":{"op":"imm","n":2},"b":{"op":"imm","n":2}}}}"#;
+
+        let ast = c
+            .pass1("[ x y z ] ( 2*3*x + 5*y - 3*z ) / (1 + 3 + 2*2)")
+            .unwrap();
+        assert_eq!(ast.to_json(), json);
+
+        let ast = Ast::from_json(json).unwrap();
+        let folded = c.pass2(&ast).unwrap();
+        assert_eq!(c.vm().run(&c.pass3_typed(&folded), &[4, 0, 0]), Ok(3));
+        assert_eq!(c.vm().run(&c.pass3_typed(&ast), &[4, 8, 0]), Ok(8));
+    }
+
+    #[test]
+    fn test_pass3_wat() {
+        let mut c = Compiler::new().with_arithmetic(ArithmeticMode::Saturating);
+        let ast = c.pass1("[ x y z ] x + 2 * 5").unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        let wat = c.pass3_wat(&ast).unwrap();
+
+        assert!(wat.starts_with(
+            "(module\n  (func $fn_main (export \"main\") \
+             (param $arg0 i64) (param $arg1 i64) (param $arg2 i64) (result i64)\n"
+        ));
+        assert!(wat.contains("    local.get $arg0\n    i64.const 10\n    call $op_add\n"));
+        assert!(wat.contains("  (func $op_add (param $a i64) (param $b i64) (result i64)\n"));
+        assert!(wat.ends_with("  )\n)\n"));
+    }
+
+    #[test]
+    fn test_stack_target() {
+        let mut c = Compiler::new().with_target(Target::Stack);
+        assert_eq!(
+            c.compile("[ x y ] x * 2 - y").unwrap(),
+            vec!["PUSHA 0", "PUSHI 2", "MUL", "PUSHA 1", "SUB"]
+        );
+        assert_eq!(
+            c.compile_program("def sq [ a ] a * a; def main [ x ] sq(x) + 1")
+                .unwrap(),
+            vec![
+                "PUSHA 0", "CALL 5 1", "PUSHI 1", "ADD", "RET", "PUSHA 0", "PUSHA 0", "MUL", "RET"
+            ]
+        );
+
+        // The stack machine needs no swaps or pushes between operands.
+        let mut registers = Compiler::new();
+        for program in [
+            "[ x y ] (x + y) / 2",
+            "[ a b c ] a * a + b * b - (c + 1) * (c - 1)",
+            "[ x ] let y = x * x in if y > 10 then y - x else 0 - y",
+        ] {
+            let ast = c.pass1(program).unwrap();
+            let asm = c.pass3_stack(&ast);
+            assert!(asm.len() < registers.pass3_typed(&ast).len(), "{program}");
+
+            for args in [[3, 4, 5], [-7, 2, 0]] {
+                let args = &args[..c.arg_names().len()];
+                assert_eq!(
+                    c.stack_vm().run(&asm, args),
+                    registers.vm().run(&registers.pass3_typed(&ast), args),
+                    "{program}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_to_fn() {
+        let mut c = Compiler::new().with_cse(true);
+        let f = c
+            .compile_to_fn("[ x y ] (x + y) * (x + y) / 2 - y ^ 2")
+            .unwrap();
+
+        assert_eq!(f(&[3, 1]), Ok(7));
+        assert_eq!(f(&[-4, 10]), Ok(-82));
+        assert_eq!(
+            f(&[3]),
+            Err(EvalError::ArgumentOutOfRange { index: 1, len: 1 })
+        );
+
+        let mut c = Compiler::new().with_arithmetic(ArithmeticMode::Checked);
+        let f = c.compile_to_fn("[ x ] x * x / (x - 3)").unwrap();
+        assert_eq!(f(&[5]), Ok(12));
+        assert_eq!(f(&[3]), Err(EvalError::DivisionByZero));
+        assert_eq!(f(&[i64::MAX]), Err(EvalError::Overflow));
+
+        assert!(matches!(
+            c.compile_to_fn("[ x ] x + y"),
+            Err(CompileError::UndeclaredVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut c = Compiler::new().with_arithmetic(ArithmeticMode::Checked);
+        let ast = c.pass1("[ x y ] (x + y) * (x + y) / 2 - y ^ 2").unwrap();
+
+        assert_eq!(ast.eval(&[3, 1]), Ok(7));
+        assert_eq!(ast.eval(&[-4, 10]), Ok(-82));
+
+        let ast = c.pass1("[ x ] x * x / (x - 3)").unwrap();
+        let simplified = c.simplify(&ast);
+        let asm = assemble(&c.pass3(&simplified).join("\n")).unwrap();
+
+        for x in [5, 3, i64::MAX] {
+            let expected = ast.eval_with(&[x], ArithmeticMode::Checked);
+            assert_eq!(
+                simplified.eval_with(&[x], ArithmeticMode::Checked),
+                expected
+            );
+            assert_eq!(c.vm().run(&asm, &[x]).ok(), expected.ok());
+        }
+        assert_eq!(
+            ast.eval_with(&[3], ArithmeticMode::Checked),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_domains() {
+        let mut c = Compiler::new();
+        assert_eq!(
+            c.pass1("[ x ] 1.5 * x"),
+            Err(CompileError::InvalidNumber {
+                literal: "1.5".to_string(),
+                span: Span::new(6, 9)
+            })
+        );
+
+        let mut c = Compiler::new().with_domain(Domain::Float);
+        let asm = assemble(&c.compile("[ x ] -1.5 * x / 4").unwrap().join("\n")).unwrap();
+        assert_eq!(
+            c.vm().run_numbers(&asm, &[Number::from(2)]),
+            Ok(Number::Float(-0.75))
+        );
+
+        // Decimal literals are quotients of integers.
+        let ast = c.pass1("[ x ] 1.5 * x").unwrap();
+        assert_eq!(c.to_source(&ast), "[ x ] 15 / 10 * x");
+        let folded = c.pass2(&ast).unwrap();
+        assert_eq!(c.to_source(&folded), "[ x ] 3 / 2 * x");
+
+        // Folding gives the same result as running the unfolded program.
+        let ast = c.pass1("[ ] 0.1 + 0.2").unwrap();
+        let folded = c.pass2(&ast).unwrap();
+        assert_eq!(
+            folded.eval_numbers(&[], c.arithmetic(), c.domain()),
+            Ok(Number::Float(0.1 + 0.2))
+        );
+        assert_eq!(
+            c.vm().run_numbers(&c.pass3_typed(&ast), &[]),
+            Ok(Number::Float(0.1 + 0.2))
+        );
+
+        // Constants beyond the range of `i64`, at 2^63 and -2^63 or more.
+        for program in [
+            "[ x ] x + 10000000000 * 10000000000",
+            "[ x ] x - 10000000000 * 10000000000",
+            "[ x ] x + 4294967296 * 2147483648",
+            "[ x ] x + -4294967296 * 2147483648 * 2",
+        ] {
+            let ast = c.pass1(program).unwrap();
+            let folded = c.pass2(&ast).unwrap();
+            let simplified = c.simplify(&ast);
+            let expected = ast.eval_numbers(&[Number::from(1)], c.arithmetic(), c.domain());
+
+            for ast in [&folded, &simplified] {
+                assert_eq!(
+                    ast.eval_numbers(&[Number::from(1)], c.arithmetic(), c.domain()),
+                    expected,
+                    "{program}"
+                );
+            }
+        }
+
+        let mut c = Compiler::new().with_domain(Domain::Rational);
+        let ast = c.pass1("[ x ] x / 3 + 0.5").unwrap();
+        let third = Number::from(Rational::new(1, 3).unwrap());
+        assert_eq!(
+            ast.eval_numbers(&[third], c.arithmetic(), c.domain()),
+            Ok(Number::from(Rational::new(11, 18).unwrap()))
+        );
+
+        let ast = c.pass1("[ x ] x * (1 / 3 + 1 / 6)").unwrap();
+        let folded = c.pass2(&ast).unwrap();
+        assert_eq!(c.to_source(&folded), "[ x ] x * (1 / 2)");
+        let ast = c.pass1("[ x ] x ^ 0.5").unwrap();
+        assert_eq!(
+            c.stack_vm()
+                .run_numbers(&c.pass3_stack(&ast), &[Number::from(4)]),
+            Err(VmError::NonIntegerExponent { pc: 4 })
+        );
+        assert_eq!(
+            c.vm()
+                .run_numbers(&c.pass3_typed(&ast), &[Number::Float(f64::NAN)]),
+            Err(VmError::ArgumentNotInDomain { index: 0 })
+        );
+
+        // Backends computing on integers only reject other domains.
+        let unsupported = Some(CompileError::UnsupportedDomain {
+            domain: Domain::Rational,
+        });
+        assert_eq!(c.compile_to_fn("[ x ] x").err(), unsupported);
+        let ast = c.pass1("[ x ] 1.5 * x").unwrap();
+        assert_eq!(c.pass3_wat(&ast).err(), unsupported);
+        assert_eq!(c.pass3_x86(&ast, "f").err(), unsupported);
+        let program = c.pass1_program("def f [ x ] 1.5 * x").unwrap();
+        assert_eq!(c.link_wat(&program).err(), unsupported);
+        assert_eq!(c.link_x86(&program).err(), unsupported);
+    }
+
+    #[test]
+    fn test_pass3_x86() {
+        let mut c = Compiler::new();
+        let ast = c.pass1("[ x y z ] x + 2 * 5").unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        let asm = c.pass3_x86(&ast, "f").unwrap();
+
+        assert!(asm.starts_with("\t.text\n\t.globl\tf\n\t.type\tf, @function\nf:\n"));
+        assert!(asm.contains("\tmovq\t%rdx, -24(%rbp)\n"));
+        assert!(asm.contains("\tmovq\t$10, %rdi\n\tmovq\t%rsi, %rax\n\taddq\t%rdi, %rax\n"));
+        assert!(asm.contains("\tleave\n\tret\n"));
+    }
+
+    #[test]
+    fn test_dot() {
+        let mut c = Compiler::new();
+        c.compile("[ x ] x + 2 * 3").unwrap();
+        assert_eq!(c.dot(), None);
+
+        let mut c = Compiler::new().with_dot(true);
+        c.compile("[ x ] x + 2 * 3").unwrap();
+        let dot = c.dot().unwrap();
+
+        assert!(dot.starts_with("digraph passes {"));
+        assert!(dot.contains("subgraph cluster_pass1"));
+        assert!(dot.contains("subgraph cluster_pass2"));
+        assert!(dot.contains("a1 [label=\"x\"];"));
+        assert!(dot.contains("a2 [label=\"*\", style=filled, fillcolor=lightgray];"));
+        assert!(dot.contains("b2 [label=\"6\"];"));
+
+        assert!(c.compile("[ x ] y").is_err());
+        assert_eq!(c.dot(), None);
+
+        assert_eq!(
+            Ast::sub(Ast::arg(0), Ast::imm(1)).to_dot(),
+            "digraph ast {\n  node [shape=box];\n  n0 [label=\"-\"];\n  n1 [label=\"arg 0\"];\n  n0 -> n1;\n  n2 [label=\"1\"];\n  n0 -> n2;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_modes() {
+        let cases = [
+            (i64::MAX, "+", 1),
+            (i64::MIN, "-", 1),
+            (i64::MAX, "*", 2),
+            (i64::MIN, "/", -1),
+            (-7, "/", 2),
+            (5, "/", 0),
+        ];
+
+        for mode in MODES {
+            let mut c = Compiler::new().with_arithmetic(mode);
+
+            for (a, op, b) in cases {
+                let folded = c
+                    .pass1(&format!("[ ] {a} {op} {b}"))
+                    .and_then(|ast| c.pass2(&ast));
+                let ast = c.pass1(&format!("[ x y ] x {op} y")).unwrap();
+                let executed = c.vm().run(&c.pass3_typed(&ast), &[a, b]);
+
+                match (folded, executed) {
+                    (Ok(Ast::Imm(n)), Ok(m)) => assert_eq!(n, m),
+                    (Ok(ast), Err(_)) => assert!(ast.as_bin_op().is_some()),
+                    (Err(CompileError::ConstantArithmetic { .. }), Err(_)) => {
+                        assert_eq!(mode, ArithmeticMode::Checked)
+                    }
+                    other => panic!("{mode:?}: {a} {op} {b} gives {other:?}"),
+                }
+            }
+        }
+
+        let input = "[ x ] x + 5 / (3 - 3)";
+        let mut c = Compiler::new().with_arithmetic(ArithmeticMode::Checked);
+        let err = c.compile(input).unwrap_err();
+        assert_eq!(
+            err.render(input),
+            "error: division by zero in constant expression `5 / 0`\n"
+        );
+
+        // A branch that may not be taken fails at runtime, like it
+        // does when evaluated.
+        let ast = c.pass1("[ x ] if x then 1 else 1 / 0").unwrap();
+        let folded = c.pass2(&ast).unwrap();
+        assert_eq!(folded, ast);
+        let asm = c.pass3_typed(&folded);
+        assert_eq!(c.vm().run(&asm, &[1]), Ok(1));
+        assert_eq!(
+            c.vm().run(&asm, &[0]),
+            Err(VmError::DivisionByZero { pc: 7 })
+        );
+        assert_eq!(ast.eval(&[0]), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_simplify() {
+        let input = "[ x y ] 2 * x * 3 + y * 1 + 0 * y";
+        let mut c = Compiler::new();
+        let ast = c.pass1(input).unwrap();
+        let folded = c.pass2(&ast).unwrap();
+        let simplified = c.simplify(&folded);
+
+        assert_eq!(
+            simplified,
+            Ast::add(Ast::mul(Ast::arg(0), Ast::imm(6)), Ast::arg(1))
+        );
+
+        let vm = Vm::new();
+        let before = c.pass3_typed(&folded);
+        let after = c.pass3_typed(&simplified);
+        assert!(after.len() < before.len());
+        assert_eq!(vm.run(&before, &[4, 2]), vm.run(&after, &[4, 2]));
+    }
+
+    #[test]
+    fn test_cse() {
+        let input = "[ x y z ] (x + y) * (x + y) + (x + y) / z";
+        let asm = Compiler::new().compile(input).unwrap();
+        let asm_cse = Compiler::new().with_cse(true).compile(input).unwrap();
+
+        assert!(asm_cse.len() < asm.len());
+
+        let vm = Vm::new();
+        let asm = assemble(&asm.join("\n")).unwrap();
+        let asm_cse = assemble(&asm_cse.join("\n")).unwrap();
+
+        for args in [[1, 2, 3], [4, -6, 2], [0, 0, 1], [7, 7, 0]] {
+            assert_eq!(vm.run(&asm, &args).ok(), vm.run(&asm_cse, &args).ok());
+        }
+    }
+
     #[test]
     fn test_pass3_1() {
         let input = "[ x ] x + 2*5";
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
-        let ast = c.pass2(&ast);
+        let ast = c.pass1(input).unwrap();
+        let ast = c.pass2(&ast).unwrap();
         let asm = c.pass3(&ast);
 
         assert_eq!(
@@ -386,67 +1182,129 @@ mod tests {
                 "AD".to_string()
             ]
         );
+        assert_eq!(
+            c.pass3_typed(&ast),
+            vec![
+                Instruction::Im(10),
+                Instruction::Sw,
+                Instruction::Ar(0),
+                Instruction::Ad
+            ]
+        );
     }
 
     #[test]
     fn test_pass3_2() {
         let input = "[ x y ] 6 * x + 5 * y";
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
-        let ast = c.pass2(&ast);
-        let asm = c.pass3(&ast);
+        let ast = c.pass1(input).unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        let asm = c.pass3_typed(&ast);
 
-        assert_eq!(simulate(asm, vec![4, 2]), 34);
+        assert_eq!(Vm::new().run(&asm, &[4, 2]), Ok(34));
     }
 
     #[test]
     fn test_pass3_3() {
         let input = "[ x ] 6 * ( x + 42 )";
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
-        let ast = c.pass2(&ast);
-        let asm = c.pass3(&ast);
+        let ast = c.pass1(input).unwrap();
+        let ast = c.pass2(&ast).unwrap();
+        let asm = c.pass3_typed(&ast);
 
-        assert_eq!(simulate(asm, vec![8]), 300);
+        assert_eq!(Vm::new().run(&asm, &[8]), Ok(300));
     }
 
     #[test]
     fn test_pass3_4() {
         let input = "[ x y z ] ( 2*3*x + 5*y - 3*z ) / (1 + 3 + 2*2)";
         let mut c = Compiler::new();
-        let ast = c.pass1(input);
-        let asm = c.pass3(&ast);
+        let ast = c.pass1(input).unwrap();
+        let asm = c.pass3_typed(&ast);
 
-        assert_eq!(simulate(asm, vec![4, 6, 2]), 48 / 8);
+        assert_eq!(Vm::new().run(&asm, &[4, 6, 2]), Ok(48 / 8));
     }
 
     #[test]
-    fn simulator() {
-        assert_eq!(simulate(vec!["IM 7".to_string()], vec![3]), 7);
-        assert_eq!(simulate(vec!["AR 1".to_string()], vec![1, 2, 3]), 2);
+    fn test_ast_accessors() {
+        let ast = Ast::sub(Ast::arg(0), Ast::imm(2));
+
+        let (op, lhs, rhs) = ast.as_bin_op().unwrap();
+        assert_eq!(op, BinOp::Sub);
+        assert_eq!(lhs.as_arg(), Some(0));
+        assert_eq!(rhs.as_imm(), Some(2));
+        assert_eq!(ast.as_imm(), None);
+        assert!(!ast.is_leaf() && lhs.is_leaf());
     }
 
-    fn simulate(assembly: Vec<String>, argv: Vec<i32>) -> i32 {
-        let mut r = (0, 0);
-        let mut stack: Vec<i32> = vec![];
+    #[test]
+    fn test_pass1_errors() {
+        let mut c = Compiler::new();
 
-        for ins in assembly {
-            let mut ws = ins.split_whitespace();
-            match ws.next() {
-                Some("IM") => r.0 = i32::from_str_radix(ws.next().unwrap(), 10).unwrap(),
-                Some("AR") => {
-                    r.0 = argv[i32::from_str_radix(ws.next().unwrap(), 10).unwrap() as usize]
-                }
-                Some("SW") => r = (r.1, r.0),
-                Some("PU") => stack.push(r.0),
-                Some("PO") => r.0 = stack.pop().unwrap(),
-                Some("AD") => r.0 += r.1,
-                Some("SU") => r.0 -= r.1,
-                Some("MU") => r.0 *= r.1,
-                Some("DI") => r.0 /= r.1,
-                _ => panic!("Invalid instruction encountered"),
-            }
-        }
-        r.0
+        assert_eq!(
+            c.pass1("[ x ] x + y"),
+            Err(CompileError::UndeclaredVariable {
+                name: "y".to_string(),
+                span: Span::new(10, 11)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x y x ] x"),
+            Err(CompileError::DuplicateArgument {
+                name: "x".to_string(),
+                span: Span::new(6, 7),
+                first: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] (x + 1"),
+            Err(CompileError::UnexpectedEof {
+                expected: "`)`",
+                span: Span::new(12, 12)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] x + 1)"),
+            Err(CompileError::UnexpectedToken {
+                found: ")".to_string(),
+                expected: "an operator or end of input",
+                span: Span::new(11, 12)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x  y + 1"),
+            Err(CompileError::UnexpectedToken {
+                found: "+".to_string(),
+                expected: "an argument name or `]`",
+                span: Span::new(7, 8)
+            })
+        );
+        assert_eq!(
+            c.pass1(""),
+            Err(CompileError::UnexpectedEof {
+                expected: "`[`",
+                span: Span::new(0, 0)
+            })
+        );
+        assert_eq!(
+            c.pass1("[ x ] 99999999999999999999999"),
+            Err(CompileError::InvalidNumber {
+                literal: "99999999999999999999999".to_string(),
+                span: Span::new(6, 29)
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_error() {
+        let input = "[ x ]\n(x + 42) *\n  (x - foo)";
+
+        let mut c = Compiler::new();
+        let err = c.pass1(input).unwrap_err();
+
+        assert_eq!(
+            err.render(input),
+            "error: undeclared variable `foo`\n --> 3:8\n  |\n3 |   (x - foo)\n  |        ^^^\n"
+        );
     }
 }
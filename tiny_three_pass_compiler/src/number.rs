@@ -0,0 +1,649 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+};
+
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    error::ArithmeticError,
+};
+
+/// The numbers a program computes with.
+///
+/// The domain is chosen on the [`Compiler`](crate::Compiler) and used
+/// both for constant folding and by the [`Vm`](crate::Vm) and the
+/// [`StackVm`](crate::StackVm) when running on [`Number`]s, so that
+/// folding never changes the result of a program.
+///
+/// Decimal literals such as `1.5` are only accepted outside of the
+/// integer domain. They are read as the quotient of two integers,
+/// `15 / 10`, which is exact for rationals and rounded like the
+/// literal for floats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Domain {
+    /// 64-bit integers, where division truncates towards zero.
+    /// Overflow is handled according to the [`ArithmeticMode`].
+    #[default]
+    Integer,
+    /// 64-bit IEEE 754 floating-point numbers.
+    ///
+    /// Arithmetic is rounded to the nearest representable number.
+    /// In checked mode, a result that is not finite is an overflow,
+    /// in saturating mode infinities are clamped to the largest finite
+    /// numbers. The remainder has the sign of the dividend.
+    Float,
+    /// Exact fractions of 64-bit integers.
+    ///
+    /// A result whose numerator or denominator does not fit into
+    /// an `i64` is an overflow in every mode. Exponents must be
+    /// integers. The remainder of `a % b` is `a - b * n` for the
+    /// quotient `a / b` truncated to the integer `n`.
+    Rational,
+}
+
+impl Domain {
+    /// Returns the integer as a number of the domain, rounded
+    /// to the nearest float in the floating-point domain.
+    pub fn number(self, n: i64) -> Number {
+        match self {
+            Self::Integer => Number::Integer(n),
+            Self::Float => Number::Float(n as f64),
+            Self::Rational => Number::Rational(Rational::from(n)),
+        }
+    }
+
+    /// Converts a number into the domain, if it can be represented
+    /// exactly or, for floats, rounded.
+    pub fn convert(self, n: Number) -> Option<Number> {
+        match (self, n) {
+            (_, Number::Integer(n)) => Some(self.number(n)),
+            (Self::Integer, Number::Float(f)) => integral(f).map(Number::Integer),
+            (Self::Integer, Number::Rational(r)) => (r.den == 1).then_some(Number::Integer(r.num)),
+            (Self::Float, Number::Float(_)) => Some(n),
+            (Self::Float, Number::Rational(r)) => Some(Number::Float(r.num as f64 / r.den as f64)),
+            (Self::Rational, Number::Float(f)) => {
+                let (mantissa, exp) = split(f)?;
+                if exp >= 0 {
+                    let num = i64::try_from((mantissa as i128) << exp.min(64)).ok()?;
+                    Some(Number::Rational(Rational::from(num)))
+                } else {
+                    let den = 1_i64
+                        .checked_shl(exp.unsigned_abs())
+                        .filter(|&den| den > 0)?;
+                    Rational::new(mantissa, den).map(Number::Rational)
+                }
+            }
+            (Self::Rational, Number::Rational(_)) => Some(n),
+        }
+    }
+
+    /// Applies the operator to two numbers of the domain.
+    pub(crate) fn apply(
+        self,
+        mode: ArithmeticMode,
+        op: BinOp,
+        lhs: Number,
+        rhs: Number,
+    ) -> Result<Number, ArithmeticError> {
+        match (lhs, rhs) {
+            (Number::Integer(lhs), Number::Integer(rhs)) => {
+                mode.apply(op, lhs, rhs).map(Number::Integer)
+            }
+            (Number::Float(lhs), Number::Float(rhs)) => {
+                apply_float(mode, op, lhs, rhs).map(Number::Float)
+            }
+            (Number::Rational(lhs), Number::Rational(rhs)) => {
+                apply_rational(op, lhs, rhs).map(Number::Rational)
+            }
+            _ => unreachable!("operands {lhs} and {rhs} of different domains"),
+        }
+    }
+
+    // Returns the value of a constant of the domain, i.e., an
+    // immediate or, outside of the integer domain, the quotient
+    // or product of two immediates as written by `Number::to_ast`.
+    pub(crate) fn constant(self, ast: &Ast) -> Option<Number> {
+        match ast {
+            &Ast::Imm(n) => Some(self.number(n)),
+            Ast::BinOp(op @ (BinOp::Div | BinOp::Mul), lhs, rhs) if self != Self::Integer => {
+                let (lhs, rhs) = (lhs.as_imm()?, rhs.as_imm()?);
+                self.apply(
+                    ArithmeticMode::Checked,
+                    *op,
+                    self.number(lhs),
+                    self.number(rhs),
+                )
+                .ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Rational => "rational",
+        })
+    }
+}
+
+/// A value of one of the [`Domain`]s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+    Rational(Rational),
+}
+
+impl Number {
+    pub fn domain(&self) -> Domain {
+        match self {
+            Self::Integer(_) => Domain::Integer,
+            Self::Float(_) => Domain::Float,
+            Self::Rational(_) => Domain::Rational,
+        }
+    }
+
+    /// Returns true for zero, which is false as a condition.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Integer(n) => *n == 0,
+            Self::Float(f) => *f == 0.0,
+            Self::Rational(r) => r.num == 0,
+        }
+    }
+
+    // Returns a constant that evaluates exactly to the number in
+    // its domain: an immediate for integers, otherwise the quotient
+    // of two immediates. Floats are written as a multiple or a
+    // fraction of a power of two, which they are, unless it does
+    // not fit into an `i64`.
+    pub(crate) fn to_ast(self) -> Option<Ast> {
+        match self {
+            Self::Integer(n) => Some(Ast::Imm(n)),
+            Self::Rational(r) if r.den == 1 => Some(Ast::Imm(r.num)),
+            Self::Rational(r) => Some(Ast::div(Ast::Imm(r.num), Ast::Imm(r.den))),
+            Self::Float(f) => match integral(f) {
+                // Negative zero is the quotient of 0 and -1.
+                Some(0) if f.is_sign_negative() => Some(Ast::div(Ast::Imm(0), Ast::Imm(-1))),
+                Some(n) => Some(Ast::Imm(n)),
+                // Beyond the range of `i64`, the exponent is positive.
+                None => {
+                    let (mantissa, exp) = split(f)?;
+                    // Moves factors of two into the mantissa, so that
+                    // the scale fits, e.g. for 2^63.
+                    let (mantissa, exp) = match exp - 62 {
+                        excess @ 1..=62 => (mantissa.checked_mul(1 << excess)?, 62),
+                        1.. => return None,
+                        _ => (mantissa, exp),
+                    };
+                    let scale = 1_i64
+                        .checked_shl(exp.unsigned_abs())
+                        .filter(|&scale| scale > 0)?;
+                    let (mantissa, scale) = (Ast::Imm(mantissa), Ast::Imm(scale));
+                    Some(if exp < 0 {
+                        Ast::div(mantissa, scale)
+                    } else {
+                        Ast::mul(mantissa, scale)
+                    })
+                }
+            },
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(n: i64) -> Self {
+        Self::Integer(n)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(f: f64) -> Self {
+        Self::Float(f)
+    }
+}
+
+impl From<Rational> for Number {
+    fn from(r: Rational) -> Self {
+        Self::Rational(r)
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::Float(x) => write!(f, "{x}"),
+            Self::Rational(r) => write!(f, "{r}"),
+        }
+    }
+}
+
+/// A fraction in lowest terms with a positive denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    /// Returns `num / den` in lowest terms, or `None` if `den` is zero
+    /// or the reduced fraction does not fit, as for `i64::MIN / -1`.
+    pub fn new(num: i64, den: i64) -> Option<Self> {
+        Self::reduce(num.into(), den.into())
+    }
+
+    pub fn numer(&self) -> i64 {
+        self.num
+    }
+
+    pub fn denom(&self) -> i64 {
+        self.den
+    }
+
+    fn reduce(num: i128, den: i128) -> Option<Self> {
+        if den == 0 {
+            return None;
+        }
+        let gcd = gcd(num.unsigned_abs(), den.unsigned_abs()) as i128;
+        let (num, den) = (num / gcd, den / gcd);
+        let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+
+        Some(Self {
+            num: num.try_into().ok()?,
+            den: den.try_into().ok()?,
+        })
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(n: i64) -> Self {
+        Self { num: n, den: 1 }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+// Returns the float as an `i64` if it is an integer in range.
+fn integral(f: f64) -> Option<i64> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+
+    (f.fract() == 0.0 && (-LIMIT..LIMIT).contains(&f)).then_some(f as i64)
+}
+
+// Splits a finite float into `mantissa * 2^exp`, with an odd
+// mantissa unless the float is zero.
+fn split(f: f64) -> Option<(i64, i32)> {
+    if !f.is_finite() {
+        return None;
+    }
+    if f == 0.0 {
+        return Some((0, 0));
+    }
+
+    let bits = f.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = (bits & ((1 << 52) - 1)) as i64;
+    let (mantissa, exp) = match biased {
+        0 => (fraction, -1074),
+        _ => (fraction | (1 << 52), biased - 1075),
+    };
+
+    let zeros = mantissa.trailing_zeros();
+    let mantissa = mantissa >> zeros;
+    let exp = exp + zeros as i32;
+
+    Some((if f < 0.0 { -mantissa } else { mantissa }, exp))
+}
+
+fn apply_float(
+    mode: ArithmeticMode,
+    op: BinOp,
+    lhs: f64,
+    rhs: f64,
+) -> Result<f64, ArithmeticError> {
+    let result = match op {
+        BinOp::Div | BinOp::Rem if rhs == 0.0 => return Err(ArithmeticError::DivisionByZero),
+        BinOp::Pow if lhs == 0.0 && rhs < 0.0 => return Err(ArithmeticError::DivisionByZero),
+        BinOp::Add => lhs + rhs,
+        BinOp::Sub => lhs - rhs,
+        BinOp::Mul => lhs * rhs,
+        BinOp::Div => lhs / rhs,
+        BinOp::Rem => lhs % rhs,
+        BinOp::Pow => lhs.powf(rhs),
+        BinOp::Eq => return Ok((lhs == rhs) as i64 as f64),
+        BinOp::Ne => return Ok((lhs != rhs) as i64 as f64),
+        BinOp::Lt => return Ok((lhs < rhs) as i64 as f64),
+        BinOp::Le => return Ok((lhs <= rhs) as i64 as f64),
+        BinOp::Gt => return Ok((lhs > rhs) as i64 as f64),
+        BinOp::Ge => return Ok((lhs >= rhs) as i64 as f64),
+    };
+
+    if result.is_finite() || !lhs.is_finite() || !rhs.is_finite() {
+        return Ok(result);
+    }
+
+    match mode {
+        ArithmeticMode::Checked => Err(ArithmeticError::Overflow),
+        ArithmeticMode::Wrapping => Ok(result),
+        ArithmeticMode::Saturating => Ok(result.clamp(f64::MIN, f64::MAX)),
+    }
+}
+
+fn apply_rational(op: BinOp, lhs: Rational, rhs: Rational) -> Result<Rational, ArithmeticError> {
+    let (a, b) = (lhs.num as i128, lhs.den as i128);
+    let (c, d) = (rhs.num as i128, rhs.den as i128);
+    let truth = |holds: bool| Ok(Rational::from(holds as i64));
+
+    let result = match op {
+        BinOp::Div | BinOp::Rem if c == 0 => return Err(ArithmeticError::DivisionByZero),
+        BinOp::Add => (a * d)
+            .checked_add(c * b)
+            .and_then(|num| Rational::reduce(num, b * d)),
+        BinOp::Sub => (a * d)
+            .checked_sub(c * b)
+            .and_then(|num| Rational::reduce(num, b * d)),
+        BinOp::Mul => Rational::reduce(a * c, b * d),
+        BinOp::Div => Rational::reduce(a * d, b * c),
+        // Both operands over the common denominator `b * d`,
+        // where the remainder of the numerators is exact.
+        BinOp::Rem => Rational::reduce((a * d) % (c * b), b * d),
+        BinOp::Pow => return pow_rational(lhs, rhs),
+        BinOp::Eq => return truth(lhs == rhs),
+        BinOp::Ne => return truth(lhs != rhs),
+        BinOp::Lt => return truth(lhs < rhs),
+        BinOp::Le => return truth(lhs <= rhs),
+        BinOp::Gt => return truth(lhs > rhs),
+        BinOp::Ge => return truth(lhs >= rhs),
+    };
+
+    result.ok_or(ArithmeticError::Overflow)
+}
+
+fn pow_rational(base: Rational, exp: Rational) -> Result<Rational, ArithmeticError> {
+    if exp.den != 1 {
+        return Err(ArithmeticError::NonIntegerExponent);
+    }
+    if base.num == 0 && exp.num < 0 {
+        return Err(ArithmeticError::DivisionByZero);
+    }
+
+    // A negative exponent raises the reciprocal, which
+    // is in lowest terms as well.
+    let base = if exp.num < 0 {
+        Rational::reduce(base.den.into(), base.num.into()).ok_or(ArithmeticError::Overflow)?
+    } else {
+        base
+    };
+    let exp = exp.num.unsigned_abs();
+
+    // Exponents beyond `u32::MAX` overflow for any base
+    // other than 0, 1 and -1, as do their powers.
+    match (base.num, base.den) {
+        (0, _) => Ok(Rational::from((exp == 0) as i64)),
+        (1, 1) => Ok(base),
+        (-1, 1) => Ok(Rational::from(if exp.is_multiple_of(2) { 1 } else { -1 })),
+        (num, den) => {
+            let exp = u32::try_from(exp).map_err(|_| ArithmeticError::Overflow)?;
+            let num = num.checked_pow(exp).ok_or(ArithmeticError::Overflow)?;
+            let den = den.checked_pow(exp).ok_or(ArithmeticError::Overflow)?;
+            Ok(Rational { num, den })
+        }
+    }
+}
+
+// A value that the machines and the evaluator compute with:
+// an `i64`, which is always an integer, or a `Number` of the
+// domain the program is run in.
+pub(crate) trait Value: Copy {
+    fn number(domain: Domain, n: i64) -> Self;
+
+    fn is_zero(self) -> bool;
+
+    fn apply(
+        domain: Domain,
+        mode: ArithmeticMode,
+        op: BinOp,
+        lhs: Self,
+        rhs: Self,
+    ) -> Result<Self, ArithmeticError>;
+}
+
+impl Value for i64 {
+    #[inline(always)]
+    fn number(_: Domain, n: i64) -> Self {
+        n
+    }
+
+    #[inline(always)]
+    fn is_zero(self) -> bool {
+        self == 0
+    }
+
+    #[inline(always)]
+    fn apply(
+        _: Domain,
+        mode: ArithmeticMode,
+        op: BinOp,
+        lhs: Self,
+        rhs: Self,
+    ) -> Result<Self, ArithmeticError> {
+        mode.apply(op, lhs, rhs)
+    }
+}
+
+impl Value for Number {
+    fn number(domain: Domain, n: i64) -> Self {
+        domain.number(n)
+    }
+
+    fn is_zero(self) -> bool {
+        Number::is_zero(&self)
+    }
+
+    fn apply(
+        domain: Domain,
+        mode: ArithmeticMode,
+        op: BinOp,
+        lhs: Self,
+        rhs: Self,
+    ) -> Result<Self, ArithmeticError> {
+        domain.apply(mode, op, lhs, rhs)
+    }
+}
+
+// Converts the arguments of a program into its domain, returning
+// the index of the first one that cannot be represented.
+pub(crate) fn convert_args(domain: Domain, args: &[Number]) -> Result<Vec<Number>, usize> {
+    args.iter()
+        .enumerate()
+        .map(|(index, &n)| domain.convert(n).ok_or(index))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MODES;
+
+    fn ratio(num: i64, den: i64) -> Number {
+        Number::Rational(Rational::new(num, den).unwrap())
+    }
+
+    #[test]
+    fn rationals() {
+        let r = Rational::new(6, -4).unwrap();
+        assert_eq!((r.numer(), r.denom()), (-3, 2));
+        assert_eq!(r.to_string(), "-3/2");
+        assert_eq!(Rational::new(1, 0), None);
+        assert_eq!(Rational::new(i64::MIN, -1), None);
+        assert!(Rational::new(1, 3) < Rational::new(1, 2));
+
+        let apply = |op, lhs, rhs| Domain::Rational.apply(ArithmeticMode::Wrapping, op, lhs, rhs);
+
+        assert_eq!(apply(BinOp::Add, ratio(1, 2), ratio(1, 3)), Ok(ratio(5, 6)));
+        assert_eq!(apply(BinOp::Sub, ratio(1, 2), ratio(1, 2)), Ok(ratio(0, 1)));
+        assert_eq!(apply(BinOp::Mul, ratio(2, 3), ratio(3, 4)), Ok(ratio(1, 2)));
+        assert_eq!(
+            apply(BinOp::Div, ratio(1, 2), ratio(-1, 4)),
+            Ok(ratio(-2, 1))
+        );
+        assert_eq!(apply(BinOp::Rem, ratio(7, 2), ratio(1, 1)), Ok(ratio(1, 2)));
+        assert_eq!(
+            apply(BinOp::Rem, ratio(-7, 2), ratio(2, 3)),
+            Ok(ratio(-1, 6))
+        );
+        assert_eq!(
+            apply(BinOp::Pow, ratio(2, 3), ratio(-3, 1)),
+            Ok(ratio(27, 8))
+        );
+        assert_eq!(
+            apply(BinOp::Pow, ratio(-1, 1), ratio(i64::MAX, 1)),
+            Ok(ratio(-1, 1))
+        );
+        assert_eq!(apply(BinOp::Lt, ratio(1, 3), ratio(1, 2)), Ok(ratio(1, 1)));
+        assert_eq!(
+            apply(BinOp::Pow, ratio(4, 1), ratio(1, 2)),
+            Err(ArithmeticError::NonIntegerExponent)
+        );
+        assert_eq!(
+            apply(BinOp::Pow, ratio(2, 1), ratio(64, 1)),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(
+            apply(BinOp::Mul, ratio(i64::MAX, 1), ratio(2, 1)),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(
+            apply(BinOp::Div, ratio(1, 1), ratio(0, 1)),
+            Err(ArithmeticError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn floats() {
+        let apply = |mode, op, lhs, rhs| {
+            Domain::Float.apply(mode, op, Number::Float(lhs), Number::Float(rhs))
+        };
+
+        for mode in MODES {
+            assert_eq!(apply(mode, BinOp::Div, 1.0, 4.0), Ok(Number::Float(0.25)));
+            assert_eq!(apply(mode, BinOp::Rem, -7.5, 2.0), Ok(Number::Float(-1.5)));
+            assert_eq!(apply(mode, BinOp::Pow, 4.0, 0.5), Ok(Number::Float(2.0)));
+            assert_eq!(apply(mode, BinOp::Ge, 0.5, 0.25), Ok(Number::Float(1.0)));
+            assert_eq!(
+                apply(mode, BinOp::Div, 1.0, 0.0),
+                Err(ArithmeticError::DivisionByZero)
+            );
+        }
+
+        assert_eq!(
+            apply(ArithmeticMode::Checked, BinOp::Mul, f64::MAX, 2.0),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(
+            apply(ArithmeticMode::Wrapping, BinOp::Mul, f64::MAX, 2.0),
+            Ok(Number::Float(f64::INFINITY))
+        );
+        assert_eq!(
+            apply(ArithmeticMode::Saturating, BinOp::Mul, -f64::MAX, 2.0),
+            Ok(Number::Float(f64::MIN))
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            Domain::Float.convert(ratio(1, 4)),
+            Some(Number::Float(0.25))
+        );
+        assert_eq!(
+            Domain::Rational.convert(Number::Float(-0.375)),
+            Some(ratio(-3, 8))
+        );
+        assert_eq!(Domain::Rational.convert(Number::Float(1e300)), None);
+        assert_eq!(Domain::Rational.convert(Number::Float(f64::NAN)), None);
+        assert_eq!(
+            Domain::Integer.convert(Number::Float(3.0)),
+            Some(Number::Integer(3))
+        );
+        assert_eq!(Domain::Integer.convert(Number::Float(0.5)), None);
+        assert_eq!(
+            Domain::Integer.convert(ratio(4, 2)),
+            Some(Number::Integer(2))
+        );
+        assert_eq!(Domain::Integer.convert(ratio(1, 2)), None);
+        assert_eq!(
+            Domain::Rational.convert(Number::Integer(7)),
+            Some(ratio(7, 1))
+        );
+    }
+
+    #[test]
+    fn constants() {
+        for (domain, n) in [
+            (Domain::Integer, Number::Integer(-3)),
+            (Domain::Float, Number::Float(0.1)),
+            (Domain::Float, Number::Float(-2.5e10)),
+            (Domain::Float, Number::Float(1.0 / 3.0)),
+            (Domain::Float, Number::Float(-0.0)),
+            (Domain::Float, Number::Float(1e20)),
+            (Domain::Float, Number::Float(-1e20)),
+            (Domain::Float, Number::Float(9_223_372_036_854_775_808.0)),
+            (Domain::Rational, ratio(-22, 7)),
+            (Domain::Rational, ratio(5, 1)),
+        ] {
+            let ast = n.to_ast().unwrap();
+            assert_eq!(domain.constant(&ast), Some(n), "{ast}");
+        }
+
+        assert_eq!(Number::Float(f64::INFINITY).to_ast(), None);
+        assert_eq!(Number::Float(1e-300).to_ast(), None);
+        assert_eq!(Number::Float(1e300).to_ast(), None);
+        assert_eq!(Number::Float(2_f64.powi(125)).to_ast(), None);
+        assert_eq!(
+            Number::Float(-9_223_372_036_854_775_808.0).to_ast(),
+            Some(Ast::imm(i64::MIN))
+        );
+        assert_eq!(
+            Number::Float(0.75).to_ast(),
+            Some(Ast::div(Ast::imm(3), Ast::imm(4)))
+        );
+        assert_eq!(
+            Domain::Integer.constant(&Ast::div(Ast::imm(3), Ast::imm(4))),
+            None
+        );
+    }
+}
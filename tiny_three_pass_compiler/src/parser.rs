@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Ast, BinOp},
+    error::{CompileError, Span},
+    number::Domain,
+    program::{Function, Program},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+    text: String,
+    span: Span,
+}
+
+pub(crate) struct TokenStream {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Offset reported when running out of tokens.
+    eof: usize,
+}
+
+impl TokenStream {
+    pub(crate) fn new(tokens: Vec<Token>, eof: usize) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            eof,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    // Looks ahead n tokens past the next one.
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n)
+    }
+}
+
+trait Nom<T> {
+    fn nom(&mut self, expected: &'static str) -> Result<T, CompileError>;
+}
+
+impl Nom<Token> for TokenStream {
+    fn nom(&mut self, expected: &'static str) -> Result<Token, CompileError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(CompileError::UnexpectedEof {
+                expected,
+                span: Span::new(self.eof, self.eof),
+            })?;
+        self.pos += 1;
+        Ok(token)
+    }
+}
+
+fn unexpected(token: Token, expected: &'static str) -> CompileError {
+    CompileError::UnexpectedToken {
+        found: token.text,
+        expected,
+        span: token.span,
+    }
+}
+
+// Reads an integer literal as an immediate and, outside of the
+// integer domain, a decimal literal with `k` fractional digits
+// as the quotient of its digits and `10^k`, e.g. `1.25` as
+// `125 / 100`.
+fn number(literal: String, span: Span, domain: Domain) -> Result<Ast, CompileError> {
+    let parsed = match literal.split_once('.') {
+        None => literal.parse().ok().map(Ast::Imm),
+        Some(_) if domain == Domain::Integer => None,
+        Some((int, fraction)) => format!("{int}{fraction}")
+            .parse()
+            .ok()
+            .zip(10_i64.checked_pow(fraction.len() as u32))
+            .map(|(digits, scale)| Ast::div(Ast::Imm(digits), Ast::Imm(scale))),
+    };
+
+    parsed.ok_or(CompileError::InvalidNumber { literal, span })
+}
+
+pub(crate) const KEYWORDS: &[&str] = &["if", "then", "else", "let", "in", "def"];
+
+fn is_identifier(token: &Token) -> bool {
+    token.text.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !KEYWORDS.contains(&token.text.as_str())
+}
+
+fn is_number(token: &Token) -> bool {
+    token.text.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn is_symbol(token: Option<&Token>, symbol: &str) -> bool {
+    token.is_some_and(|token| token.text == symbol)
+}
+
+struct Signature {
+    index: usize,
+    arity: usize,
+    span: Span,
+}
+
+pub(crate) struct Parser {
+    tokens: TokenStream,
+    args: HashMap<String, usize>,
+    arg_spans: Vec<Span>,
+    // Names bound by the enclosing `let`s, outermost first, so
+    // that the index of a name is the slot of its local.
+    locals: Vec<(String, Span)>,
+    functions: HashMap<String, Signature>,
+    domain: Domain,
+}
+
+impl Parser {
+    pub(crate) fn new(tokens: TokenStream) -> Self {
+        Self {
+            tokens,
+            args: HashMap::new(),
+            arg_spans: vec![],
+            locals: vec![],
+            functions: HashMap::new(),
+            domain: Domain::default(),
+        }
+    }
+
+    // Sets the domain of number literals, decimal literals are
+    // only valid outside of the integer domain.
+    pub(crate) fn with_domain(mut self, domain: Domain) -> Self {
+        self.domain = domain;
+        self
+    }
+
+    // Grammar
+    // -------
+    // program    ::= definition
+    //              | definition ';' program
+    //
+    // definition ::= 'def' variable function
+    //
+    // function   ::= '[' arg-list ']' block
+    //
+    // arg-list   ::= /* nothing */
+    //              | variable arg-list
+    //
+    // block      ::= comparison
+    //              | 'if' block 'then' block 'else' block
+    //              | 'let' variable '=' block 'in' block
+    //
+    // comparison ::= expression
+    //              | expression cmp-op expression
+    //
+    // cmp-op     ::= '==' | '!=' | '<' | '<=' | '>' | '>='
+    //
+    // expression ::= term
+    //              | expression '+' term
+    //              | expression '-' term
+    //
+    // term       ::= unary
+    //              | term '*' unary
+    //              | term '/' unary
+    //              | term '%' unary
+    //
+    // unary      ::= power
+    //              | '-' unary
+    //
+    // power      ::= factor
+    //              | factor '^' unary
+    //
+    // factor     ::= number
+    //              | variable
+    //              | variable '(' call-args ')'
+    //              | '(' block ')'
+    //
+    // call-args  ::= /* nothing */
+    //              | block-list
+    //
+    // block-list ::= block
+    //              | block ',' block-list
+    //
+    // Comparisons do not associate, so `a < b < c` is an error.
+    // Exponentiation associates to the right and binds tighter
+    // than negation, so `-2 ^ 2` is `-(2 ^ 2)`. Keywords cannot be
+    // used as variables.
+    //
+    // A number is a sequence of digits or, outside of the integer
+    // domain, a decimal such as `1.5`.
+    //
+    // A `let` binding is visible in its body only, and it must not
+    // shadow an argument or another binding in scope.
+    //
+    // A single function is parsed from `function`, without a name,
+    // and cannot call other functions.
+    pub(crate) fn parse(&mut self) -> Result<Ast, CompileError> {
+        self.args()?;
+        let ast = self.block()?;
+
+        match self.tokens.peek() {
+            Some(token) => Err(unexpected(token.clone(), "an operator or end of input")),
+            None => Ok(ast),
+        }
+    }
+
+    pub(crate) fn parse_program(&mut self) -> Result<Program, CompileError> {
+        self.signatures();
+        let mut functions = vec![];
+
+        loop {
+            self.expect("def", "`def`")?;
+            let name = self.tokens.nom("a function name")?;
+            if !is_identifier(&name) {
+                return Err(unexpected(name, "a function name"));
+            }
+
+            self.args.clear();
+            self.arg_spans.clear();
+            self.args()?;
+
+            let first = self.functions[&name.text].span;
+            if first != name.span {
+                return Err(CompileError::DuplicateFunction {
+                    name: name.text,
+                    span: name.span,
+                    first,
+                });
+            }
+
+            functions.push(Function {
+                name: name.text,
+                args: self.arg_names(),
+                body: self.block()?,
+            });
+
+            match self.tokens.peek() {
+                Some(token) if token.text == ";" => self.tokens.nom("`;`")?,
+                Some(token) => {
+                    return Err(unexpected(
+                        token.clone(),
+                        "an operator, `;` or end of input",
+                    ))
+                }
+                None => break,
+            };
+        }
+
+        Ok(Program { functions })
+    }
+
+    // Collects the name and arity of every function before parsing
+    // their bodies, so that calls may refer to functions defined later.
+    // Malformed headers are skipped and reported when parsed.
+    fn signatures(&mut self) {
+        let tokens = &self.tokens.tokens;
+
+        for (i, header) in tokens.windows(3).enumerate() {
+            let [def, name, open] = header else {
+                unreachable!()
+            };
+            if def.text != "def" || !is_identifier(name) || open.text != "[" {
+                continue;
+            }
+
+            let arity = tokens[i + 3..]
+                .iter()
+                .take_while(|token| is_identifier(token))
+                .count();
+            let index = self.functions.len();
+
+            self.functions
+                .entry(name.text.clone())
+                .or_insert(Signature {
+                    index,
+                    arity,
+                    span: name.span,
+                });
+        }
+    }
+
+    fn block(&mut self) -> Result<Ast, CompileError> {
+        if is_symbol(self.tokens.peek(), "let") {
+            return self.binding();
+        }
+        if !is_symbol(self.tokens.peek(), "if") {
+            return self.comparison();
+        }
+        self.tokens.nom("`if`")?;
+
+        let cond = self.block()?;
+        self.expect("then", "`then`")?;
+        let then = self.block()?;
+        self.expect("else", "`else`")?;
+        let otherwise = self.block()?;
+
+        Ok(Ast::if_else(cond, then, otherwise))
+    }
+
+    fn binding(&mut self) -> Result<Ast, CompileError> {
+        self.tokens.nom("`let`")?;
+
+        let name = self.tokens.nom("a variable name")?;
+        if !is_identifier(&name) {
+            return Err(unexpected(name, "a variable name"));
+        }
+        if let Some(shadowed) = self.lookup(&name.text).map(|(_, span)| span) {
+            return Err(CompileError::ShadowedBinding {
+                name: name.text,
+                span: name.span,
+                shadowed,
+            });
+        }
+
+        self.expect("=", "`=`")?;
+        let value = self.block()?;
+        self.expect("in", "`in`")?;
+
+        self.locals.push((name.text, name.span));
+        let body = self.block();
+        self.locals.pop();
+
+        Ok(Ast::let_in(value, body?))
+    }
+
+    // Resolves a name to a local or argument, and the span it was declared at.
+    fn lookup(&self, name: &str) -> Option<(Ast, Span)> {
+        match self.locals.iter().position(|(local, _)| local == name) {
+            Some(slot) => Some((Ast::Local(slot), self.locals[slot].1)),
+            None => self
+                .args
+                .get(name)
+                .map(|&idx| (Ast::Arg(idx), self.arg_spans[idx])),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Ast, CompileError> {
+        let lhs = self.expression()?;
+
+        let op = match self.tokens.peek().and_then(|t| BinOp::from_symbol(&t.text)) {
+            Some(op) if op.is_comparison() => op,
+            _ => return Ok(lhs),
+        };
+        self.tokens.nom("a comparison operator")?;
+        let rhs = self.expression()?;
+
+        match self.tokens.peek() {
+            Some(token) if BinOp::from_symbol(&token.text).is_some_and(|op| op.is_comparison()) => {
+                Err(CompileError::ChainedComparison { span: token.span })
+            }
+            _ => Ok(Ast::bin_op(op, lhs, rhs)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Ast, CompileError> {
+        let mut lhs = self.term()?;
+
+        while let Some(token) = self.tokens.peek() {
+            match BinOp::from_symbol(&token.text) {
+                Some(op @ (BinOp::Add | BinOp::Sub)) => {
+                    self.tokens.nom("`+` or `-`")?;
+                    let rhs = self.term()?;
+                    lhs = Ast::bin_op(op, lhs, rhs);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Ast, CompileError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(token) = self.tokens.peek() {
+            match BinOp::from_symbol(&token.text) {
+                Some(op @ (BinOp::Mul | BinOp::Div | BinOp::Rem)) => {
+                    self.tokens.nom("`*`, `/` or `%`")?;
+                    let rhs = self.unary()?;
+                    lhs = Ast::bin_op(op, lhs, rhs);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Ast, CompileError> {
+        if !is_symbol(self.tokens.peek(), "-") {
+            return self.power();
+        }
+        let minus = self.tokens.nom("`-`")?;
+
+        // A negative literal, unless it is the base of a power.
+        // Parsing the literal with its sign allows `i64::MIN`.
+        let literal =
+            self.tokens.peek().is_some_and(is_number) && !is_symbol(self.tokens.peek_nth(1), "^");
+
+        if literal {
+            let next = self.tokens.nom("a number")?;
+            let literal = format!("-{}", next.text);
+            let span = Span::new(minus.span.start, next.span.end);
+            number(literal, span, self.domain)
+        } else {
+            // negation, lowered to `0 - unary`
+            Ok(Ast::sub(Ast::imm(0), self.unary()?))
+        }
+    }
+
+    fn power(&mut self) -> Result<Ast, CompileError> {
+        let base = self.factor()?;
+
+        if is_symbol(self.tokens.peek(), "^") {
+            self.tokens.nom("`^`")?;
+            let exponent = self.unary()?;
+            Ok(Ast::bin_op(BinOp::Pow, base, exponent))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn factor(&mut self) -> Result<Ast, CompileError> {
+        const EXPECTED: &str = "a number, a variable, `-` or `(`";
+
+        let token = self.tokens.nom(EXPECTED)?;
+
+        match token.text.as_bytes()[0] {
+            // number
+            b'0'..=b'9' => number(token.text, token.span, self.domain),
+            // expression
+            b'(' => {
+                let e = self.block()?;
+                self.expect(")", "`)`")?;
+                Ok(e)
+            }
+            // call
+            _ if is_identifier(&token) && is_symbol(self.tokens.peek(), "(") => self.call(token),
+            // variable
+            _ if is_identifier(&token) => match self.lookup(&token.text) {
+                Some((ast, _)) => Ok(ast),
+                None => Err(CompileError::UndeclaredVariable {
+                    name: token.text,
+                    span: token.span,
+                }),
+            },
+            _ => Err(unexpected(token, EXPECTED)),
+        }
+    }
+
+    fn call(&mut self, name: Token) -> Result<Ast, CompileError> {
+        let (index, arity) = match self.functions.get(&name.text) {
+            Some(signature) => (signature.index, signature.arity),
+            None => {
+                return Err(CompileError::UndefinedFunction {
+                    name: name.text,
+                    span: name.span,
+                })
+            }
+        };
+
+        self.expect("(", "`(`")?;
+        let mut args = vec![];
+
+        let close = loop {
+            if args.is_empty() && is_symbol(self.tokens.peek(), ")") {
+                break self.tokens.nom("`)`")?;
+            }
+            args.push(self.block()?);
+
+            let next = self.tokens.nom("`,` or `)`")?;
+            match next.text.as_str() {
+                "," => {}
+                ")" => break next,
+                _ => return Err(unexpected(next, "`,` or `)`")),
+            }
+        };
+
+        if args.len() != arity {
+            return Err(CompileError::ArityMismatch {
+                name: name.text,
+                expected: arity,
+                found: args.len(),
+                span: Span::new(name.span.start, close.span.end),
+            });
+        }
+
+        Ok(Ast::call(index, args))
+    }
+
+    fn args(&mut self) -> Result<(), CompileError> {
+        self.expect("[", "`[`")?;
+
+        loop {
+            let next = self.tokens.nom("an argument name or `]`")?;
+
+            match next.text.as_str() {
+                "]" => break,
+                _ if is_identifier(&next) => {
+                    if let Some(&idx) = self.args.get(&next.text) {
+                        return Err(CompileError::DuplicateArgument {
+                            name: next.text,
+                            span: next.span,
+                            first: self.arg_spans[idx],
+                        });
+                    }
+                    self.args.insert(next.text, self.arg_spans.len());
+                    self.arg_spans.push(next.span);
+                }
+                _ => return Err(unexpected(next, "an argument name or `]`")),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the names of the arguments of the last parsed function, in order.
+    pub(crate) fn arg_names(&self) -> Vec<String> {
+        let mut names = vec![String::new(); self.arg_spans.len()];
+        for (name, &idx) in &self.args {
+            names[idx] = name.clone();
+        }
+        names
+    }
+
+    fn expect(&mut self, text: &str, expected: &'static str) -> Result<Token, CompileError> {
+        let token = self.tokens.nom(expected)?;
+
+        if token.text == text {
+            Ok(token)
+        } else {
+            Err(unexpected(token, expected))
+        }
+    }
+}
+
+pub(crate) fn tokenize(program: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut iter = program.char_indices().peekable();
+
+    while let Some(&(start, c)) = iter.peek() {
+        let mut tmp = String::new();
+
+        match c {
+            'a'..='z' | 'A'..='Z' => {
+                while let Some((_, c)) = iter.next_if(|(_, c)| c.is_alphabetic()) {
+                    tmp.push(c);
+                }
+            }
+            '0'..='9' => {
+                while let Some((_, c)) = iter.next_if(|(_, c)| c.is_ascii_digit()) {
+                    tmp.push(c);
+                }
+                // A decimal point must be followed by a digit.
+                let mut ahead = iter.clone();
+                if ahead.next().is_some_and(|(_, c)| c == '.')
+                    && ahead.next().is_some_and(|(_, c)| c.is_ascii_digit())
+                {
+                    tmp.push(iter.next().unwrap().1);
+                    while let Some((_, c)) = iter.next_if(|(_, c)| c.is_ascii_digit()) {
+                        tmp.push(c);
+                    }
+                }
+            }
+            _ if c.is_whitespace() => {
+                iter.next();
+                continue;
+            }
+            '<' | '>' | '=' | '!' => {
+                tmp.push(c);
+                iter.next();
+                if let Some((_, c)) = iter.next_if(|(_, c)| *c == '=') {
+                    tmp.push(c);
+                }
+            }
+            _ => {
+                tmp.push(c);
+                iter.next();
+            }
+        }
+
+        let span = Span::new(start, start + tmp.len());
+        tokens.push(Token { text: tmp, span });
+    }
+
+    tokens
+}
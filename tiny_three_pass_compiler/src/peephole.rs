@@ -0,0 +1,355 @@
+use crate::instruction::Instruction::{self, *};
+
+// Registers live at a program point, as a bit set.
+type Live = u8;
+
+const R0: Live = 1;
+const R1: Live = 2;
+
+// A rewrite of a fixed-size window of instructions. The rewrite
+// receives the registers that are live after the window, i.e.,
+// that are read before being overwritten by the rest of the program.
+struct Rule {
+    // Identifies the rule in tests.
+    #[cfg_attr(not(test), allow(dead_code))]
+    name: &'static str,
+    len: usize,
+    rewrite: fn(&[Instruction], Live) -> Option<Vec<Instruction>>,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        // SW; SW => _
+        name: "double swap",
+        len: 2,
+        rewrite: |w, _| matches!(w, [Sw, Sw]).then(Vec::new),
+    },
+    Rule {
+        // PU; PO => _
+        name: "push pop",
+        len: 2,
+        rewrite: |w, _| matches!(w, [Pu, Po]).then(Vec::new),
+    },
+    Rule {
+        // IM n; IM m => IM m
+        //
+        // Only for an immediate: AR and LD may fail out of range,
+        // which dropping them would hide.
+        name: "dead load",
+        len: 2,
+        rewrite: |w, _| (matches!(w[0], Im(_)) && overwrites_r0(&w[1])).then(|| vec![w[1]]),
+    },
+    Rule {
+        // SW; IM m => IM m, if R1 is dead
+        name: "dead swap",
+        len: 2,
+        rewrite: |w, live| {
+            (w[0] == Sw && overwrites_r0(&w[1]) && live & R1 == 0).then(|| vec![w[1]])
+        },
+    },
+    Rule {
+        // SW; AD => AD, SW; LT => GT, if R1 is dead
+        name: "swapped operands",
+        len: 2,
+        rewrite: |w, live| match w[1].bin_op().and_then(|op| op.mirrored()) {
+            Some(op) if w[0] == Sw && live & R1 == 0 => Some(vec![Instruction::from_bin_op(op)]),
+            _ => None,
+        },
+    },
+    Rule {
+        // PU; IM n; SW; PO => SW; IM n; SW
+        name: "spilled load",
+        len: 4,
+        rewrite: |w, _| match w {
+            [Pu, load, Sw, Po] if is_load(load) => Some(vec![Sw, *load, Sw]),
+            _ => None,
+        },
+    },
+    Rule {
+        // ST n; LD n => ST n
+        name: "reload",
+        len: 2,
+        rewrite: |w, _| match w {
+            [St(n), Ld(m)] if n == m => Some(vec![w[0]]),
+            _ => None,
+        },
+    },
+];
+
+// Loads into R0 without reading the stack. LD may read the slot
+// that a preceding PU wrote.
+fn is_load(ins: &Instruction) -> bool {
+    matches!(ins, Im(_) | Ar(_))
+}
+
+// Writes R0 without reading it.
+fn overwrites_r0(ins: &Instruction) -> bool {
+    matches!(ins, Im(_) | Ar(_) | Ld(_) | Po)
+}
+
+// Returns the registers live before each instruction, plus
+// the registers live at the end of the program (only R0).
+//
+// A jump may go backwards, so the analysis is repeated until
+// nothing changes. A jump out of range traps, leaving nothing live.
+// Calls and returns keep both registers, and the code they continue
+// with is not known here, so both registers are live before them.
+fn liveness(program: &[Instruction]) -> Vec<Live> {
+    let mut live = vec![0; program.len() + 1];
+    live[program.len()] = R0;
+
+    loop {
+        let mut changed = false;
+
+        for (pc, ins) in program.iter().enumerate().rev() {
+            let after = live[pc + 1];
+            let at = |target: &usize| live.get(*target).copied().unwrap_or(0);
+
+            let before = match ins {
+                Im(_) | Ar(_) | Ld(_) | Po => after & !R0,
+                Pu | St(_) => after | R0,
+                Sw => ((after & R0) << 1) | ((after & R1) >> 1),
+                Jz(target) => after | at(target) | R0,
+                Jmp(target) => at(target),
+                Call(..) => R0 | R1,
+                Ret => after | R0 | R1,
+                _ => after | R0 | R1,
+            };
+
+            changed |= before != live[pc];
+            live[pc] = before;
+        }
+
+        if !changed {
+            return live;
+        }
+    }
+}
+
+/// Shortens a program by rewriting instruction patterns that
+/// have a cheaper equivalent, until no more rules apply.
+///
+/// Rewrites preserve the result (R0) of the program, but not
+/// necessarily the final value of R1. Jumps and calls are
+/// retargeted to account for removed instructions.
+pub fn peephole(program: &[Instruction]) -> Vec<Instruction> {
+    let mut program = program.to_vec();
+
+    while let Some((pc, rule, replacement)) = next_rewrite(&program) {
+        let end = pc + rule.len;
+        let shrink = rule.len - replacement.len();
+
+        for ins in &mut program {
+            if let Jz(target) | Jmp(target) | Call(target, _) = ins {
+                if *target >= end {
+                    *target -= shrink;
+                }
+            }
+        }
+
+        program.splice(pc..end, replacement);
+    }
+
+    program
+}
+
+// Windows never contain a jump or call, or the target of one past
+// their first instruction, as control flow may enter or leave them there.
+fn next_rewrite(program: &[Instruction]) -> Option<(usize, &'static Rule, Vec<Instruction>)> {
+    let live = liveness(program);
+
+    let mut barrier = vec![false; program.len() + 1];
+    for (pc, ins) in program.iter().enumerate() {
+        if let Some(target) = ins.target() {
+            barrier[pc] = true;
+            if let Some(b) = barrier.get_mut(target) {
+                *b = true;
+            }
+        }
+    }
+
+    (0..program.len())
+        .filter(|&pc| program[pc].target().is_none())
+        .find_map(|pc| {
+            RULES
+                .iter()
+                .filter(|rule| pc + rule.len <= program.len())
+                .filter(|rule| !barrier[pc + 1..pc + rule.len].contains(&true))
+                .find_map(|rule| {
+                    let end = pc + rule.len;
+                    (rule.rewrite)(&program[pc..end], live[end]).map(|r| (pc, rule, r))
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Rng, vm::Vm};
+
+    fn rule(name: &str) -> &'static Rule {
+        RULES.iter().find(|rule| rule.name == name).unwrap()
+    }
+
+    #[test]
+    fn liveness_through_swaps() {
+        // IM 1 is read by AD after being swapped into R1
+        assert_eq!(
+            liveness(&[Im(1), Sw, Im(2), Ad]),
+            vec![0, R0, R1, R0 | R1, R0]
+        );
+    }
+
+    // Rule name, window, registers live after the window, replacement
+    type Case = (
+        &'static str,
+        &'static [Instruction],
+        Live,
+        Option<&'static [Instruction]>,
+    );
+
+    #[test]
+    fn rules() {
+        let cases: &[Case] = &[
+            ("double swap", &[Sw, Sw], R0, Some(&[])),
+            ("push pop", &[Pu, Po], R0, Some(&[])),
+            ("dead load", &[Im(1), Ar(0)], R0, Some(&[Ar(0)])),
+            ("dead load", &[Im(1), Sw], R0, None),
+            ("dead load", &[Ar(9), Im(1)], R0, None),
+            ("dead load", &[Ld(9), Im(1)], R0, None),
+            ("dead swap", &[Sw, Im(2)], R0, Some(&[Im(2)])),
+            ("dead swap", &[Sw, Im(2)], R0 | R1, None),
+            ("swapped operands", &[Sw, Ad], R0, Some(&[Ad])),
+            ("swapped operands", &[Sw, Lt], R0, Some(&[Gt])),
+            ("swapped operands", &[Sw, Su], R0, None),
+            (
+                "spilled load",
+                &[Pu, Ar(1), Sw, Po],
+                R0,
+                Some(&[Sw, Ar(1), Sw]),
+            ),
+            ("spilled load", &[Pu, Ld(0), Sw, Po], R0, None),
+            ("reload", &[St(0), Ld(0)], R0, Some(&[St(0)])),
+            ("reload", &[St(0), Ld(1)], R0, None),
+        ];
+
+        for (name, window, live, expected) in cases {
+            assert_eq!(
+                (rule(name).rewrite)(window, *live).as_deref(),
+                *expected,
+                "{name}: {window:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fixpoint() {
+        // IM n; SW; IM m only needs IM m if R1 is dead
+        assert_eq!(peephole(&[Im(1), Sw, Im(2)]), vec![Im(2)]);
+        assert_eq!(peephole(&[Ar(0), Sw, Sw, Pu, Po]), vec![Ar(0)]);
+        assert_eq!(
+            peephole(&[Im(1), Sw, Im(2), Ad]),
+            vec![Im(1), Sw, Im(2), Ad]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // SW; SW is removed and the jumps past it are retargeted
+        assert_eq!(
+            peephole(&[Ar(0), Jz(5), Sw, Sw, Jmp(6), Im(1)]),
+            vec![Ar(0), Jz(3), Jmp(4), Im(1)]
+        );
+        // SW; SW is not a window if control flow enters in between
+        assert_eq!(
+            peephole(&[Ar(0), Sw, Ar(1), Jz(5), Sw, Sw, Su]),
+            vec![Ar(0), Sw, Ar(1), Jz(5), Sw, Sw, Su]
+        );
+        // R1 is live at the jump target
+        assert_eq!(
+            liveness(&[Im(1), Sw, Ar(0), Jz(5), Im(2), Ad]),
+            vec![0, R0, R1, R0 | R1, R1, R0 | R1, R0]
+        );
+        // the call is retargeted
+        assert_eq!(
+            peephole(&[Ar(0), Sw, Sw, Pu, Call(6, 1), Ret, Ar(0), Ret]),
+            vec![Ar(0), Pu, Call(4, 1), Ret, Ar(0), Ret]
+        );
+        // the callee may read both registers
+        assert_eq!(
+            liveness(&[Im(1), Sw, Call(3, 0), Ret]),
+            vec![R1, R0 | R1, R0 | R1, R0 | R1, R0]
+        );
+        assert_eq!(
+            peephole(&[Im(7), Sw, Im(0), Call(5, 0), Ret, Sw, Ret]),
+            vec![Im(7), Sw, Im(0), Call(5, 0), Ret, Sw, Ret]
+        );
+    }
+
+    // Generates a random program with forward jumps and calls that
+    // never accesses an unset slot of the first frame. Jumps may skip
+    // pushes, so a program may pop off an empty stack.
+    fn random_program(rng: &mut Rng, len: usize) -> Vec<Instruction> {
+        let mut program = vec![Pu, Pu];
+        let mut depth = 2;
+
+        for _ in 0..len {
+            let ins = match rng.below(16) {
+                0 => Im(rng.range(-3, 3)),
+                1 => Ar(rng.below(3)),
+                2 => Sw,
+                3 => Pu,
+                4 if depth > 2 => Po,
+                5 => Ld(rng.below(2)),
+                6 => St(rng.below(2)),
+                7 => Ad,
+                8 => Su,
+                9 => Mu,
+                10 => Le,
+                11 => Mo,
+                12 => Jz(0),
+                13 => Call(0, rng.below(depth - 1)),
+                14 => Ret,
+                _ => Di,
+            };
+            match ins {
+                Pu => depth += 1,
+                Po => depth -= 1,
+                Call(_, argc) => depth -= argc,
+                _ => {}
+            }
+            program.push(ins);
+        }
+
+        let end = program.len();
+        for (pc, ins) in program.iter_mut().enumerate() {
+            if let Jz(target) | Call(target, _) = ins {
+                *target = pc + 1 + rng.below(end - pc);
+            }
+        }
+
+        program
+    }
+
+    #[test]
+    fn preserves_results() {
+        let mut rng = Rng::new(42);
+        let vm = Vm::new();
+
+        for _ in 0..500 {
+            let program = random_program(&mut rng, 30);
+            let optimized = peephole(&program);
+
+            assert!(optimized.len() <= program.len());
+
+            for _ in 0..10 {
+                let args = rng.args(3);
+                assert_eq!(
+                    vm.run(&program, &args).ok(),
+                    vm.run(&optimized, &args).ok(),
+                    "{program:?} optimized to {optimized:?} for {args:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::{self, Display};
+
+use crate::{ast::Ast, unparse};
+
+/// A named function of a [`Program`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<String>,
+    pub body: Ast,
+}
+
+impl Function {
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+}
+
+/// A compilation unit of one or more functions, which call each
+/// other through [`Ast::Call`] by their index in `functions`.
+/// The last function is the entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    /// Returns the index of the entry point, or `None`
+    /// if the program has no functions.
+    pub fn entry(&self) -> Option<usize> {
+        self.functions.len().checked_sub(1)
+    }
+
+    /// Returns the index of the function with the given name.
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|f| f.name == name)
+    }
+
+    /// Prints the program as source text, one function per line,
+    /// that parses back to an equal program.
+    pub fn to_source(&self) -> String {
+        let names: Vec<String> = self.functions.iter().map(|f| f.name.clone()).collect();
+
+        self.functions
+            .iter()
+            .map(|f| {
+                format!(
+                    "def {} {}",
+                    f.name,
+                    unparse::function(&f.body, &f.args, &names)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(";\n")
+    }
+}
+
+// An instruction of a machine that the functions of a program are
+// linked for.
+pub(crate) trait Link: Sized {
+    // Returns the instruction of a function starting at `start`,
+    // with its jump target relocated accordingly.
+    fn relocate(self, start: usize) -> Self;
+
+    // Replaces the function a call refers to by its start.
+    fn resolve(&mut self, starts: &[usize]);
+
+    // Returns the instruction returning from a function.
+    fn ret() -> Self;
+}
+
+// Links the functions of a program into one, starting with the
+// entry point. A program without functions links to no code. Every
+// function returns with `ret`, which ends the program when returning
+// from the entry point. Jump and call targets are relocated to the
+// start of the function.
+pub(crate) fn link<I: Link>(program: &Program, generate: impl Fn(&Ast) -> Vec<I>) -> Vec<I> {
+    let entry = program.entry();
+    let order = entry
+        .into_iter()
+        .chain((0..program.functions.len()).filter(|&f| Some(f) != entry));
+
+    let mut asm = vec![];
+    let mut starts = vec![0; program.functions.len()];
+
+    for function in order {
+        let start = asm.len();
+        starts[function] = start;
+
+        asm.extend(
+            generate(&program.functions[function].body)
+                .into_iter()
+                .map(|ins| ins.relocate(start)),
+        );
+        asm.push(I::ret());
+    }
+
+    for ins in &mut asm {
+        ins.resolve(&starts);
+    }
+
+    asm
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_source())
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    number::Domain,
+};
+
+// Simplifies the AST algebraically, going beyond constant folding:
+//
+// - identities and annihilators: `x + 0`, `x - 0`, `x * 1`, `x / 1`
+//   and `x ^ 1` become `x`, `x * 0`, `x - x` and `x % 1` become `0`,
+//   `x ^ 0` becomes `1`,
+// - conditionals with a constant condition are replaced by the
+//   branch that is taken, and so are conditionals with equal branches,
+// - in wrapping mode and the integer domain, chains of `+` and `*`
+//   are flattened and their constants are combined, so that
+//   `2 * x * 3` becomes `x * 6`.
+//
+// Reassociation is only applied in wrapping mode, where `+` and `*`
+// are associative. In checked or saturating mode, combining constants
+// could introduce or hide an overflow, e.g. in `x + i64::MAX - 1`, so
+// `2 * x * 3` is left as is.
+//
+// Division is never reassociated, as integer division truncates.
+// Subexpressions that may fail at runtime are never dropped, e.g.
+// `0 * (x / y)` is kept as is, and so is `0 * (x + y)` in checked
+// mode.
+//
+// Outside of the integer domain, nothing is reassociated, as the
+// arithmetic of floats is not associative either, and `x % 1` is the
+// fractional part of `x`. For floats, `x * 0` and `x - x` are not 0
+// if `x` is infinite.
+pub(crate) fn simplify(ast: &Ast, mode: ArithmeticMode, domain: Domain) -> Ast {
+    let exact = domain != Domain::Float;
+
+    match ast {
+        Ast::BinOp(op, lhs, rhs) => {
+            let lhs = simplify(lhs, mode, domain);
+            let rhs = simplify(rhs, mode, domain);
+
+            match (op, lhs, rhs) {
+                (BinOp::Add | BinOp::Mul, lhs, rhs)
+                    if mode == ArithmeticMode::Wrapping && domain == Domain::Integer =>
+                {
+                    reassociate(*op, lhs, rhs)
+                }
+                (BinOp::Add, lhs, Ast::Imm(0)) | (BinOp::Add, Ast::Imm(0), lhs) => lhs,
+                (BinOp::Mul, lhs, Ast::Imm(1)) | (BinOp::Mul, Ast::Imm(1), lhs) => lhs,
+                (BinOp::Mul, lhs, Ast::Imm(0)) | (BinOp::Mul, Ast::Imm(0), lhs)
+                    if exact && !may_trap(&lhs, mode, domain) =>
+                {
+                    Ast::Imm(0)
+                }
+                (BinOp::Sub, lhs, Ast::Imm(0)) => lhs,
+                (BinOp::Sub, lhs, rhs) if exact && lhs == rhs && !may_trap(&lhs, mode, domain) => {
+                    Ast::Imm(0)
+                }
+                (BinOp::Div, lhs, Ast::Imm(1)) => lhs,
+                (BinOp::Rem, lhs, Ast::Imm(1))
+                    if domain == Domain::Integer && !may_trap(&lhs, mode, domain) =>
+                {
+                    Ast::Imm(0)
+                }
+                (BinOp::Pow, lhs, Ast::Imm(1)) => lhs,
+                (BinOp::Pow, lhs, Ast::Imm(0)) if !may_trap(&lhs, mode, domain) => Ast::Imm(1),
+                (op, lhs, rhs) => match (domain.constant(&lhs), domain.constant(&rhs)) {
+                    (Some(a), Some(b)) => domain
+                        .apply(mode, *op, a, b)
+                        .ok()
+                        .and_then(|n| n.to_ast())
+                        .unwrap_or_else(|| Ast::bin_op(*op, lhs, rhs)),
+                    _ => Ast::bin_op(*op, lhs, rhs),
+                },
+            }
+        }
+        Ast::Let(value, body) => {
+            Ast::let_in(simplify(value, mode, domain), simplify(body, mode, domain))
+        }
+        Ast::If(cond, then, otherwise) => {
+            let then = simplify(then, mode, domain);
+            let otherwise = simplify(otherwise, mode, domain);
+            let cond = simplify(cond, mode, domain);
+
+            match domain.constant(&cond) {
+                Some(n) if n.is_zero() => otherwise,
+                Some(_) => then,
+                None if then == otherwise && !may_trap(&cond, mode, domain) => then,
+                None => Ast::if_else(cond, then, otherwise),
+            }
+        }
+        Ast::Call(function, args) => Ast::call(
+            *function,
+            args.iter().map(|arg| simplify(arg, mode, domain)).collect(),
+        ),
+        leaf => leaf.clone(),
+    }
+}
+
+// Rebuilds a chain of a commutative and associative operation
+// with all non-constant operands in their original order, followed
+// by the combined constant, unless it is the identity element.
+fn reassociate(op: BinOp, lhs: Ast, rhs: Ast) -> Ast {
+    let mut operands = vec![];
+    flatten(op, lhs, &mut operands);
+    flatten(op, rhs, &mut operands);
+
+    let identity = match op {
+        BinOp::Add => 0,
+        BinOp::Mul => 1,
+        _ => unreachable!(),
+    };
+    let combine = |lhs, rhs| ArithmeticMode::Wrapping.apply(op, lhs, rhs).unwrap();
+
+    let mut constant = identity;
+    let mut terms = vec![];
+
+    for operand in operands {
+        match operand {
+            Ast::Imm(n) => constant = combine(constant, n),
+            term => terms.push(term),
+        }
+    }
+
+    if op == BinOp::Mul
+        && constant == 0
+        && !terms
+            .iter()
+            .any(|term| may_trap(term, ArithmeticMode::Wrapping, Domain::Integer))
+    {
+        return Ast::Imm(0);
+    }
+
+    if constant != identity || terms.is_empty() {
+        terms.push(Ast::Imm(constant));
+    }
+
+    terms
+        .into_iter()
+        .reduce(|lhs, rhs| Ast::bin_op(op, lhs, rhs))
+        .unwrap()
+}
+
+fn flatten(op: BinOp, ast: Ast, operands: &mut Vec<Ast>) {
+    match ast {
+        Ast::BinOp(inner, lhs, rhs) if inner == op => {
+            flatten(op, *lhs, operands);
+            flatten(op, *rhs, operands);
+        }
+        ast => operands.push(ast),
+    }
+}
+
+// Returns true if evaluating the AST may fail at runtime. Division
+// and remainder may divide by zero, and so may a negative exponent.
+// In checked mode and for rationals, any arithmetic may overflow.
+// A call may fail or not return at all.
+fn may_trap(ast: &Ast, mode: ArithmeticMode, domain: Domain) -> bool {
+    let overflows = mode == ArithmeticMode::Checked || domain == Domain::Rational;
+    let may_trap = |ast| may_trap(ast, mode, domain);
+
+    match ast {
+        Ast::Imm(_) | Ast::Arg(_) | Ast::Local(_) => false,
+        Ast::BinOp(BinOp::Div | BinOp::Rem, _, _) | Ast::Call(..) => true,
+        Ast::BinOp(BinOp::Pow, _, rhs) if !matches!(**rhs, Ast::Imm(0..)) => true,
+        Ast::BinOp(op, _, _) if overflows && !op.is_comparison() => true,
+        Ast::BinOp(_, lhs, rhs) | Ast::Let(lhs, rhs) => may_trap(lhs) || may_trap(rhs),
+        Ast::If(cond, then, otherwise) => may_trap(cond) || may_trap(then) || may_trap(otherwise),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplify(ast: &Ast) -> Ast {
+        super::simplify(ast, ArithmeticMode::Wrapping, Domain::Integer)
+    }
+
+    fn x() -> Ast {
+        Ast::arg(0)
+    }
+
+    fn y() -> Ast {
+        Ast::arg(1)
+    }
+
+    #[test]
+    fn identities() {
+        assert_eq!(simplify(&Ast::add(x(), Ast::imm(0))), x());
+        assert_eq!(simplify(&Ast::add(Ast::imm(0), x())), x());
+        assert_eq!(simplify(&Ast::sub(x(), Ast::imm(0))), x());
+        assert_eq!(simplify(&Ast::mul(Ast::imm(1), x())), x());
+        assert_eq!(simplify(&Ast::div(x(), Ast::imm(1))), x());
+        assert_eq!(simplify(&Ast::bin_op(BinOp::Pow, x(), Ast::imm(1))), x());
+    }
+
+    #[test]
+    fn annihilators() {
+        assert_eq!(simplify(&Ast::mul(Ast::imm(0), x())), Ast::imm(0));
+        assert_eq!(simplify(&Ast::sub(x(), x())), Ast::imm(0));
+        assert_eq!(
+            simplify(&Ast::bin_op(BinOp::Rem, x(), Ast::imm(1))),
+            Ast::imm(0)
+        );
+        assert_eq!(
+            simplify(&Ast::bin_op(BinOp::Pow, x(), Ast::imm(0))),
+            Ast::imm(1)
+        );
+
+        let trapping = Ast::div(x(), y());
+        assert_eq!(
+            simplify(&Ast::mul(Ast::imm(0), trapping.clone())),
+            Ast::mul(trapping.clone(), Ast::imm(0))
+        );
+        assert_eq!(
+            simplify(&Ast::sub(trapping.clone(), trapping.clone())),
+            Ast::sub(trapping.clone(), trapping)
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        // if 2 - 2 then x else y
+        let cond = Ast::sub(Ast::imm(2), Ast::imm(2));
+        assert_eq!(simplify(&Ast::if_else(cond, x(), y())), y());
+        // if x then y * 1 else y
+        let ast = Ast::if_else(x(), Ast::mul(y(), Ast::imm(1)), y());
+        assert_eq!(simplify(&ast), y());
+        // if x / y then 1 else 1 may divide by zero
+        let ast = Ast::if_else(Ast::div(x(), y()), Ast::imm(1), Ast::imm(1));
+        assert_eq!(simplify(&ast), ast);
+    }
+
+    #[test]
+    fn reassociation() {
+        // 2 * x * 3
+        assert_eq!(
+            simplify(&Ast::mul(Ast::mul(Ast::imm(2), x()), Ast::imm(3))),
+            Ast::mul(x(), Ast::imm(6))
+        );
+        // 2 + (x + (3 + y))
+        assert_eq!(
+            simplify(&Ast::add(
+                Ast::imm(2),
+                Ast::add(x(), Ast::add(Ast::imm(3), y()))
+            )),
+            Ast::add(Ast::add(x(), y()), Ast::imm(5))
+        );
+        // x * 4 / 2 is not x * 2 for integer division
+        let ast = Ast::div(Ast::mul(x(), Ast::imm(4)), Ast::imm(2));
+        assert_eq!(simplify(&ast), ast);
+    }
+
+    #[test]
+    fn no_reassociation_without_wrapping() {
+        // x + i64::MAX + -1 overflows for x = 1 in checked mode
+        let ast = Ast::add(Ast::add(x(), Ast::imm(i64::MAX)), Ast::imm(-1));
+
+        // 2 * x * 3 keeps its constants apart
+        let product = Ast::mul(Ast::mul(Ast::imm(2), x()), Ast::imm(3));
+
+        for mode in [ArithmeticMode::Checked, ArithmeticMode::Saturating] {
+            assert_eq!(super::simplify(&ast, mode, Domain::Integer), ast);
+            assert_eq!(super::simplify(&product, mode, Domain::Integer), product);
+            assert_eq!(
+                super::simplify(
+                    &Ast::mul(Ast::imm(1), Ast::add(x(), Ast::imm(0))),
+                    mode,
+                    Domain::Integer
+                ),
+                x()
+            );
+        }
+        // and so it does outside of the integer domain
+        for domain in [Domain::Float, Domain::Rational] {
+            assert_eq!(
+                super::simplify(&product, ArithmeticMode::Wrapping, domain),
+                product
+            );
+        }
+
+        // x * y may overflow in checked mode, x < y never does
+        let product = Ast::mul(Ast::mul(x(), y()), Ast::imm(0));
+        assert_eq!(
+            super::simplify(&product, ArithmeticMode::Checked, Domain::Integer),
+            product
+        );
+        let comparison = Ast::mul(Ast::bin_op(BinOp::Lt, x(), y()), Ast::imm(0));
+        assert_eq!(
+            super::simplify(&comparison, ArithmeticMode::Checked, Domain::Integer),
+            Ast::imm(0)
+        );
+    }
+}
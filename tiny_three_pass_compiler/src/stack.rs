@@ -0,0 +1,617 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    codegen::slots,
+    error::{InstructionError, VmError},
+    instruction::operand,
+    number::{Domain, Number, Value},
+    program::{self, Link, Program},
+    vm::{machine_builders, Config, Frame},
+};
+
+/// An instruction of a pure stack machine, which has no registers:
+/// instructions take their operands from the top of the stack and
+/// push their result. Arguments are read from the current frame and
+/// local slots are addressed from the bottom of the frame's part of
+/// the stack.
+///
+/// Jump targets are absolute instruction offsets. Jumping to the
+/// offset past the last instruction ends the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackInstruction {
+    /// `PUSHI n`: push the constant value n
+    Pushi(i64),
+    /// `PUSHA n`: push the n-th input argument
+    Pusha(usize),
+    /// `LOAD n`: push the n-th local slot
+    Load(usize),
+    /// `STORE n`: pop the top value into the n-th local slot
+    Store(usize),
+    /// `ADD`: pop b and a and push a + b
+    Add,
+    /// `SUB`: pop b and a and push a - b
+    Sub,
+    /// `MUL`: pop b and a and push a * b
+    Mul,
+    /// `DIV`: pop b and a and push a / b
+    Div,
+    /// `MOD`: pop b and a and push the remainder of a / b
+    Mod,
+    /// `POW`: pop b and a and push a raised to the power of b
+    Pow,
+    /// `EQ`: pop b and a and push 1 if a is equal to b, else 0
+    Eq,
+    /// `NE`: pop b and a and push 1 if a is not equal to b, else 0
+    Ne,
+    /// `LT`: pop b and a and push 1 if a is less than b, else 0
+    Lt,
+    /// `LE`: pop b and a and push 1 if a is less than or equal to b, else 0
+    Le,
+    /// `GT`: pop b and a and push 1 if a is greater than b, else 0
+    Gt,
+    /// `GE`: pop b and a and push 1 if a is greater than or equal to b, else 0
+    Ge,
+    /// `JZ n`: pop the top value and jump to the n-th instruction if it is zero
+    Jz(usize),
+    /// `JMP n`: jump to the n-th instruction
+    Jmp(usize),
+    /// `CALL n m`: pop m values as the arguments of a new frame,
+    /// pushed in order, and jump to the n-th instruction
+    Call(usize, usize),
+    /// `RET`: pop the result, drop the current frame, push the result
+    /// and return to the instruction after its `CALL`, or end the
+    /// program if there is none
+    Ret,
+}
+
+impl StackInstruction {
+    /// Returns the mnemonic, e.g. `"PUSHI"`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Pushi(_) => "PUSHI",
+            Self::Pusha(_) => "PUSHA",
+            Self::Load(_) => "LOAD",
+            Self::Store(_) => "STORE",
+            Self::Add => "ADD",
+            Self::Sub => "SUB",
+            Self::Mul => "MUL",
+            Self::Div => "DIV",
+            Self::Mod => "MOD",
+            Self::Pow => "POW",
+            Self::Eq => "EQ",
+            Self::Ne => "NE",
+            Self::Lt => "LT",
+            Self::Le => "LE",
+            Self::Gt => "GT",
+            Self::Ge => "GE",
+            Self::Jz(_) => "JZ",
+            Self::Jmp(_) => "JMP",
+            Self::Call(..) => "CALL",
+            Self::Ret => "RET",
+        }
+    }
+
+    /// Returns the instruction applying the operator to the two
+    /// values on top of the stack.
+    pub fn from_bin_op(op: BinOp) -> Self {
+        match op {
+            BinOp::Add => Self::Add,
+            BinOp::Sub => Self::Sub,
+            BinOp::Mul => Self::Mul,
+            BinOp::Div => Self::Div,
+            BinOp::Rem => Self::Mod,
+            BinOp::Pow => Self::Pow,
+            BinOp::Eq => Self::Eq,
+            BinOp::Ne => Self::Ne,
+            BinOp::Lt => Self::Lt,
+            BinOp::Le => Self::Le,
+            BinOp::Gt => Self::Gt,
+            BinOp::Ge => Self::Ge,
+        }
+    }
+
+    /// Returns the operator if this instruction applies one to the
+    /// two values on top of the stack.
+    pub fn bin_op(&self) -> Option<BinOp> {
+        match self {
+            Self::Add => Some(BinOp::Add),
+            Self::Sub => Some(BinOp::Sub),
+            Self::Mul => Some(BinOp::Mul),
+            Self::Div => Some(BinOp::Div),
+            Self::Mod => Some(BinOp::Rem),
+            Self::Pow => Some(BinOp::Pow),
+            Self::Eq => Some(BinOp::Eq),
+            Self::Ne => Some(BinOp::Ne),
+            Self::Lt => Some(BinOp::Lt),
+            Self::Le => Some(BinOp::Le),
+            Self::Gt => Some(BinOp::Gt),
+            Self::Ge => Some(BinOp::Ge),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StackInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pushi(n) => write!(f, "{} {}", self.mnemonic(), n),
+            Self::Pusha(n) | Self::Load(n) | Self::Store(n) | Self::Jz(n) | Self::Jmp(n) => {
+                write!(f, "{} {}", self.mnemonic(), n)
+            }
+            Self::Call(target, argc) => write!(f, "{} {} {}", self.mnemonic(), target, argc),
+            _ => f.write_str(self.mnemonic()),
+        }
+    }
+}
+
+impl FromStr for StackInstruction {
+    type Err = InstructionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ws = s.split_whitespace();
+        let mnemonic = ws.next().unwrap_or_default();
+
+        let ins = match mnemonic {
+            "PUSHI" => Self::Pushi(operand(mnemonic, ws.next())?),
+            "PUSHA" => Self::Pusha(operand(mnemonic, ws.next())?),
+            "LOAD" => Self::Load(operand(mnemonic, ws.next())?),
+            "STORE" => Self::Store(operand(mnemonic, ws.next())?),
+            "ADD" => Self::Add,
+            "SUB" => Self::Sub,
+            "MUL" => Self::Mul,
+            "DIV" => Self::Div,
+            "MOD" => Self::Mod,
+            "POW" => Self::Pow,
+            "EQ" => Self::Eq,
+            "NE" => Self::Ne,
+            "LT" => Self::Lt,
+            "LE" => Self::Le,
+            "GT" => Self::Gt,
+            "GE" => Self::Ge,
+            "JZ" => Self::Jz(operand(mnemonic, ws.next())?),
+            "JMP" => Self::Jmp(operand(mnemonic, ws.next())?),
+            "CALL" => Self::Call(operand(mnemonic, ws.next())?, operand(mnemonic, ws.next())?),
+            "RET" => Self::Ret,
+            _ => return Err(InstructionError::UnknownMnemonic(mnemonic.to_string())),
+        };
+
+        match ws.next() {
+            Some(extra) => Err(InstructionError::UnexpectedOperand(extra.to_string())),
+            None => Ok(ins),
+        }
+    }
+}
+
+// Generates instructions for the stack machine. Without registers,
+// there is no case analysis on the shape of the operands: every
+// node is emitted in postorder, leaving its value on the stack.
+//
+// Local slots are reserved at the bottom of the stack before
+// evaluation starts, as for the register machine, see `codegen`.
+// A conditional pops its condition:
+//
+//     cond; JZ else; then; JMP end; else: otherwise; end:
+pub(crate) fn generate(ast: &Ast) -> Vec<StackInstruction> {
+    let mut asm = vec![StackInstruction::Pushi(0); slots(ast)];
+    emit(ast, 0, &mut asm);
+    asm
+}
+
+fn emit(ast: &Ast, depth: usize, asm: &mut Vec<StackInstruction>) {
+    match ast {
+        Ast::Imm(n) => asm.push(StackInstruction::Pushi(*n)),
+        Ast::Arg(n) => asm.push(StackInstruction::Pusha(*n)),
+        Ast::Local(slot) => asm.push(StackInstruction::Load(*slot)),
+        Ast::Let(value, body) => {
+            emit(value, depth, asm);
+            asm.push(StackInstruction::Store(depth));
+            emit(body, depth + 1, asm);
+        }
+        Ast::If(cond, then, otherwise) => {
+            emit(cond, depth, asm);
+            let jz = asm.len();
+            asm.push(StackInstruction::Jz(0));
+            emit(then, depth, asm);
+            let jmp = asm.len();
+            asm.push(StackInstruction::Jmp(0));
+            asm[jz] = StackInstruction::Jz(asm.len());
+            emit(otherwise, depth, asm);
+            asm[jmp] = StackInstruction::Jmp(asm.len());
+        }
+        Ast::Call(function, args) => {
+            for arg in args {
+                emit(arg, depth, asm);
+            }
+            asm.push(StackInstruction::Call(*function, args.len()));
+        }
+        Ast::BinOp(op, lhs, rhs) => {
+            emit(lhs, depth, asm);
+            emit(rhs, depth, asm);
+            asm.push(StackInstruction::from_bin_op(*op));
+        }
+    }
+}
+
+impl Link for StackInstruction {
+    fn relocate(self, start: usize) -> Self {
+        match self {
+            Self::Jz(target) => Self::Jz(start + target),
+            Self::Jmp(target) => Self::Jmp(start + target),
+            ins => ins,
+        }
+    }
+
+    fn resolve(&mut self, starts: &[usize]) {
+        if let Self::Call(function, _) = self {
+            *function = starts[*function];
+        }
+    }
+
+    fn ret() -> Self {
+        Self::Ret
+    }
+}
+
+// Links the functions of a program into one, see `program::link`.
+pub(crate) fn link(program: &Program) -> Vec<StackInstruction> {
+    program::link(program, generate)
+}
+
+/// A virtual machine executing [`StackInstruction`]s.
+///
+/// Overflow is handled according to the [`ArithmeticMode`], which
+/// defaults to wrapping. The result of a program is the value on top
+/// of the stack after the last instruction has been executed.
+///
+/// Errors are reported like by the [`Vm`](crate::Vm), popping off of
+/// an empty stack being a [`VmError::StackUnderflow`]. Like the `Vm`,
+/// it computes on integers or, with [`StackVm::run_numbers`], on
+/// numbers of a [`Domain`].
+#[derive(Debug, Clone, Default)]
+pub struct StackVm {
+    config: Config,
+}
+
+impl StackVm {
+    machine_builders!();
+
+    /// Executes the program for the given arguments and returns
+    /// the value on top of the stack.
+    pub fn run(&self, program: &[StackInstruction], args: &[i64]) -> Result<i64, VmError> {
+        self.execute(program, args.to_vec(), Domain::Integer)
+    }
+
+    /// Executes the program in the domain of the machine, converting
+    /// the arguments into it, and returns the value on top of the stack.
+    pub fn run_numbers(
+        &self,
+        program: &[StackInstruction],
+        args: &[Number],
+    ) -> Result<Number, VmError> {
+        let args = self.config.convert_args(args)?;
+        self.execute(program, args, self.config.domain)
+    }
+
+    fn execute<N: Value>(
+        &self,
+        program: &[StackInstruction],
+        args: Vec<N>,
+        domain: Domain,
+    ) -> Result<N, VmError> {
+        let mut stack: Vec<N> = vec![];
+        let mut frames = vec![Frame {
+            args,
+            base: 0,
+            ret: program.len(),
+        }];
+        let mut pc = 0;
+        let mut steps = 0;
+
+        while let Some(ins) = program.get(pc) {
+            self.config.step(&mut steps)?;
+
+            let jump = |target: usize| {
+                if target <= program.len() {
+                    Ok(target)
+                } else {
+                    Err(VmError::JumpOutOfRange { pc, target })
+                }
+            };
+            let mut next = pc + 1;
+            let frame = frames.last().unwrap();
+            let base = frame.base;
+
+            match ins {
+                StackInstruction::Pushi(n) => self.push(&mut stack, pc, N::number(domain, *n))?,
+                StackInstruction::Pusha(n) => {
+                    let arg = *frame.args.get(*n).ok_or(VmError::ArgumentOutOfRange {
+                        pc,
+                        index: *n,
+                        len: frame.args.len(),
+                    })?;
+                    self.push(&mut stack, pc, arg)?
+                }
+                StackInstruction::Load(slot) => {
+                    let value = *base
+                        .checked_add(*slot)
+                        .and_then(|index| stack.get(index))
+                        .ok_or(VmError::SlotOutOfRange { pc, slot: *slot })?;
+                    self.push(&mut stack, pc, value)?
+                }
+                StackInstruction::Store(slot) => {
+                    let value = pop(&mut stack, base, pc)?;
+                    *base
+                        .checked_add(*slot)
+                        .and_then(|index| stack.get_mut(index))
+                        .ok_or(VmError::SlotOutOfRange { pc, slot: *slot })? = value
+                }
+                StackInstruction::Jz(target) => {
+                    if pop(&mut stack, base, pc)?.is_zero() {
+                        next = jump(*target)?
+                    }
+                }
+                StackInstruction::Jmp(target) => next = jump(*target)?,
+                StackInstruction::Call(target, argc) => {
+                    if base.checked_add(*argc).is_none_or(|end| stack.len() < end) {
+                        return Err(VmError::StackUnderflow { pc });
+                    }
+                    self.config.reserve(frames.len(), pc)?;
+                    let args = stack.split_off(stack.len() - argc);
+                    frames.push(Frame {
+                        args,
+                        base: stack.len(),
+                        ret: pc + 1,
+                    });
+                    next = jump(*target)?
+                }
+                StackInstruction::Ret => {
+                    let result = pop(&mut stack, base, pc)?;
+                    let frame = frames.pop().unwrap();
+                    if frames.is_empty() {
+                        return Ok(result);
+                    }
+                    stack.truncate(frame.base);
+                    stack.push(result);
+                    next = frame.ret
+                }
+                _ => {
+                    let op = ins.bin_op().expect("arithmetic instruction");
+                    let rhs = pop(&mut stack, base, pc)?;
+                    let lhs = pop(&mut stack, base, pc)?;
+                    let result = N::apply(domain, self.config.mode, op, lhs, rhs)
+                        .map_err(|e| VmError::arithmetic(e, pc))?;
+                    stack.push(result)
+                }
+            }
+
+            pc = next;
+        }
+
+        stack.pop().ok_or(VmError::StackUnderflow { pc })
+    }
+
+    fn push<N>(&self, stack: &mut Vec<N>, pc: usize, value: N) -> Result<(), VmError> {
+        self.config.reserve(stack.len(), pc)?;
+        stack.push(value);
+        Ok(())
+    }
+}
+
+// Pops a value of the current frame, whose part of the stack
+// starts at `base`.
+fn pop<N>(stack: &mut Vec<N>, base: usize, pc: usize) -> Result<N, VmError> {
+    if stack.len() == base {
+        return Err(VmError::StackUnderflow { pc });
+    }
+    Ok(stack.pop().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codegen, instruction::disassemble, program::Function, testing::cross_check};
+    use StackInstruction::*;
+
+    #[test]
+    fn round_trip() {
+        let program = vec![
+            Pushi(-10),
+            Pusha(0),
+            Add,
+            Store(1),
+            Load(1),
+            Mod,
+            Le,
+            Jz(9),
+            Jmp(3),
+            Call(12, 2),
+            Ret,
+        ];
+        let listing = disassemble(&program);
+
+        assert_eq!(
+            listing,
+            "PUSHI -10\nPUSHA 0\nADD\nSTORE 1\nLOAD 1\nMOD\nLE\nJZ 9\nJMP 3\nCALL 12 2\nRET\n"
+        );
+        assert_eq!(
+            listing
+                .lines()
+                .map(str::parse)
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(program)
+        );
+        assert_eq!(
+            "PUSH 1".parse::<StackInstruction>(),
+            Err(InstructionError::UnknownMnemonic("PUSH".to_string()))
+        );
+        assert_eq!(
+            "ADD 1".parse::<StackInstruction>(),
+            Err(InstructionError::UnexpectedOperand("1".to_string()))
+        );
+    }
+
+    #[test]
+    fn generates_postorder() {
+        // let a = x * 2 in if a > y then a else y - 3
+        let ast = Ast::let_in(
+            Ast::mul(Ast::arg(0), Ast::imm(2)),
+            Ast::if_else(
+                Ast::bin_op(BinOp::Gt, Ast::local(0), Ast::arg(1)),
+                Ast::local(0),
+                Ast::sub(Ast::arg(1), Ast::imm(3)),
+            ),
+        );
+        let asm = generate(&ast);
+
+        assert_eq!(
+            asm,
+            vec![
+                Pushi(0),
+                Pusha(0),
+                Pushi(2),
+                Mul,
+                Store(0),
+                Load(0),
+                Pusha(1),
+                Gt,
+                Jz(11),
+                Load(0),
+                Jmp(14),
+                Pusha(1),
+                Pushi(3),
+                Sub,
+            ]
+        );
+        let vm = StackVm::new();
+        assert_eq!(vm.run(&asm, &[5, 4]), Ok(10));
+        assert_eq!(vm.run(&asm, &[1, 4]), Ok(1));
+    }
+
+    #[test]
+    fn links_functions() {
+        // def sq [a] a * a; def main [x] sq(x + 1) - 1
+        let program = Program {
+            functions: vec![
+                Function {
+                    name: "sq".to_string(),
+                    args: vec!["a".to_string()],
+                    body: Ast::mul(Ast::arg(0), Ast::arg(0)),
+                },
+                Function {
+                    name: "main".to_string(),
+                    args: vec!["x".to_string()],
+                    body: Ast::sub(
+                        Ast::call(0, vec![Ast::add(Ast::arg(0), Ast::imm(1))]),
+                        Ast::imm(1),
+                    ),
+                },
+            ],
+        };
+        let asm = link(&program);
+
+        assert_eq!(
+            asm,
+            vec![
+                Pusha(0),
+                Pushi(1),
+                Add,
+                Call(7, 1),
+                Pushi(1),
+                Sub,
+                Ret,
+                Pusha(0),
+                Pusha(0),
+                Mul,
+                Ret
+            ]
+        );
+        assert_eq!(StackVm::new().run(&asm, &[4]), Ok(24));
+        assert_eq!(codegen::link(&program).len(), 16);
+    }
+
+    #[test]
+    fn errors() {
+        let vm = StackVm::new();
+
+        assert_eq!(vm.run(&[], &[]), Err(VmError::StackUnderflow { pc: 0 }));
+        assert_eq!(
+            vm.run(&[Pushi(1), Add], &[]),
+            Err(VmError::StackUnderflow { pc: 1 })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Store(0)], &[]),
+            Err(VmError::SlotOutOfRange { pc: 1, slot: 0 })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Call(2, 0), Load(usize::MAX)], &[]),
+            Err(VmError::SlotOutOfRange {
+                pc: 2,
+                slot: usize::MAX
+            })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Call(2, 0), Pushi(2), Store(usize::MAX)], &[]),
+            Err(VmError::SlotOutOfRange {
+                pc: 3,
+                slot: usize::MAX
+            })
+        );
+        assert_eq!(
+            vm.run(&[Pusha(2)], &[1, 2]),
+            Err(VmError::ArgumentOutOfRange {
+                pc: 0,
+                index: 2,
+                len: 2
+            })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Pushi(0), Div], &[]),
+            Err(VmError::DivisionByZero { pc: 2 })
+        );
+        assert_eq!(
+            vm.clone()
+                .with_arithmetic(ArithmeticMode::Checked)
+                .run(&[Pushi(i64::MIN), Pushi(-1), Div], &[]),
+            Err(VmError::Overflow { pc: 2 })
+        );
+        assert_eq!(
+            vm.clone()
+                .with_stack_limit(2)
+                .run(&[Pushi(1), Pushi(2), Pushi(3)], &[]),
+            Err(VmError::StackOverflow { pc: 2, limit: 2 })
+        );
+        assert_eq!(
+            vm.clone().with_budget(100).run(&[Jmp(0)], &[]),
+            Err(VmError::BudgetExhausted { budget: 100 })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(0), Jz(3)], &[]),
+            Err(VmError::JumpOutOfRange { pc: 1, target: 3 })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Call(3, 0), Ret, Add, Ret], &[]),
+            Err(VmError::StackUnderflow { pc: 3 })
+        );
+        assert_eq!(
+            vm.run(&[Pushi(1), Call(2, 0), Call(0, usize::MAX)], &[]),
+            Err(VmError::StackUnderflow { pc: 2 })
+        );
+        assert_eq!(
+            vm.clone().with_stack_limit(8).run(&[Call(0, 0)], &[]),
+            Err(VmError::StackOverflow { pc: 0, limit: 8 })
+        );
+    }
+
+    #[test]
+    fn random_programs() {
+        cross_check(22, |ast, mode| {
+            let (vm, asm) = (StackVm::new().with_arithmetic(mode), generate(ast));
+            move |args: &[i64]| vm.run(&asm, args).ok()
+        });
+    }
+}
@@ -0,0 +1,152 @@
+// Helpers for randomized tests.
+
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    number::{Domain, Number},
+};
+
+pub(crate) const MODES: [ArithmeticMode; 3] = [
+    ArithmeticMode::Checked,
+    ArithmeticMode::Wrapping,
+    ArithmeticMode::Saturating,
+];
+
+const OPS: &[BinOp] = &[
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mul,
+    BinOp::Div,
+    BinOp::Rem,
+    BinOp::Pow,
+    BinOp::Eq,
+    BinOp::Ne,
+    BinOp::Lt,
+    BinOp::Le,
+    BinOp::Gt,
+    BinOp::Ge,
+];
+
+// A xorshift generator, good enough to produce test inputs
+// without pulling in a dependency.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Returns a number in `0..n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Returns a number in `lo..=hi`.
+    pub(crate) fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i64
+    }
+
+    pub(crate) fn args(&mut self, n: usize) -> Vec<i64> {
+        (0..n).map(|_| self.range(-100, 100)).collect()
+    }
+
+    // Returns an AST of at most the given depth referring to `args`
+    // arguments, with operations, conditionals and bindings.
+    pub(crate) fn ast(&mut self, depth: usize, args: usize) -> Ast {
+        self.ast_in(depth, args, 0)
+    }
+
+    fn ast_in(&mut self, depth: usize, args: usize, locals: usize) -> Ast {
+        if depth == 0 || self.below(4) == 0 {
+            return match self.below(3) {
+                0 => Ast::imm(self.range(-10, 10)),
+                1 if locals > 0 => Ast::local(self.below(locals)),
+                _ => Ast::arg(self.below(args)),
+            };
+        }
+
+        match self.below(10) {
+            0 => Ast::let_in(
+                self.ast_in(depth - 1, args, locals),
+                self.ast_in(depth - 1, args, locals + 1),
+            ),
+            1 => Ast::if_else(
+                self.ast_in(depth - 1, args, locals),
+                self.ast_in(depth - 1, args, locals),
+                self.ast_in(depth - 1, args, locals),
+            ),
+            _ => Ast::bin_op(
+                OPS[self.below(OPS.len())],
+                self.ast_in(depth - 1, args, locals),
+                self.ast_in(depth - 1, args, locals),
+            ),
+        }
+    }
+}
+
+// Returns random programs, each with the mode to run it in and
+// the arguments to run it for, for every mode.
+pub(crate) fn programs(seed: u64) -> Vec<(Ast, ArithmeticMode, Vec<Vec<i64>>)> {
+    let mut rng = Rng::new(seed);
+
+    MODES
+        .into_iter()
+        .flat_map(|mode| (0..300).map(move |_| mode))
+        .map(|mode| {
+            let ast = rng.ast(5, 3);
+            let cases = (0..5).map(|_| rng.args(3)).collect();
+            (ast, mode, cases)
+        })
+        .collect()
+}
+
+// Checks that a backend agrees with `Ast::eval_with` on the random
+// `programs`, where `compile` turns a program into a function of its
+// arguments returning the result, if any.
+pub(crate) fn cross_check<F>(seed: u64, compile: impl Fn(&Ast, ArithmeticMode) -> F)
+where
+    F: Fn(&[i64]) -> Option<i64>,
+{
+    for (ast, mode, cases) in programs(seed) {
+        let f = compile(&ast, mode);
+        for args in cases {
+            assert_eq!(
+                f(&args),
+                ast.eval_with(&args, mode).ok(),
+                "{ast} for {args:?}"
+            );
+        }
+    }
+}
+
+// Checks like `cross_check`, but against `Ast::eval_numbers` in the
+// domain. Results are compared by text, so that NaN equals NaN and
+// 0 differs from -0.
+pub(crate) fn cross_check_numbers<F>(
+    seed: u64,
+    domain: Domain,
+    compile: impl Fn(&Ast, ArithmeticMode) -> F,
+) where
+    F: Fn(&[Number]) -> Option<Number>,
+{
+    let text = |result: Option<Number>| result.map(|n| n.to_string());
+
+    for (ast, mode, cases) in programs(seed) {
+        let f = compile(&ast, mode);
+        for args in cases {
+            let args: Vec<Number> = args.into_iter().map(Number::from).collect();
+            assert_eq!(
+                text(f(&args)),
+                text(ast.eval_numbers(&args, mode, domain).ok()),
+                "{ast} for {args:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt::Write;
+
+use crate::{
+    ast::{Ast, BinOp},
+    codegen::{arity, slots},
+    parser::KEYWORDS,
+};
+
+// Binding strength of the grammar rules, from loosest to tightest.
+const BLOCK: u8 = 0;
+const COMPARISON: u8 = 1;
+const SUM: u8 = 2;
+const PRODUCT: u8 = 3;
+const UNARY: u8 = 4;
+const POWER: u8 = 5;
+const ATOM: u8 = 6;
+
+// Prints a function as source text that parses back to the same AST,
+// e.g. `[ x y ] (x + y) / 2`.
+//
+// An operand is parenthesized only if the rule it is parsed from
+// binds looser than the operator requires. Both operands of a
+// comparison are sums, the left operand of a left-associative
+// operator is on the same level and the right one on the next,
+// and `^` takes an atom on the left and a unary on the right.
+//
+// `0 - x` is printed as `-x`, which is what it is parsed from,
+// unless `x` is a number: `-2` is parsed as a literal.
+//
+// Arguments without a name are named `a`, `b`, ..., functions
+// without a name are printed by their index, e.g. `#0(x)`, and
+// bindings are named after the first names that are not taken.
+// A local outside of its binding is printed by its slot, e.g. `local0`.
+pub(crate) fn function(ast: &Ast, args: &[String], functions: &[String]) -> String {
+    let arity = args.len().max(arity(ast));
+    let args: Vec<String> = (0..arity)
+        .map(|n| args.get(n).cloned().unwrap_or_else(|| alphabetic(n)))
+        .collect();
+
+    let locals = (0..)
+        .map(alphabetic)
+        .filter(|name| {
+            !args.contains(name) && !functions.contains(name) && !KEYWORDS.contains(&name.as_str())
+        })
+        .take(slots(ast))
+        .collect();
+
+    let printer = Printer {
+        args: &args,
+        functions,
+        locals,
+    };
+
+    let mut out = String::from("[ ");
+    for arg in &args {
+        out.push_str(arg);
+        out.push(' ');
+    }
+    out.push_str("] ");
+    printer.print(ast, BLOCK, 0, &mut out);
+    out
+}
+
+struct Printer<'a> {
+    args: &'a [String],
+    functions: &'a [String],
+    locals: Vec<String>,
+}
+
+impl Printer<'_> {
+    // Prints the AST as an operand that must bind at least as tight
+    // as `min`, inside of `depth` bindings.
+    fn print(&self, ast: &Ast, min: u8, depth: usize, out: &mut String) {
+        if level(ast) < min {
+            out.push('(');
+            self.print(ast, BLOCK, depth, out);
+            out.push(')');
+            return;
+        }
+
+        match ast {
+            Ast::Imm(n) => write!(out, "{n}").unwrap(),
+            Ast::Arg(n) => out.push_str(&self.args[*n]),
+            Ast::Local(slot) if *slot < depth => out.push_str(&self.locals[*slot]),
+            Ast::Local(slot) => write!(out, "local{slot}").unwrap(),
+            Ast::BinOp(_, _, rhs) if is_negation(ast) => {
+                out.push('-');
+                self.print(rhs, UNARY, depth, out);
+            }
+            Ast::BinOp(op, lhs, rhs) => {
+                let (l, r) = operands(*op);
+                self.print(lhs, l, depth, out);
+                write!(out, " {op} ").unwrap();
+                self.print(rhs, r, depth, out);
+            }
+            Ast::Let(value, body) => {
+                write!(out, "let {} = ", self.locals[depth]).unwrap();
+                self.print(value, BLOCK, depth, out);
+                out.push_str(" in ");
+                self.print(body, BLOCK, depth + 1, out);
+            }
+            Ast::If(cond, then, otherwise) => {
+                out.push_str("if ");
+                self.print(cond, BLOCK, depth, out);
+                out.push_str(" then ");
+                self.print(then, BLOCK, depth, out);
+                out.push_str(" else ");
+                self.print(otherwise, BLOCK, depth, out);
+            }
+            Ast::Call(function, args) => {
+                match self.functions.get(*function) {
+                    Some(name) => out.push_str(name),
+                    None => write!(out, "#{function}").unwrap(),
+                }
+                out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.print(arg, BLOCK, depth, out);
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+// Returns the rule the AST is printed as.
+fn level(ast: &Ast) -> u8 {
+    match ast {
+        Ast::Imm(n) if *n < 0 => UNARY,
+        Ast::Imm(_) | Ast::Arg(_) | Ast::Local(_) | Ast::Call(..) => ATOM,
+        Ast::BinOp(..) if is_negation(ast) => UNARY,
+        Ast::BinOp(op, _, _) if op.is_comparison() => COMPARISON,
+        Ast::BinOp(BinOp::Add | BinOp::Sub, _, _) => SUM,
+        Ast::BinOp(BinOp::Pow, _, _) => POWER,
+        Ast::BinOp(..) => PRODUCT,
+        Ast::Let(..) | Ast::If(..) => BLOCK,
+    }
+}
+
+// Returns the levels required of the left and right operand.
+fn operands(op: BinOp) -> (u8, u8) {
+    match op {
+        _ if op.is_comparison() => (SUM, SUM),
+        BinOp::Add | BinOp::Sub => (SUM, PRODUCT),
+        BinOp::Pow => (ATOM, UNARY),
+        _ => (PRODUCT, UNARY),
+    }
+}
+
+fn is_negation(ast: &Ast) -> bool {
+    match ast {
+        Ast::BinOp(BinOp::Sub, lhs, rhs) => {
+            matches!(**lhs, Ast::Imm(0)) && !matches!(**rhs, Ast::Imm(0..))
+        }
+        _ => false,
+    }
+}
+
+// Returns the n-th name of the sequence a, b, ..., z, aa, ab, ...
+fn alphabetic(mut n: usize) -> String {
+    let mut name = vec![];
+    loop {
+        name.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Rng, Compiler};
+
+    fn print(ast: &Ast) -> String {
+        function(ast, &["x".to_string(), "y".to_string()], &[])
+    }
+
+    fn x() -> Ast {
+        Ast::arg(0)
+    }
+
+    fn y() -> Ast {
+        Ast::arg(1)
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(alphabetic(0), "a");
+        assert_eq!(alphabetic(25), "z");
+        assert_eq!(alphabetic(26), "aa");
+        assert_eq!(alphabetic(27 * 26), "aaa");
+
+        assert_eq!(function(&Ast::arg(1), &[], &[]), "[ a b ] b");
+        assert_eq!(
+            function(
+                &Ast::let_in(Ast::arg(0), Ast::local(0)),
+                &["a".to_string()],
+                &[]
+            ),
+            "[ a ] let b = a in b"
+        );
+        assert_eq!(
+            function(&Ast::call(0, vec![Ast::imm(1)]), &[], &[]),
+            "[ ] #0(1)"
+        );
+        assert_eq!(
+            Ast::add(Ast::local(0), Ast::imm(1)).to_string(),
+            "[ ] local0 + 1"
+        );
+        assert_eq!(
+            print(&Ast::add(
+                Ast::let_in(Ast::imm(1), Ast::let_in(Ast::imm(2), Ast::local(1))),
+                Ast::local(1)
+            )),
+            "[ x y ] (let a = 1 in let b = 2 in b) + local1"
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        let cases = [
+            (Ast::sub(x(), Ast::sub(y(), Ast::imm(1))), "x - (y - 1)"),
+            (Ast::sub(Ast::sub(x(), y()), Ast::imm(1)), "x - y - 1"),
+            (Ast::add(x(), Ast::add(y(), Ast::imm(1))), "x + (y + 1)"),
+            (Ast::div(x(), Ast::mul(y(), Ast::imm(2))), "x / (y * 2)"),
+            (Ast::mul(Ast::add(x(), y()), Ast::imm(2)), "(x + y) * 2"),
+            (Ast::add(Ast::mul(x(), y()), Ast::imm(2)), "x * y + 2"),
+            (Ast::mul(Ast::imm(-2), x()), "-2 * x"),
+            (Ast::sub(x(), Ast::imm(-2)), "x - -2"),
+            (Ast::sub(Ast::imm(0), x()), "-x"),
+            (Ast::sub(Ast::imm(0), Ast::imm(2)), "0 - 2"),
+            (Ast::mul(Ast::sub(Ast::imm(0), x()), y()), "-x * y"),
+            (Ast::sub(Ast::imm(0), Ast::mul(x(), y())), "-(x * y)"),
+        ];
+
+        for (ast, source) in cases {
+            assert_eq!(print(&ast), format!("[ x y ] {source}"));
+        }
+    }
+
+    #[test]
+    fn operators() {
+        let pow = |lhs, rhs| Ast::bin_op(BinOp::Pow, lhs, rhs);
+        let lt = |lhs, rhs| Ast::bin_op(BinOp::Lt, lhs, rhs);
+
+        let cases = [
+            (pow(x(), pow(y(), Ast::imm(2))), "x ^ y ^ 2"),
+            (pow(pow(x(), y()), Ast::imm(2)), "(x ^ y) ^ 2"),
+            (pow(Ast::imm(-2), x()), "(-2) ^ x"),
+            (pow(x(), Ast::imm(-2)), "x ^ -2"),
+            (Ast::sub(Ast::imm(0), pow(Ast::imm(2), x())), "-2 ^ x"),
+            (lt(lt(x(), y()), Ast::imm(1)), "(x < y) < 1"),
+            (lt(Ast::add(x(), y()), Ast::imm(1)), "x + y < 1"),
+            (
+                Ast::add(Ast::if_else(x(), y(), Ast::imm(1)), Ast::imm(2)),
+                "(if x then y else 1) + 2",
+            ),
+            (
+                Ast::if_else(lt(x(), y()), Ast::let_in(x(), Ast::local(0)), y()),
+                "if x < y then let a = x in a else y",
+            ),
+        ];
+
+        for (ast, source) in cases {
+            assert_eq!(print(&ast), format!("[ x y ] {source}"));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::new(7);
+        let mut c = Compiler::new();
+        let args = ["x".to_string(), "y".to_string(), "z".to_string()];
+
+        for _ in 0..1000 {
+            let ast = rng.ast(5, 3);
+            let source = function(&ast, &args, &[]);
+
+            assert_eq!(c.pass1(&source), Ok(ast), "{source}");
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use crate::{
+    arith::ArithmeticMode,
+    error::VmError,
+    instruction::Instruction,
+    number::{convert_args, Domain, Number, Value},
+};
+
+/// A virtual machine executing [`Instruction`]s.
+///
+/// The machine has two registers, R0 and R1, both initialized
+/// to zero, and a stack. Overflow is handled according to the
+/// [`ArithmeticMode`], which defaults to wrapping. The result of
+/// a program is the value of R0 after the last instruction has
+/// been executed.
+///
+/// [`Vm::run`] computes on integers, [`Vm::run_numbers`] on numbers
+/// of the [`Domain`] set with [`Vm::with_domain`].
+#[derive(Debug, Clone, Default)]
+pub struct Vm {
+    config: Config,
+}
+
+impl Vm {
+    machine_builders!();
+
+    /// Executes the program for the given arguments and returns R0.
+    pub fn run(&self, program: &[Instruction], args: &[i64]) -> Result<i64, VmError> {
+        self.execute(program, args.to_vec(), Domain::Integer)
+    }
+
+    /// Executes the program in the domain of the machine, converting
+    /// the arguments into it, and returns R0.
+    pub fn run_numbers(&self, program: &[Instruction], args: &[Number]) -> Result<Number, VmError> {
+        let args = self.config.convert_args(args)?;
+        self.execute(program, args, self.config.domain)
+    }
+
+    fn execute<N: Value>(
+        &self,
+        program: &[Instruction],
+        args: Vec<N>,
+        domain: Domain,
+    ) -> Result<N, VmError> {
+        let zero = N::number(domain, 0);
+        let mut r = (zero, zero);
+        let mut stack: Vec<N> = vec![];
+        let mut frames = vec![Frame {
+            args,
+            base: 0,
+            ret: program.len(),
+        }];
+        let mut pc = 0;
+        let mut steps = 0;
+
+        while let Some(ins) = program.get(pc) {
+            self.config.step(&mut steps)?;
+
+            let jump = |target: usize| {
+                if target <= program.len() {
+                    Ok(target)
+                } else {
+                    Err(VmError::JumpOutOfRange { pc, target })
+                }
+            };
+            let mut next = pc + 1;
+            let frame = frames.last().unwrap();
+
+            match ins {
+                Instruction::Im(n) => r.0 = N::number(domain, *n),
+                Instruction::Ar(n) => {
+                    r.0 = *frame.args.get(*n).ok_or(VmError::ArgumentOutOfRange {
+                        pc,
+                        index: *n,
+                        len: frame.args.len(),
+                    })?
+                }
+                Instruction::Sw => r = (r.1, r.0),
+                Instruction::Pu => {
+                    self.config.reserve(stack.len(), pc)?;
+                    stack.push(r.0)
+                }
+                Instruction::Po => {
+                    if stack.len() == frame.base {
+                        return Err(VmError::StackUnderflow { pc });
+                    }
+                    r.0 = stack.pop().unwrap()
+                }
+                Instruction::Ld(slot) => {
+                    r.0 = *frame
+                        .base
+                        .checked_add(*slot)
+                        .and_then(|index| stack.get(index))
+                        .ok_or(VmError::SlotOutOfRange { pc, slot: *slot })?
+                }
+                Instruction::St(slot) => {
+                    *frame
+                        .base
+                        .checked_add(*slot)
+                        .and_then(|index| stack.get_mut(index))
+                        .ok_or(VmError::SlotOutOfRange { pc, slot: *slot })? = r.0
+                }
+                Instruction::Jz(target) => {
+                    if r.0.is_zero() {
+                        next = jump(*target)?
+                    }
+                }
+                Instruction::Jmp(target) => next = jump(*target)?,
+                Instruction::Call(target, argc) => {
+                    if frame
+                        .base
+                        .checked_add(*argc)
+                        .is_none_or(|end| stack.len() < end)
+                    {
+                        return Err(VmError::StackUnderflow { pc });
+                    }
+                    self.config.reserve(frames.len(), pc)?;
+                    let args = stack.split_off(stack.len() - argc);
+                    frames.push(Frame {
+                        args,
+                        base: stack.len(),
+                        ret: pc + 1,
+                    });
+                    next = jump(*target)?
+                }
+                Instruction::Ret => {
+                    let frame = frames.pop().unwrap();
+                    if frames.is_empty() {
+                        break;
+                    }
+                    stack.truncate(frame.base);
+                    next = frame.ret
+                }
+                _ => {
+                    let op = ins.bin_op().expect("arithmetic instruction");
+                    r.0 = N::apply(domain, self.config.mode, op, r.0, r.1)
+                        .map_err(|e| VmError::arithmetic(e, pc))?
+                }
+            }
+
+            pc = next;
+        }
+
+        Ok(r.0)
+    }
+}
+
+// The settings of the `Vm` and the `StackVm`, which share their
+// builders through `machine_builders!`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Config {
+    pub(crate) stack_limit: usize,
+    pub(crate) budget: Option<usize>,
+    pub(crate) mode: ArithmeticMode,
+    pub(crate) domain: Domain,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            stack_limit: Self::DEFAULT_STACK_LIMIT,
+            budget: None,
+            mode: ArithmeticMode::default(),
+            domain: Domain::default(),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) const DEFAULT_STACK_LIMIT: usize = 1024;
+
+    // Counts the execution of one more instruction against the budget.
+    pub(crate) fn step(&self, steps: &mut usize) -> Result<(), VmError> {
+        if let Some(budget) = self.budget {
+            if *steps == budget {
+                return Err(VmError::BudgetExhausted { budget });
+            }
+        }
+        *steps += 1;
+        Ok(())
+    }
+
+    // Fails if there is no room for one more value or frame
+    // beside the `len` ones there are.
+    pub(crate) fn reserve(&self, len: usize, pc: usize) -> Result<(), VmError> {
+        if len == self.stack_limit {
+            return Err(VmError::StackOverflow {
+                pc,
+                limit: self.stack_limit,
+            });
+        }
+        Ok(())
+    }
+
+    // Converts the arguments of a program into the domain.
+    pub(crate) fn convert_args(&self, args: &[Number]) -> Result<Vec<Number>, VmError> {
+        convert_args(self.domain, args).map_err(|index| VmError::ArgumentNotInDomain { index })
+    }
+}
+
+// Implements the constructor and the builders of a machine, which
+// keeps its settings in a `config` field.
+macro_rules! machine_builders {
+    () => {
+        pub const DEFAULT_STACK_LIMIT: usize = crate::vm::Config::DEFAULT_STACK_LIMIT;
+
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Sets the maximum number of values on the stack,
+        /// which also limits the depth of nested calls.
+        pub fn with_stack_limit(mut self, stack_limit: usize) -> Self {
+            self.config.stack_limit = stack_limit;
+            self
+        }
+
+        /// Sets the maximum number of instructions to execute.
+        pub fn with_budget(mut self, budget: usize) -> Self {
+            self.config.budget = Some(budget);
+            self
+        }
+
+        /// Sets the overflow behaviour of arithmetic instructions.
+        pub fn with_arithmetic(mut self, mode: ArithmeticMode) -> Self {
+            self.config.mode = mode;
+            self
+        }
+
+        /// Sets the numbers [`Self::run_numbers`] computes with.
+        pub fn with_domain(mut self, domain: Domain) -> Self {
+            self.config.domain = domain;
+            self
+        }
+    };
+}
+
+pub(crate) use machine_builders;
+
+// The arguments and the start of the locals of a function
+// invocation, and where to continue once it returns.
+pub(crate) struct Frame<N = i64> {
+    pub(crate) args: Vec<N>,
+    pub(crate) base: usize,
+    pub(crate) ret: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn run() {
+        let vm = Vm::new();
+
+        assert_eq!(vm.run(&[Im(7)], &[3]), Ok(7));
+        assert_eq!(vm.run(&[Ar(1)], &[1, 2, 3]), Ok(2));
+        assert_eq!(vm.run(&[Im(3), Sw, Ar(0), Su], &[1]), Ok(-2));
+        assert_eq!(vm.run(&[Im(3), Sw, Ar(0), Pw], &[2]), Ok(8));
+        assert_eq!(vm.run(&[Im(3), Sw, Ar(0), Lt], &[2]), Ok(1));
+        assert_eq!(vm.run(&[Ar(0), Pu, Im(0), Po], &[5]), Ok(5));
+        assert_eq!(vm.run(&[Pu, Pu, Ar(0), St(1), Im(0), Ld(1)], &[5]), Ok(5));
+
+        // if x then 1 else 2
+        let program = [Ar(0), Jz(4), Im(1), Jmp(5), Im(2)];
+        assert_eq!(vm.run(&program, &[7]), Ok(1));
+        assert_eq!(vm.run(&program, &[0]), Ok(2));
+
+        // f(x, 3) - 1 where f(a, b) = a * b, with a local in f
+        let program = [
+            Ar(0),
+            Pu,
+            Im(3),
+            Pu,
+            Call(10, 2),
+            Sw,
+            Im(1),
+            Sw,
+            Su,
+            Ret,
+            Pu,
+            Ar(1),
+            Sw,
+            Ar(0),
+            Mu,
+            St(0),
+            Ld(0),
+            Ret,
+        ];
+        assert_eq!(vm.run(&program, &[5]), Ok(14));
+    }
+
+    #[test]
+    fn errors() {
+        let vm = Vm::new();
+
+        assert_eq!(
+            vm.run(&[Sw, Po], &[]),
+            Err(VmError::StackUnderflow { pc: 1 })
+        );
+        assert_eq!(
+            vm.run(&[Pu, St(1)], &[]),
+            Err(VmError::SlotOutOfRange { pc: 1, slot: 1 })
+        );
+        assert_eq!(
+            vm.run(&[Pu, Call(2, 0), Ld(usize::MAX)], &[]),
+            Err(VmError::SlotOutOfRange {
+                pc: 2,
+                slot: usize::MAX
+            })
+        );
+        assert_eq!(
+            vm.run(&[Pu, Call(2, 0), St(usize::MAX)], &[]),
+            Err(VmError::SlotOutOfRange {
+                pc: 2,
+                slot: usize::MAX
+            })
+        );
+        assert_eq!(
+            vm.run(&[Ar(2)], &[1, 2]),
+            Err(VmError::ArgumentOutOfRange {
+                pc: 0,
+                index: 2,
+                len: 2
+            })
+        );
+        assert_eq!(
+            vm.run(&[Im(0), Sw, Im(1), Di], &[]),
+            Err(VmError::DivisionByZero { pc: 3 })
+        );
+        assert_eq!(
+            vm.clone()
+                .with_arithmetic(ArithmeticMode::Checked)
+                .run(&[Im(-1), Sw, Im(i64::MIN), Di], &[]),
+            Err(VmError::Overflow { pc: 3 })
+        );
+        assert_eq!(
+            vm.run(&[Im(0), Sw, Im(1), Mo], &[]),
+            Err(VmError::DivisionByZero { pc: 3 })
+        );
+        assert_eq!(
+            vm.clone().with_stack_limit(2).run(&[Pu, Pu, Pu], &[]),
+            Err(VmError::StackOverflow { pc: 2, limit: 2 })
+        );
+        assert_eq!(
+            vm.clone().with_budget(2).run(&[Im(1), Sw, Im(2)], &[]),
+            Err(VmError::BudgetExhausted { budget: 2 })
+        );
+        assert_eq!(
+            vm.clone().with_budget(100).run(&[Im(1), Jmp(0)], &[]),
+            Err(VmError::BudgetExhausted { budget: 100 })
+        );
+        assert_eq!(
+            vm.run(&[Im(0), Jz(3)], &[]),
+            Err(VmError::JumpOutOfRange { pc: 1, target: 3 })
+        );
+        assert_eq!(
+            vm.run(&[Pu, Call(3, 0), Ret, Po], &[]),
+            Err(VmError::StackUnderflow { pc: 3 })
+        );
+        assert_eq!(
+            vm.run(&[Call(1, 1)], &[]),
+            Err(VmError::StackUnderflow { pc: 0 })
+        );
+        assert_eq!(
+            vm.run(&[Pu, Call(2, 0), Call(0, usize::MAX)], &[]),
+            Err(VmError::StackUnderflow { pc: 2 })
+        );
+        assert_eq!(
+            vm.clone().with_stack_limit(8).run(&[Call(0, 0)], &[]),
+            Err(VmError::StackOverflow { pc: 0, limit: 8 })
+        );
+    }
+}
@@ -0,0 +1,876 @@
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    codegen::slots,
+    program::Program,
+};
+
+// Generates a WebAssembly module in the text format (WAT), e.g.
+// for `[ x ] x + 2`:
+//
+//     (module
+//       (func $fn_main (export "main") (param $arg0 i64) (result i64)
+//         local.get $arg0
+//         i64.const 2
+//         i64.add
+//       )
+//     )
+//
+// Every function of the program becomes a function taking its
+// arguments as `i64` params and returning an `i64`, named with a
+// `fn_` prefix. Only the entry point is exported, by its name.
+// Locals bound by `Let` become `$local` locals, addressed like the
+// slots of the target machine.
+//
+// Instructions are emitted in the linear (stack machine) form, one
+// per line. Operands are simply evaluated left to right, as there
+// are no registers to allocate.
+//
+// Operators that behave like their Wasm counterpart in the chosen
+// `ArithmeticMode` are emitted inline. Others are emitted as calls
+// of `$op_` helper functions, which are added to the module if used.
+// Checked overflow traps with `unreachable`, just like division by
+// zero traps in `i64.div_s` and `i64.rem_s`.
+pub(crate) fn module(program: &Program, mode: ArithmeticMode) -> String {
+    let mut wat = Wat {
+        program,
+        mode,
+        out: String::from("(module\n"),
+        helpers: BTreeSet::new(),
+    };
+
+    for function in 0..program.functions.len() {
+        wat.function(function);
+    }
+    for helper in wat.helpers.clone() {
+        wat.out.push_str(&helper.definition(mode));
+    }
+
+    wat.out.push_str(")\n");
+    wat.out
+}
+
+struct Wat<'a> {
+    program: &'a Program,
+    mode: ArithmeticMode,
+    out: String,
+    helpers: BTreeSet<Helper>,
+}
+
+impl Wat<'_> {
+    fn function(&mut self, index: usize) {
+        let function = &self.program.functions[index];
+
+        write!(self.out, "  (func $fn_{}", function.name).unwrap();
+        if Some(index) == self.program.entry() {
+            write!(self.out, " (export \"{}\")", function.name).unwrap();
+        }
+        for arg in 0..function.arity() {
+            write!(self.out, " (param $arg{arg} i64)").unwrap();
+        }
+        self.out.push_str(" (result i64)\n");
+        for slot in 0..slots(&function.body) {
+            writeln!(self.out, "    (local $local{slot} i64)").unwrap();
+        }
+
+        self.emit(&function.body, 0, 2);
+        self.out.push_str("  )\n");
+    }
+
+    fn line(&mut self, indent: usize, ins: &str) {
+        writeln!(self.out, "{:indent$}{ins}", "", indent = 2 * indent).unwrap();
+    }
+
+    fn emit(&mut self, ast: &Ast, depth: usize, indent: usize) {
+        match ast {
+            Ast::Imm(n) => self.line(indent, &format!("i64.const {n}")),
+            Ast::Arg(n) => self.line(indent, &format!("local.get $arg{n}")),
+            Ast::Local(slot) => self.line(indent, &format!("local.get $local{slot}")),
+            Ast::Let(value, body) => {
+                self.emit(value, depth, indent);
+                self.line(indent, &format!("local.set $local{depth}"));
+                self.emit(body, depth + 1, indent);
+            }
+            Ast::If(cond, then, otherwise) => {
+                self.emit(cond, depth, indent);
+                self.line(indent, "i64.const 0");
+                self.line(indent, "i64.ne");
+                self.line(indent, "if (result i64)");
+                self.emit(then, depth, indent + 1);
+                self.line(indent, "else");
+                self.emit(otherwise, depth, indent + 1);
+                self.line(indent, "end");
+            }
+            Ast::Call(function, args) => {
+                for arg in args {
+                    self.emit(arg, depth, indent);
+                }
+                let name = &self.program.functions[*function].name;
+                self.line(indent, &format!("call $fn_{name}"));
+            }
+            Ast::BinOp(op, lhs, rhs) => {
+                self.emit(lhs, depth, indent);
+                self.emit(rhs, depth, indent);
+                self.bin_op(*op, indent);
+            }
+        }
+    }
+
+    fn bin_op(&mut self, op: BinOp, indent: usize) {
+        let comparison = match op {
+            BinOp::Eq => Some("i64.eq"),
+            BinOp::Ne => Some("i64.ne"),
+            BinOp::Lt => Some("i64.lt_s"),
+            BinOp::Le => Some("i64.le_s"),
+            BinOp::Gt => Some("i64.gt_s"),
+            BinOp::Ge => Some("i64.ge_s"),
+            _ => None,
+        };
+        if let Some(ins) = comparison {
+            self.line(indent, ins);
+            self.line(indent, "i64.extend_i32_u");
+            return;
+        }
+
+        let helper = match (op, self.mode) {
+            (BinOp::Add, ArithmeticMode::Wrapping) => return self.line(indent, "i64.add"),
+            (BinOp::Sub, ArithmeticMode::Wrapping) => return self.line(indent, "i64.sub"),
+            (BinOp::Mul, ArithmeticMode::Wrapping) => return self.line(indent, "i64.mul"),
+            (BinOp::Rem, _) => return self.line(indent, "i64.rem_s"),
+            (BinOp::Add, _) => Helper::Add,
+            (BinOp::Sub, _) => Helper::Sub,
+            (BinOp::Mul, _) => Helper::Mul,
+            (BinOp::Div, _) => Helper::Div,
+            _ => Helper::Pow,
+        };
+
+        self.helpers.insert(helper);
+        // Pow multiplies with the helper of the mode.
+        if helper == Helper::Pow && self.mode != ArithmeticMode::Wrapping {
+            self.helpers.insert(Helper::Mul);
+        }
+        self.line(indent, &format!("call $op_{}", helper.name()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Helper {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+impl Helper {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Mul => "mul",
+            Self::Div => "div",
+            Self::Pow => "pow",
+        }
+    }
+
+    // Returns the helper function for the mode. Overflow is detected
+    // without widening: a sum overflows if its sign differs from the
+    // sign of both operands, a product if dividing it by one operand
+    // does not give the other one.
+    fn definition(self, mode: ArithmeticMode) -> String {
+        // The result on overflow, given the instructions pushing
+        // the saturated value.
+        let overflow = |saturated: &str| match mode {
+            ArithmeticMode::Checked => "unreachable".to_string(),
+            ArithmeticMode::Saturating => format!("{saturated}\nreturn"),
+            ArithmeticMode::Wrapping => "i64.const -9223372036854775808\nreturn".to_string(),
+        };
+        // MIN if the value is negative, else MAX.
+        let saturated = |value: &str| {
+            format!(
+                "i64.const -9223372036854775808\n\
+                 i64.const 9223372036854775807\n\
+                 {value}\n\
+                 i64.const 0\n\
+                 i64.lt_s\n\
+                 select"
+            )
+        };
+
+        let body = match self {
+            Self::Add | Self::Sub => {
+                let (ins, sign) = match self {
+                    Self::Add => ("i64.add", "local.get $b\nlocal.get $r"),
+                    _ => ("i64.sub", "local.get $a\nlocal.get $b"),
+                };
+                format!(
+                    "local.get $a\n\
+                     local.get $b\n\
+                     {ins}\n\
+                     local.set $r\n\
+                     local.get $a\n\
+                     local.get $r\n\
+                     i64.xor\n\
+                     {sign}\n\
+                     i64.xor\n\
+                     i64.and\n\
+                     i64.const 0\n\
+                     i64.lt_s\n\
+                     if\n\
+                     {}\n\
+                     end\n\
+                     local.get $r",
+                    indent(&overflow(&saturated("local.get $a")))
+                )
+            }
+            Self::Mul => {
+                let overflow = indent(&overflow(&saturated("local.get $a\nlocal.get $b\ni64.xor")));
+                format!(
+                    "local.get $a\n\
+                     i64.const -1\n\
+                     i64.eq\n\
+                     local.get $b\n\
+                     i64.const -9223372036854775808\n\
+                     i64.eq\n\
+                     i32.and\n\
+                     if\n\
+                     {overflow}\n\
+                     end\n\
+                     local.get $a\n\
+                     local.get $b\n\
+                     i64.mul\n\
+                     local.set $r\n\
+                     local.get $a\n\
+                     i64.eqz\n\
+                     if\n\
+                     \x20 local.get $r\n\
+                     \x20 return\n\
+                     end\n\
+                     local.get $r\n\
+                     local.get $a\n\
+                     i64.div_s\n\
+                     local.get $b\n\
+                     i64.ne\n\
+                     if\n\
+                     {overflow}\n\
+                     end\n\
+                     local.get $r"
+                )
+            }
+            Self::Div => format!(
+                "local.get $a\n\
+                 i64.const -9223372036854775808\n\
+                 i64.eq\n\
+                 local.get $b\n\
+                 i64.const -1\n\
+                 i64.eq\n\
+                 i32.and\n\
+                 if\n\
+                 {}\n\
+                 end\n\
+                 local.get $a\n\
+                 local.get $b\n\
+                 i64.div_s",
+                indent(&overflow("i64.const 9223372036854775807"))
+            ),
+            // See `ArithmeticMode::apply` for negative exponents. With
+            // wrapping, the power is computed by squaring. Otherwise,
+            // any base other than 0, 1 and -1 overflows from the 64th
+            // power on, so the exponent is cut down to 64 or 65, keeping
+            // its parity, and multiplied out with the helper of the mode.
+            Self::Pow => {
+                let positive = if mode == ArithmeticMode::Wrapping {
+                    "i64.const 1\n\
+                     local.set $r\n\
+                     block\n\
+                     \x20 loop\n\
+                     \x20   local.get $b\n\
+                     \x20   i64.eqz\n\
+                     \x20   br_if 1\n\
+                     \x20   local.get $b\n\
+                     \x20   i64.const 1\n\
+                     \x20   i64.and\n\
+                     \x20   i32.wrap_i64\n\
+                     \x20   if\n\
+                     \x20     local.get $r\n\
+                     \x20     local.get $a\n\
+                     \x20     i64.mul\n\
+                     \x20     local.set $r\n\
+                     \x20   end\n\
+                     \x20   local.get $a\n\
+                     \x20   local.get $a\n\
+                     \x20   i64.mul\n\
+                     \x20   local.set $a\n\
+                     \x20   local.get $b\n\
+                     \x20   i64.const 1\n\
+                     \x20   i64.shr_u\n\
+                     \x20   local.set $b\n\
+                     \x20   br 0\n\
+                     \x20 end\n\
+                     end\n\
+                     local.get $r"
+                } else {
+                    "local.get $b\n\
+                     i64.const 65\n\
+                     i64.gt_s\n\
+                     if\n\
+                     \x20 local.get $b\n\
+                     \x20 i64.const 1\n\
+                     \x20 i64.and\n\
+                     \x20 i64.const 64\n\
+                     \x20 i64.or\n\
+                     \x20 local.set $b\n\
+                     end\n\
+                     i64.const 1\n\
+                     local.set $r\n\
+                     block\n\
+                     \x20 loop\n\
+                     \x20   local.get $b\n\
+                     \x20   i64.eqz\n\
+                     \x20   br_if 1\n\
+                     \x20   local.get $r\n\
+                     \x20   local.get $a\n\
+                     \x20   call $op_mul\n\
+                     \x20   local.set $r\n\
+                     \x20   local.get $b\n\
+                     \x20   i64.const 1\n\
+                     \x20   i64.sub\n\
+                     \x20   local.set $b\n\
+                     \x20   br 0\n\
+                     \x20 end\n\
+                     end\n\
+                     local.get $r"
+                };
+                format!(
+                    "local.get $a\n\
+                     i64.const -1\n\
+                     i64.eq\n\
+                     if\n\
+                     \x20 i64.const -1\n\
+                     \x20 i64.const 1\n\
+                     \x20 local.get $b\n\
+                     \x20 i64.const 1\n\
+                     \x20 i64.and\n\
+                     \x20 i32.wrap_i64\n\
+                     \x20 select\n\
+                     \x20 return\n\
+                     end\n\
+                     local.get $b\n\
+                     i64.const 0\n\
+                     i64.lt_s\n\
+                     if\n\
+                     \x20 i64.const 1\n\
+                     \x20 local.get $a\n\
+                     \x20 i64.div_s\n\
+                     \x20 return\n\
+                     end\n\
+                     {positive}"
+                )
+            }
+        };
+
+        let mut out = format!(
+            "  (func $op_{} (param $a i64) (param $b i64) (result i64)\n    (local $r i64)\n",
+            self.name()
+        );
+        for line in body.lines() {
+            writeln!(out, "    {line}").unwrap();
+        }
+        out.push_str("  )\n");
+        out
+    }
+}
+
+// Indents every line of the instructions by one level.
+fn indent(ins: &str) -> String {
+    ins.lines()
+        .map(|line| format!("  {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        program::Function,
+        testing::{cross_check, MODES},
+        vm::Vm,
+        Compiler,
+    };
+
+    // A minimal interpreter for the subset of WAT emitted above. It
+    // also validates the structure of the module, panicking if a
+    // function refers to an undeclared local, function or branch
+    // target, or does not leave exactly one value on the stack.
+
+    #[derive(Debug)]
+    enum Sexp {
+        Atom(String),
+        List(Vec<Sexp>),
+    }
+
+    impl Sexp {
+        fn parse(wat: &str) -> Self {
+            let spaced = wat.replace('(', " ( ").replace(')', " ) ");
+            let mut tokens = spaced.split_whitespace();
+            assert_eq!(tokens.next(), Some("("));
+            let sexp = Self::list(&mut tokens);
+            assert_eq!(tokens.next(), None, "trailing tokens");
+            sexp
+        }
+
+        fn list<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Self {
+            let mut items = vec![];
+            loop {
+                match tokens.next().expect("unbalanced parentheses") {
+                    "(" => items.push(Self::list(tokens)),
+                    ")" => return Self::List(items),
+                    atom => items.push(Self::Atom(atom.to_string())),
+                }
+            }
+        }
+
+        fn atom(&self) -> &str {
+            match self {
+                Self::Atom(atom) => atom,
+                Self::List(_) => panic!("expected an atom, found {self:?}"),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    enum Op {
+        Block(Vec<Op>),
+        Loop(Vec<Op>),
+        If(Vec<Op>, Vec<Op>),
+        Ins(String, Option<String>),
+    }
+
+    struct Func {
+        params: Vec<String>,
+        locals: Vec<String>,
+        body: Vec<Op>,
+    }
+
+    struct Module {
+        funcs: HashMap<String, Func>,
+        exports: HashMap<String, String>,
+    }
+
+    enum Flow {
+        Next,
+        Br(usize),
+        Return,
+    }
+
+    type Trap = &'static str;
+
+    fn pop(stack: &mut Vec<i64>) -> i64 {
+        stack.pop().expect("operand stack underflow")
+    }
+
+    impl Module {
+        fn parse(wat: &str) -> Self {
+            let Sexp::List(items) = Sexp::parse(wat) else {
+                unreachable!()
+            };
+            assert_eq!(items[0].atom(), "module");
+
+            let mut module = Module {
+                funcs: HashMap::new(),
+                exports: HashMap::new(),
+            };
+
+            for item in &items[1..] {
+                let Sexp::List(items) = item else {
+                    panic!("expected a function, found {item:?}")
+                };
+                assert_eq!(items[0].atom(), "func");
+                let name = items[1].atom().to_string();
+                let mut func = Func {
+                    params: vec![],
+                    locals: vec![],
+                    body: vec![],
+                };
+                let mut result = false;
+                let mut ins = vec![];
+
+                for item in &items[2..] {
+                    match item {
+                        Sexp::List(decl) => match decl[0].atom() {
+                            "export" => {
+                                let export = decl[1].atom().trim_matches('"').to_string();
+                                module.exports.insert(export, name.clone());
+                            }
+                            "param" | "local" => {
+                                assert_eq!(decl[2].atom(), "i64");
+                                let names = if decl[0].atom() == "param" {
+                                    &mut func.params
+                                } else {
+                                    &mut func.locals
+                                };
+                                names.push(decl[1].atom().to_string());
+                            }
+                            // The type of a function or of an `if`.
+                            "result" => {
+                                assert_eq!(decl[1].atom(), "i64");
+                                result = true;
+                            }
+                            _ => panic!("unexpected {decl:?}"),
+                        },
+                        Sexp::Atom(atom) => ins.push(atom.as_str()),
+                    }
+                }
+                assert!(result, "{name} has no result");
+
+                let mut ins = ins.into_iter();
+                let (body, end) = Self::ops(&mut ins, 0);
+                assert_eq!(end, None, "unexpected `{end:?}`");
+                func.body = body;
+                module.funcs.insert(name, func);
+            }
+
+            module
+        }
+
+        // Parses instructions up to an `end` or `else`, which is returned,
+        // inside of `depth` blocks.
+        fn ops<'a>(
+            ins: &mut impl Iterator<Item = &'a str>,
+            depth: usize,
+        ) -> (Vec<Op>, Option<&'a str>) {
+            let mut ops = vec![];
+
+            while let Some(mnemonic) = ins.next() {
+                let op = match mnemonic {
+                    "end" | "else" => return (ops, Some(mnemonic)),
+                    "block" | "loop" => {
+                        let (body, end) = Self::ops(ins, depth + 1);
+                        assert_eq!(end, Some("end"));
+                        if mnemonic == "block" {
+                            Op::Block(body)
+                        } else {
+                            Op::Loop(body)
+                        }
+                    }
+                    "if" => {
+                        let (then, end) = Self::ops(ins, depth + 1);
+                        let otherwise = match end {
+                            Some("else") => {
+                                let (otherwise, end) = Self::ops(ins, depth + 1);
+                                assert_eq!(end, Some("end"));
+                                otherwise
+                            }
+                            end => {
+                                assert_eq!(end, Some("end"));
+                                vec![]
+                            }
+                        };
+                        Op::If(then, otherwise)
+                    }
+                    "local.get" | "local.set" | "i64.const" | "call" | "br" | "br_if" => {
+                        let operand = ins.next().expect("missing operand");
+                        if mnemonic.starts_with("br") {
+                            assert!(operand.parse::<usize>().unwrap() < depth, "branch target");
+                        }
+                        Op::Ins(mnemonic.to_string(), Some(operand.to_string()))
+                    }
+                    _ => Op::Ins(mnemonic.to_string(), None),
+                };
+                ops.push(op);
+            }
+
+            (ops, None)
+        }
+
+        fn run(&self, export: &str, args: &[i64]) -> Result<i64, Trap> {
+            self.call(&self.exports[export], args.to_vec(), 0)
+        }
+
+        fn call(&self, name: &str, args: Vec<i64>, depth: usize) -> Result<i64, Trap> {
+            let func = &self.funcs[name];
+            assert_eq!(args.len(), func.params.len(), "arguments of {name}");
+            if depth == Vm::DEFAULT_STACK_LIMIT {
+                return Err("call stack exhausted");
+            }
+
+            let mut locals: HashMap<&str, i64> =
+                func.params.iter().map(String::as_str).zip(args).collect();
+            locals.extend(func.locals.iter().map(|local| (local.as_str(), 0)));
+            let mut stack = vec![];
+
+            match self.exec(&func.body, &mut locals, &mut stack, depth)? {
+                Flow::Return => Ok(stack.pop().expect("return value")),
+                Flow::Next => {
+                    assert_eq!(stack.len(), 1, "{name} must leave one value");
+                    Ok(stack[0])
+                }
+                Flow::Br(_) => unreachable!("branch targets are validated"),
+            }
+        }
+
+        fn exec(
+            &self,
+            ops: &[Op],
+            locals: &mut HashMap<&str, i64>,
+            stack: &mut Vec<i64>,
+            depth: usize,
+        ) -> Result<Flow, Trap> {
+            for op in ops {
+                let flow = match op {
+                    Op::Block(body) => self.exec(body, locals, stack, depth)?,
+                    Op::If(then, otherwise) => {
+                        let body = if pop(stack) != 0 { then } else { otherwise };
+                        self.exec(body, locals, stack, depth)?
+                    }
+                    Op::Loop(body) => loop {
+                        match self.exec(body, locals, stack, depth)? {
+                            Flow::Br(0) => {}
+                            flow => break flow,
+                        }
+                    },
+                    Op::Ins(ins, operand) => {
+                        self.ins(ins, operand.as_deref().unwrap_or(""), locals, stack, depth)?
+                    }
+                };
+
+                // Branches leave the block they target.
+                match flow {
+                    Flow::Next => {}
+                    Flow::Br(0) if !matches!(op, Op::Ins(..)) => {}
+                    Flow::Br(n) if !matches!(op, Op::Ins(..)) => return Ok(Flow::Br(n - 1)),
+                    flow => return Ok(flow),
+                }
+            }
+
+            Ok(Flow::Next)
+        }
+
+        fn ins(
+            &self,
+            ins: &str,
+            operand: &str,
+            locals: &mut HashMap<&str, i64>,
+            stack: &mut Vec<i64>,
+            depth: usize,
+        ) -> Result<Flow, Trap> {
+            match ins {
+                "local.get" => stack.push(locals[operand]),
+                "local.set" => {
+                    let value = pop(stack);
+                    *locals.get_mut(operand).expect("declared local") = value
+                }
+                "i64.const" => stack.push(operand.parse().unwrap()),
+                "call" => {
+                    let argc = self.funcs[operand].params.len();
+                    let args = stack.split_off(stack.len() - argc);
+                    stack.push(self.call(operand, args, depth + 1)?)
+                }
+                "br" => return Ok(Flow::Br(operand.parse().unwrap())),
+                "br_if" => {
+                    if pop(stack) != 0 {
+                        return Ok(Flow::Br(operand.parse().unwrap()));
+                    }
+                }
+                "return" => return Ok(Flow::Return),
+                "unreachable" => return Err("unreachable"),
+                "select" => {
+                    let (cond, b, a) = (pop(stack), pop(stack), pop(stack));
+                    stack.push(if cond != 0 { a } else { b })
+                }
+                "i64.eqz" | "i32.eqz" => {
+                    let value = pop(stack);
+                    stack.push((value == 0) as i64)
+                }
+                "i64.extend_i32_u" => {
+                    let value = pop(stack);
+                    stack.push(value as u32 as i64)
+                }
+                "i32.wrap_i64" => {
+                    let value = pop(stack);
+                    stack.push(value as i32 as i64)
+                }
+                _ => {
+                    let (b, a) = (pop(stack), pop(stack));
+                    stack.push(match ins {
+                        "i64.add" => a.wrapping_add(b),
+                        "i64.sub" => a.wrapping_sub(b),
+                        "i64.mul" => a.wrapping_mul(b),
+                        "i64.div_s" if b == 0 => return Err("integer divide by zero"),
+                        "i64.div_s" => a.checked_div(b).ok_or("integer overflow")?,
+                        "i64.rem_s" if b == 0 => return Err("integer divide by zero"),
+                        "i64.rem_s" => a.wrapping_rem(b),
+                        "i64.and" | "i32.and" => a & b,
+                        "i64.or" => a | b,
+                        "i64.xor" => a ^ b,
+                        "i64.shr_u" => ((a as u64) >> (b & 63)) as i64,
+                        "i64.eq" => (a == b) as i64,
+                        "i64.ne" => (a != b) as i64,
+                        "i64.lt_s" => (a < b) as i64,
+                        "i64.le_s" => (a <= b) as i64,
+                        "i64.gt_s" => (a > b) as i64,
+                        "i64.ge_s" => (a >= b) as i64,
+                        _ => panic!("unknown instruction `{ins}`"),
+                    })
+                }
+            }
+
+            Ok(Flow::Next)
+        }
+    }
+
+    fn single(ast: &Ast, arity: usize, mode: ArithmeticMode) -> String {
+        let program = Program {
+            functions: vec![Function {
+                name: "main".to_string(),
+                args: vec![String::new(); arity],
+                body: ast.clone(),
+            }],
+        };
+        module(&program, mode)
+    }
+
+    #[test]
+    fn structure() {
+        // [ x y ] let a = x * 2 in if a > y then a else y % 3
+        let ast = Ast::let_in(
+            Ast::mul(Ast::arg(0), Ast::imm(2)),
+            Ast::if_else(
+                Ast::bin_op(BinOp::Gt, Ast::local(0), Ast::arg(1)),
+                Ast::local(0),
+                Ast::bin_op(BinOp::Rem, Ast::arg(1), Ast::imm(3)),
+            ),
+        );
+
+        assert_eq!(
+            single(&ast, 2, ArithmeticMode::Wrapping),
+            r#"(module
+  (func $fn_main (export "main") (param $arg0 i64) (param $arg1 i64) (result i64)
+    (local $local0 i64)
+    local.get $arg0
+    i64.const 2
+    i64.mul
+    local.set $local0
+    local.get $local0
+    local.get $arg1
+    i64.gt_s
+    i64.extend_i32_u
+    i64.const 0
+    i64.ne
+    if (result i64)
+      local.get $local0
+    else
+      local.get $arg1
+      i64.const 3
+      i64.rem_s
+    end
+  )
+)
+"#
+        );
+
+        let wat = single(&ast, 2, ArithmeticMode::Checked);
+        assert!(wat.contains("    call $op_mul\n"));
+        assert!(wat.contains("  (func $op_mul (param $a i64) (param $b i64) (result i64)\n"));
+        assert!(!wat.contains("$op_add"));
+
+        let module = Module::parse(&wat);
+        assert_eq!(module.run("main", &[7, 3]), Ok(14));
+        assert_eq!(module.run("main", &[1, 3]), Ok(0));
+        assert_eq!(module.run("main", &[i64::MAX, 3]), Err("unreachable"));
+    }
+
+    #[test]
+    fn operators() {
+        let values = [
+            i64::MIN,
+            i64::MIN + 1,
+            -3_037_000_500,
+            -65,
+            -3,
+            -2,
+            -1,
+            0,
+            1,
+            2,
+            3,
+            62,
+            63,
+            64,
+            65,
+            3_037_000_499,
+            3_037_000_500,
+            i64::MAX - 1,
+            i64::MAX,
+        ];
+
+        for mode in MODES {
+            for op in [
+                BinOp::Add,
+                BinOp::Sub,
+                BinOp::Mul,
+                BinOp::Div,
+                BinOp::Rem,
+                BinOp::Pow,
+                BinOp::Eq,
+                BinOp::Ne,
+                BinOp::Lt,
+                BinOp::Le,
+                BinOp::Gt,
+                BinOp::Ge,
+            ] {
+                let module =
+                    Module::parse(&single(&Ast::bin_op(op, Ast::arg(0), Ast::arg(1)), 2, mode));
+
+                for a in values {
+                    for b in values {
+                        assert_eq!(
+                            module.run("main", &[a, b]).ok(),
+                            mode.apply(op, a, b).ok(),
+                            "{a} {op} {b} in {mode:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_programs() {
+        cross_check(20, |ast, mode| {
+            let module = Module::parse(&single(ast, 3, mode));
+            move |args: &[i64]| module.run("main", args).ok()
+        });
+    }
+
+    #[test]
+    fn calls() {
+        let mut c = Compiler::new();
+        let program = c
+            .pass1_program(
+                "def fact [ n ] if n <= 1 then 1 else n * fact(n - 1);
+                 def sq [ a ] a * a;
+                 def main [ x y ] sq(x + 1) - fact(y)",
+            )
+            .unwrap();
+
+        let wat = c.link_wat(&program).unwrap();
+        assert!(
+            wat.contains("(func $fn_main (export \"main\") (param $arg0 i64) (param $arg1 i64)")
+        );
+        assert!(wat.contains("(func $fn_fact (param $arg0 i64) (result i64)"));
+        assert!(wat.contains("call $fn_fact"));
+
+        let module = Module::parse(&wat);
+        let asm = c.link(&program);
+        for args in [[3, 5], [-4, 0], [10, 20]] {
+            assert_eq!(module.run("main", &args).ok(), c.vm().run(&asm, &args).ok());
+        }
+        assert_eq!(module.run("main", &[3, 5]), Ok(-104));
+    }
+}
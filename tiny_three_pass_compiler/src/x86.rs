@@ -0,0 +1,1037 @@
+use std::fmt::{self, Display, Write};
+
+use crate::{
+    arith::ArithmeticMode,
+    ast::{Ast, BinOp},
+    codegen::need,
+    program::Program,
+};
+
+// Generates x86-64 assembly in the syntax of the GNU assembler,
+// following the System V calling convention: a function takes its
+// first six arguments in `rdi`, `rsi`, `rdx`, `rcx`, `r8` and `r9`,
+// the others on the stack, and returns its result in `rax`.
+//
+// The entry point of the program is a global function named like
+// it, so that `def area [ w h ] w * h` can be called from C as
+//
+//     int64_t area(int64_t w, int64_t h);
+//
+// Other functions are only reachable through local labels, which
+// are prefixed with the name of the entry point to allow several
+// programs in one object file.
+//
+// Code is generated in three steps:
+//
+// 1. The body of each function is lowered to instructions on an
+//    unlimited number of virtual registers, see `Ir`.
+// 2. Virtual registers are mapped to machine registers by linear
+//    scan allocation, spilling to the stack frame if they run out.
+// 3. Every instruction is emitted on its allocated operands, using
+//    `rax`, `rcx` and `rdx` as scratch registers.
+//
+// Errors trap: division by zero raises `SIGFPE` through `idiv`,
+// checked overflow `SIGILL` through `ud2`.
+pub(crate) fn assembly(program: &Program, mode: ArithmeticMode) -> String {
+    let entry = program
+        .entry()
+        .map_or("", |entry| &program.functions[entry].name);
+    let labels: Vec<String> = program
+        .functions
+        .iter()
+        .map(|function| format!(".L{entry}.{}", function.name))
+        .collect();
+
+    let mut out = String::from("\t.text\n");
+    for (index, function) in program.functions.iter().enumerate() {
+        if Some(index) == program.entry() {
+            write!(
+                out,
+                "\t.globl\t{entry}\n\t.type\t{entry}, @function\n{entry}:\n"
+            )
+            .unwrap();
+        }
+
+        let lowered = Lowering::new(&function.body, function.arity());
+        let homes = function.arity().min(ARGS.len());
+        let allocation = allocate(&lowered.code, lowered.vregs, homes);
+
+        Emitter {
+            out: &mut out,
+            mode,
+            label: &labels[index],
+            labels: &labels,
+            allocation: &allocation,
+            trap: false,
+        }
+        .function(&lowered.code, function.arity());
+
+        if Some(index) == program.entry() {
+            writeln!(out, "\t.size\t{entry}, .-{entry}").unwrap();
+        }
+    }
+    out.push_str("\t.section\t.note.GNU-stack,\"\",@progbits\n");
+    out
+}
+
+// The registers taking the arguments of a call, in order.
+const ARGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+// The registers available to the allocator, those clobbered by calls
+// first. `rax`, `rcx` and `rdx` are kept as scratch registers, since
+// `idiv` needs `rax` and `rdx`.
+const REGISTERS: [&str; 11] = [
+    "rsi", "rdi", "r8", "r9", "r10", "r11", "rbx", "r12", "r13", "r14", "r15",
+];
+
+// The registers preserved by calls, which a function has to save
+// before using them.
+const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+type Vreg = usize;
+type Label = usize;
+
+// An instruction on virtual registers. Every register is assigned
+// once, except for the result of a conditional, which is assigned
+// at the end of either branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ir {
+    // Loads the n-th argument of the function.
+    Param(Vreg, usize),
+    Imm(Vreg, i64),
+    Mov(Vreg, Vreg),
+    BinOp(BinOp, Vreg, Vreg, Vreg),
+    // Jumps to the label if the register is zero.
+    Jz(Vreg, Label),
+    Jmp(Label),
+    Label(Label),
+    Call(Vreg, usize, Vec<Vreg>),
+    Ret(Vreg),
+}
+
+impl Ir {
+    // Returns the registers the instruction assigns or reads.
+    fn vregs(&self) -> Vec<Vreg> {
+        match self {
+            Self::Param(v, _) | Self::Imm(v, _) | Self::Jz(v, _) | Self::Ret(v) => vec![*v],
+            Self::Mov(dst, src) => vec![*dst, *src],
+            Self::BinOp(_, dst, lhs, rhs) => vec![*dst, *lhs, *rhs],
+            Self::Call(dst, _, args) => std::iter::once(*dst).chain(args.clone()).collect(),
+            Self::Jmp(_) | Self::Label(_) => vec![],
+        }
+    }
+}
+
+struct Lowering {
+    code: Vec<Ir>,
+    vregs: usize,
+    labels: usize,
+    args: Vec<Option<Vreg>>,
+    locals: Vec<Vreg>,
+}
+
+impl Lowering {
+    // Lowers the body of a function, loading the arguments it uses
+    // on entry.
+    fn new(body: &Ast, arity: usize) -> Self {
+        let mut used = vec![false; arity];
+        uses(body, &mut used);
+
+        let mut lowering = Self {
+            code: vec![],
+            vregs: 0,
+            labels: 0,
+            args: vec![None; arity],
+            locals: vec![],
+        };
+        for (n, used) in used.into_iter().enumerate() {
+            if used {
+                let v = lowering.vreg();
+                lowering.code.push(Ir::Param(v, n));
+                lowering.args[n] = Some(v);
+            }
+        }
+
+        let result = lowering.expr(body);
+        lowering.code.push(Ir::Ret(result));
+        lowering
+    }
+
+    fn vreg(&mut self) -> Vreg {
+        self.vregs += 1;
+        self.vregs - 1
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    // Emits the instructions evaluating the AST and returns the
+    // register holding its value.
+    fn expr(&mut self, ast: &Ast) -> Vreg {
+        match ast {
+            Ast::Imm(n) => {
+                let v = self.vreg();
+                self.code.push(Ir::Imm(v, *n));
+                v
+            }
+            Ast::Arg(n) => self.args[*n].expect("argument is loaded"),
+            Ast::Local(slot) => self.locals[*slot],
+            Ast::Let(value, body) => {
+                let value = self.expr(value);
+                self.locals.push(value);
+                let result = self.expr(body);
+                self.locals.pop();
+                result
+            }
+            Ast::If(cond, then, otherwise) => {
+                let (otherwise_label, end) = (self.label(), self.label());
+                let result = self.vreg();
+
+                let cond = self.expr(cond);
+                self.code.push(Ir::Jz(cond, otherwise_label));
+                let then = self.expr(then);
+                self.code.push(Ir::Mov(result, then));
+                self.code.push(Ir::Jmp(end));
+                self.code.push(Ir::Label(otherwise_label));
+                let otherwise = self.expr(otherwise);
+                self.code.push(Ir::Mov(result, otherwise));
+                self.code.push(Ir::Label(end));
+                result
+            }
+            Ast::Call(function, args) => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                let result = self.vreg();
+                self.code.push(Ir::Call(result, *function, args));
+                result
+            }
+            // The operand with the higher Sethi–Ullman number goes
+            // first, which keeps fewer registers live at a time.
+            Ast::BinOp(op, lhs, rhs) => {
+                let (lhs, rhs) = if need(rhs) > need(lhs) {
+                    let rhs = self.expr(rhs);
+                    (self.expr(lhs), rhs)
+                } else {
+                    (self.expr(lhs), self.expr(rhs))
+                };
+                let result = self.vreg();
+                self.code.push(Ir::BinOp(*op, result, lhs, rhs));
+                result
+            }
+        }
+    }
+}
+
+// Marks the arguments the AST refers to.
+fn uses(ast: &Ast, used: &mut [bool]) {
+    match ast {
+        Ast::Arg(n) => used[*n] = true,
+        Ast::Imm(_) | Ast::Local(_) => {}
+        Ast::BinOp(_, lhs, rhs) | Ast::Let(lhs, rhs) => {
+            uses(lhs, used);
+            uses(rhs, used);
+        }
+        Ast::If(cond, then, otherwise) => {
+            uses(cond, used);
+            uses(then, used);
+            uses(otherwise, used);
+        }
+        Ast::Call(_, args) => args.iter().for_each(|arg| uses(arg, used)),
+    }
+}
+
+// The location of a value: a register or a slot of the stack frame,
+// addressed relative to `rbp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Loc {
+    Reg(&'static str),
+    Frame(i64),
+}
+
+impl Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "%{reg}"),
+            Self::Frame(offset) => write!(f, "{offset}(%rbp)"),
+        }
+    }
+}
+
+// The stack frame of a function holds, from `rbp` downwards, the
+// arguments passed in registers, the spilled virtual registers and
+// the callee-saved registers in use. Its size keeps `rsp` aligned
+// to 16 bytes, as calls require.
+#[derive(Debug)]
+struct Allocation {
+    locs: Vec<Loc>,
+    saved: Vec<(&'static str, Loc)>,
+    frame: usize,
+}
+
+// Allocates registers by linear scan (Poletto and Sarkar, 1999).
+//
+// A virtual register is live from its first to its last mention in
+// the code. Since there are no loops, this interval covers every
+// point where it is live, and possibly more if a branch jumps over
+// some of it. Registers are handed out in the order the intervals
+// start, and returned when they end. Values live across a call get
+// a callee-saved register. If none is free, the interval ending last
+// is spilled to the stack frame, which is either the new one or one
+// whose register it takes.
+//
+// An interval ending where another one starts may share its
+// register: the emitter reads all operands of an instruction before
+// writing its result.
+fn allocate(code: &[Ir], vregs: usize, homes: usize) -> Allocation {
+    let mut start = vec![usize::MAX; vregs];
+    let mut end = vec![0; vregs];
+    let mut calls = vec![];
+    for (at, ir) in code.iter().enumerate() {
+        if let Ir::Call(..) = ir {
+            calls.push(at);
+        }
+        for v in ir.vregs() {
+            start[v] = start[v].min(at);
+            end[v] = end[v].max(at);
+        }
+    }
+    let across_call: Vec<bool> = (0..vregs)
+        .map(|v| calls.iter().any(|&at| start[v] < at && at < end[v]))
+        .collect();
+
+    let mut order: Vec<Vreg> = (0..vregs).collect();
+    order.sort_by_key(|&v| start[v]);
+
+    let mut locs = vec![Loc::Frame(0); vregs];
+    let mut free = REGISTERS.to_vec();
+    let mut active: Vec<Vreg> = vec![];
+    let mut spills = 0;
+    let mut spill = || {
+        spills += 1;
+        Loc::Frame(-8 * (homes + spills) as i64)
+    };
+
+    for v in order {
+        active.retain(|&live| {
+            if end[live] > start[v] {
+                return true;
+            }
+            if let Loc::Reg(reg) = locs[live] {
+                free.push(reg);
+            }
+            false
+        });
+        free.sort_by_key(|reg| REGISTERS.iter().position(|r| r == reg));
+
+        let usable = |reg: &str| !across_call[v] || CALLEE_SAVED.contains(&reg);
+        if let Some(i) = free.iter().position(|reg| usable(reg)) {
+            locs[v] = Loc::Reg(free.remove(i));
+            active.push(v);
+            continue;
+        }
+
+        let victim = active
+            .iter()
+            .copied()
+            .filter(|&live| matches!(locs[live], Loc::Reg(reg) if usable(reg)))
+            .max_by_key(|&live| end[live])
+            .filter(|&live| end[live] > end[v]);
+        match victim {
+            Some(victim) => {
+                locs[v] = locs[victim];
+                locs[victim] = spill();
+                active.retain(|&live| live != victim);
+                active.push(v);
+            }
+            None => locs[v] = spill(),
+        }
+    }
+
+    let saved: Vec<(&'static str, Loc)> = CALLEE_SAVED
+        .into_iter()
+        .filter(|reg| locs.contains(&Loc::Reg(reg)))
+        .enumerate()
+        .map(|(i, reg)| (reg, Loc::Frame(-8 * (homes + spills + i + 1) as i64)))
+        .collect();
+    let frame = (8 * (homes + spills + saved.len())).next_multiple_of(16);
+
+    Allocation { locs, saved, frame }
+}
+
+const MAX: &str = "$9223372036854775807";
+
+struct Emitter<'a> {
+    out: &'a mut String,
+    mode: ArithmeticMode,
+    // The label of the function, also the prefix of its local labels.
+    label: &'a str,
+    labels: &'a [String],
+    allocation: &'a Allocation,
+    // Whether the function jumps to its `ud2` on checked overflow.
+    trap: bool,
+}
+
+impl Emitter<'_> {
+    fn function(&mut self, code: &[Ir], arity: usize) {
+        let label = self.label;
+        self.label(label);
+        self.ins("pushq\t%rbp");
+        self.ins("movq\t%rsp, %rbp");
+        if self.allocation.frame > 0 {
+            self.ins(&format!("subq\t${}, %rsp", self.allocation.frame));
+        }
+        for &(reg, loc) in &self.allocation.saved {
+            self.ins(&format!("movq\t%{reg}, {loc}"));
+        }
+        for (n, reg) in ARGS.iter().take(arity).enumerate() {
+            self.ins(&format!("movq\t%{reg}, {}", home(n)));
+        }
+
+        for ir in code {
+            self.ir(ir);
+        }
+
+        if self.trap {
+            self.label(&format!("{label}.trap"));
+            self.ins("ud2");
+        }
+    }
+
+    fn ins(&mut self, ins: &str) {
+        writeln!(self.out, "\t{ins}").unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.out, "{label}:").unwrap();
+    }
+
+    fn loc(&self, v: Vreg) -> Loc {
+        self.allocation.locs[v]
+    }
+
+    // Moves between locations, through `rax` if both are in memory.
+    fn mov(&mut self, src: Loc, dst: Loc) {
+        match (src, dst) {
+            _ if src == dst => {}
+            (Loc::Frame(_), Loc::Frame(_)) => {
+                self.ins(&format!("movq\t{src}, %rax"));
+                self.ins(&format!("movq\t%rax, {dst}"));
+            }
+            _ => self.ins(&format!("movq\t{src}, {dst}")),
+        }
+    }
+
+    fn ir(&mut self, ir: &Ir) {
+        match ir {
+            Ir::Param(v, n) => self.mov(home(*n), self.loc(*v)),
+            Ir::Imm(v, n) => match self.loc(*v) {
+                loc if i32::try_from(*n).is_ok() => self.ins(&format!("movq\t${n}, {loc}")),
+                Loc::Reg(reg) => self.ins(&format!("movabsq\t${n}, %{reg}")),
+                loc => {
+                    self.ins(&format!("movabsq\t${n}, %rax"));
+                    self.ins(&format!("movq\t%rax, {loc}"));
+                }
+            },
+            Ir::Mov(dst, src) => self.mov(self.loc(*src), self.loc(*dst)),
+            Ir::BinOp(op, dst, lhs, rhs) => {
+                self.bin_op(*op, self.loc(*lhs), self.loc(*rhs));
+                self.ins(&format!("movq\t%rax, {}", self.loc(*dst)));
+            }
+            Ir::Jz(v, label) => {
+                self.ins(&format!("cmpq\t$0, {}", self.loc(*v)));
+                self.ins(&format!("je\t{}.{label}", self.label));
+            }
+            Ir::Jmp(label) => self.ins(&format!("jmp\t{}.{label}", self.label)),
+            Ir::Label(label) => self.label(&format!("{}.{label}", self.label)),
+            Ir::Call(dst, function, args) => self.call(*function, args, self.loc(*dst)),
+            Ir::Ret(v) => {
+                self.mov(self.loc(*v), Loc::Reg("rax"));
+                for &(reg, loc) in &self.allocation.saved {
+                    self.ins(&format!("movq\t{loc}, %{reg}"));
+                }
+                self.ins("leave");
+                self.ins("ret");
+            }
+        }
+    }
+
+    // Arguments are pushed in reverse order, then the first six are
+    // popped into their registers, which avoids overwriting a register
+    // that holds another argument. The stack is padded to keep it
+    // aligned if an odd number of arguments remains on it.
+    fn call(&mut self, function: usize, args: &[Vreg], dst: Loc) {
+        let on_stack = args.len().saturating_sub(ARGS.len());
+        let pad = on_stack % 2 * 8;
+
+        if pad > 0 {
+            self.ins(&format!("subq\t${pad}, %rsp"));
+        }
+        for arg in args.iter().rev() {
+            self.ins(&format!("pushq\t{}", self.loc(*arg)));
+        }
+        for reg in ARGS.iter().take(args.len()) {
+            self.ins(&format!("popq\t%{reg}"));
+        }
+        self.ins(&format!("call\t{}", self.labels[function]));
+        if on_stack > 0 {
+            self.ins(&format!("addq\t${}, %rsp", 8 * on_stack + pad));
+        }
+        self.mov(Loc::Reg("rax"), dst);
+    }
+
+    // Computes `lhs op rhs` into `rax`. Sequences of several
+    // instructions use numeric local labels, which only need to be
+    // unique within the sequence.
+    fn bin_op(&mut self, op: BinOp, lhs: Loc, rhs: Loc) {
+        let condition = match op {
+            BinOp::Eq => Some("e"),
+            BinOp::Ne => Some("ne"),
+            BinOp::Lt => Some("l"),
+            BinOp::Le => Some("le"),
+            BinOp::Gt => Some("g"),
+            BinOp::Ge => Some("ge"),
+            _ => None,
+        };
+        if let Some(condition) = condition {
+            self.ins(&format!("movq\t{lhs}, %rax"));
+            self.ins(&format!("cmpq\t{rhs}, %rax"));
+            self.ins(&format!("set{condition}\t%al"));
+            self.ins("movzbq\t%al, %rax");
+            return;
+        }
+
+        match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                let ins = match op {
+                    BinOp::Add => "addq",
+                    BinOp::Sub => "subq",
+                    _ => "imulq",
+                };
+                self.ins(&format!("movq\t{lhs}, %rax"));
+                self.ins(&format!("{ins}\t{rhs}, %rax"));
+                // A wrapped sum or difference has the opposite sign
+                // of the exact one, a product has the sign of lhs ^ rhs.
+                if op == BinOp::Mul {
+                    self.overflow(&[
+                        &format!("movq\t{lhs}, %rax"),
+                        &format!("xorq\t{rhs}, %rax"),
+                        "sarq\t$63, %rax",
+                        &format!("movabsq\t{MAX}, %rdx"),
+                        "xorq\t%rdx, %rax",
+                    ]);
+                } else {
+                    self.overflow(&["sarq\t$63, %rax", "btcq\t$63, %rax"]);
+                }
+            }
+            // x86 traps on MIN / -1, so division by -1 is a negation.
+            BinOp::Div => {
+                self.ins(&format!("movq\t{lhs}, %rax"));
+                self.ins(&format!("cmpq\t$-1, {rhs}"));
+                self.ins("jne\t1f");
+                self.ins("negq\t%rax");
+                self.overflow(&[&format!("movabsq\t{MAX}, %rax")]);
+                self.ins("jmp\t2f");
+                self.label("1");
+                self.ins("cqto");
+                self.ins(&format!("idivq\t{rhs}"));
+                self.label("2");
+            }
+            BinOp::Rem => {
+                self.ins(&format!("movq\t{lhs}, %rax"));
+                self.ins(&format!("cmpq\t$-1, {rhs}"));
+                self.ins("jne\t1f");
+                self.ins("xorl\t%eax, %eax");
+                self.ins("jmp\t2f");
+                self.label("1");
+                self.ins("cqto");
+                self.ins(&format!("idivq\t{rhs}"));
+                self.ins("movq\t%rdx, %rax");
+                self.label("2");
+            }
+            _ => self.pow(lhs, rhs),
+        }
+    }
+
+    // Handles signed overflow of the last instruction: nothing to do
+    // with wrapping, a trap if checked, and the given instructions
+    // computing the saturated value into `rax` if saturating.
+    fn overflow(&mut self, saturated: &[&str]) {
+        match self.mode {
+            ArithmeticMode::Wrapping => {}
+            ArithmeticMode::Checked => {
+                self.trap = true;
+                self.ins(&format!("jo\t{}.trap", self.label));
+            }
+            ArithmeticMode::Saturating => {
+                self.ins("jno\t8f");
+                for ins in saturated {
+                    self.ins(ins);
+                }
+                self.label("8");
+            }
+        }
+    }
+
+    // Computes `lhs ** rhs` into `rax`, like the `$op_pow` helper of
+    // the WebAssembly backend: the base in `rcx`, the exponent in `rdx`.
+    fn pow(&mut self, lhs: Loc, rhs: Loc) {
+        self.ins(&format!("movq\t{lhs}, %rcx"));
+        self.ins(&format!("movq\t{rhs}, %rdx"));
+        // (-1) ** n alternates with the parity of n.
+        self.ins("cmpq\t$-1, %rcx");
+        self.ins("jne\t1f");
+        self.ins("movq\t$1, %rax");
+        self.ins("testq\t$1, %rdx");
+        self.ins("jz\t9f");
+        self.ins("movq\t$-1, %rax");
+        self.ins("jmp\t9f");
+        // A negative exponent gives 1 / lhs.
+        self.label("1");
+        self.ins("testq\t%rdx, %rdx");
+        self.ins("jns\t2f");
+        self.ins("movq\t$1, %rax");
+        self.ins("cqto");
+        self.ins("idivq\t%rcx");
+        self.ins("jmp\t9f");
+        self.label("2");
+        self.ins("movq\t$1, %rax");
+
+        if self.mode == ArithmeticMode::Wrapping {
+            self.label("3");
+            self.ins("testq\t%rdx, %rdx");
+            self.ins("jz\t9f");
+            self.ins("testq\t$1, %rdx");
+            self.ins("jz\t4f");
+            self.ins("imulq\t%rcx, %rax");
+            self.label("4");
+            self.ins("imulq\t%rcx, %rcx");
+            self.ins("shrq\t$1, %rdx");
+            self.ins("jmp\t3b");
+        } else {
+            self.ins("cmpq\t$65, %rdx");
+            self.ins("jle\t3f");
+            self.ins("andq\t$1, %rdx");
+            self.ins("orq\t$64, %rdx");
+            self.label("3");
+            self.ins("testq\t%rdx, %rdx");
+            self.ins("jz\t9f");
+            self.ins("imulq\t%rcx, %rax");
+            if self.mode == ArithmeticMode::Checked {
+                self.trap = true;
+                self.ins(&format!("jo\t{}.trap", self.label));
+            } else {
+                self.ins("jo\t5f");
+            }
+            self.ins("decq\t%rdx");
+            self.ins("jmp\t3b");
+            // The saturated power is negative if the base is negative
+            // and the exponent odd.
+            if self.mode == ArithmeticMode::Saturating {
+                self.label("5");
+                self.ins(&format!("movabsq\t{MAX}, %rax"));
+                self.ins("testq\t%rcx, %rcx");
+                self.ins("jns\t9f");
+                self.ins(&format!("testq\t$1, {rhs}"));
+                self.ins("jz\t9f");
+                self.ins("notq\t%rax");
+            }
+        }
+        self.label("9");
+    }
+}
+
+// Returns the location of the n-th argument: the frame slot it is
+// saved to on entry, or its slot in the frame of the caller.
+fn home(n: usize) -> Loc {
+    if n < ARGS.len() {
+        Loc::Frame(-8 * (n as i64 + 1))
+    } else {
+        Loc::Frame(16 + 8 * (n - ARGS.len()) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::Command};
+
+    use super::*;
+    use crate::{
+        program::Function,
+        testing::{programs, MODES},
+        vm::Vm,
+        Compiler,
+    };
+
+    // A function to call natively, with the argument lists to call it on.
+    struct Calls {
+        symbol: String,
+        arity: usize,
+        cases: Vec<Vec<i64>>,
+    }
+
+    // Assembles the code with the local C compiler, together with a
+    // driver calling each function on its cases. Returns the results
+    // in order, `None` for calls that trapped.
+    fn run_native(test: &str, asm: &str, calls: &[Calls]) -> Vec<Option<i64>> {
+        let mut driver = String::from(
+            "#include <inttypes.h>\n\
+             #include <setjmp.h>\n\
+             #include <signal.h>\n\
+             #include <stdio.h>\n\
+             \n\
+             static sigjmp_buf env;\n\
+             static void trap(int sig) { (void)sig; siglongjmp(env, 1); }\n",
+        );
+        // Every function gets a wrapper taking its arguments as an
+        // array, and a flat array of the arguments of all its cases.
+        let mut table = vec![];
+        for (i, calls) in calls.iter().enumerate() {
+            let params = vec!["int64_t"; calls.arity].join(", ");
+            let args: Vec<String> = (0..calls.arity).map(|n| format!("a[{n}]")).collect();
+            let cases: Vec<String> = calls
+                .cases
+                .iter()
+                .flatten()
+                .map(|&n| match n {
+                    i64::MIN => "INT64_MIN".to_string(),
+                    n => format!("{n}LL"),
+                })
+                .chain(["0".to_string()])
+                .collect();
+            write!(
+                driver,
+                "int64_t {symbol}({params});\n\
+                 static int64_t call{i}(const int64_t *a) {{ return {symbol}({}); }}\n\
+                 static const int64_t cases{i}[] = {{ {} }};\n",
+                args.join(", "),
+                cases.join(", "),
+                symbol = calls.symbol,
+            )
+            .unwrap();
+            table.push(format!(
+                "{{ call{i}, cases{i}, {}, {} }}",
+                calls.cases.len(),
+                calls.arity
+            ));
+        }
+        write!(
+            driver,
+            "static const struct {{\n\
+             \x20 int64_t (*call)(const int64_t *);\n\
+             \x20 const int64_t *cases;\n\
+             \x20 size_t count, arity;\n\
+             }} table[] = {{ {} }};\n\
+             \n\
+             int main(void) {{\n\
+             \x20 signal(SIGFPE, trap);\n\
+             \x20 signal(SIGILL, trap);\n\
+             \x20 for (size_t i = 0; i < sizeof table / sizeof *table; i++) {{\n\
+             \x20   for (size_t n = 0; n < table[i].count; n++) {{\n\
+             \x20     if (sigsetjmp(env, 1) == 0)\n\
+             \x20       printf(\"%\" PRId64 \"\\n\", table[i].call(table[i].cases + n * table[i].arity));\n\
+             \x20     else\n\
+             \x20       puts(\"trap\");\n\
+             \x20   }}\n\
+             \x20 }}\n\
+             \x20 return 0;\n\
+             }}\n",
+            table.join(", ")
+        )
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("tpc-x86-{}-{test}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("driver.c"), driver).unwrap();
+        fs::write(dir.join("code.s"), asm).unwrap();
+
+        let cc = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-o", "driver", "driver.c", "code.s"])
+            .output()
+            .expect("a C compiler is installed as `cc`");
+        assert!(
+            cc.status.success(),
+            "{}",
+            String::from_utf8_lossy(&cc.stderr)
+        );
+        let output = Command::new(dir.join("driver")).output().unwrap();
+        assert!(output.status.success());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let results: Vec<Option<i64>> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().ok())
+            .collect();
+        assert_eq!(
+            results.len(),
+            calls.iter().map(|c| c.cases.len()).sum::<usize>()
+        );
+        results
+    }
+
+    fn single(name: &str, ast: &Ast, arity: usize, mode: ArithmeticMode) -> String {
+        let program = Program {
+            functions: vec![Function {
+                name: name.to_string(),
+                args: vec![String::new(); arity],
+                body: ast.clone(),
+            }],
+        };
+        assembly(&program, mode)
+    }
+
+    #[test]
+    fn structure() {
+        // [ x y ] if x < y then x * 2 else y
+        let ast = Ast::if_else(
+            Ast::bin_op(BinOp::Lt, Ast::arg(0), Ast::arg(1)),
+            Ast::mul(Ast::arg(0), Ast::imm(2)),
+            Ast::arg(1),
+        );
+
+        assert_eq!(
+            single("f", &ast, 2, ArithmeticMode::Checked),
+            r#"	.text
+	.globl	f
+	.type	f, @function
+f:
+.Lf.f:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	subq	$16, %rsp
+	movq	%rdi, -8(%rbp)
+	movq	%rsi, -16(%rbp)
+	movq	-8(%rbp), %rsi
+	movq	-16(%rbp), %rdi
+	movq	%rsi, %rax
+	cmpq	%rdi, %rax
+	setl	%al
+	movzbq	%al, %rax
+	movq	%rax, %r8
+	cmpq	$0, %r8
+	je	.Lf.f.0
+	movq	$2, %r8
+	movq	%rsi, %rax
+	imulq	%r8, %rax
+	jo	.Lf.f.trap
+	movq	%rax, %rsi
+	jmp	.Lf.f.1
+.Lf.f.0:
+	movq	%rdi, %rsi
+.Lf.f.1:
+	movq	%rsi, %rax
+	leave
+	ret
+.Lf.f.trap:
+	ud2
+	.size	f, .-f
+	.section	.note.GNU-stack,"",@progbits
+"#
+        );
+    }
+
+    #[test]
+    fn allocation() {
+        // Sixteen bindings live at once, more than there are registers.
+        let count = 16;
+        let mut ast = (0..count)
+            .map(Ast::local)
+            .reduce(|sum, local| Ast::mul(sum, Ast::add(local, Ast::imm(1))))
+            .unwrap();
+        for n in (0..count).rev() {
+            ast = Ast::let_in(Ast::add(Ast::arg(0), Ast::imm(n as i64)), ast);
+        }
+        let lowered = Lowering::new(&ast, 1);
+        let allocation = allocate(&lowered.code, lowered.vregs, 1);
+        assert!(allocation.locs.contains(&Loc::Frame(-16)));
+        assert_eq!(allocation.saved.len(), CALLEE_SAVED.len());
+
+        // x + f(x) keeps x in a callee-saved register across the call.
+        let program = Program {
+            functions: vec![
+                Function {
+                    name: "f".to_string(),
+                    args: vec!["a".to_string()],
+                    body: Ast::arg(0),
+                },
+                Function {
+                    name: "g".to_string(),
+                    args: vec!["x".to_string()],
+                    body: Ast::add(Ast::arg(0), Ast::call(0, vec![Ast::arg(0)])),
+                },
+            ],
+        };
+        let lowered = Lowering::new(&program.functions[1].body, 1);
+        let allocation = allocate(&lowered.code, lowered.vregs, 1);
+        assert_eq!(allocation.locs[0], Loc::Reg("rbx"));
+        assert_eq!(allocation.saved, [("rbx", Loc::Frame(-16))]);
+
+        let asm = format!(
+            "{}{}",
+            single("spills", &ast, 1, ArithmeticMode::Wrapping),
+            assembly(&program, ArithmeticMode::Wrapping)
+        );
+        let vm = Vm::new();
+        let cases: Vec<Vec<i64>> = vec![vec![-20], vec![-1], vec![0], vec![3]];
+        let results = run_native(
+            "allocation",
+            &asm,
+            &[
+                Calls {
+                    symbol: "spills".to_string(),
+                    arity: 1,
+                    cases: cases.clone(),
+                },
+                Calls {
+                    symbol: "g".to_string(),
+                    arity: 1,
+                    cases: cases.clone(),
+                },
+            ],
+        );
+        let spills = crate::codegen::generate(&ast);
+        let g = crate::codegen::link(&program);
+        let expected: Vec<Option<i64>> = cases
+            .iter()
+            .map(|args| vm.run(&spills, args).ok())
+            .chain(cases.iter().map(|args| vm.run(&g, args).ok()))
+            .collect();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn operators() {
+        let values = [
+            i64::MIN,
+            i64::MIN + 1,
+            -3_037_000_500,
+            -65,
+            -3,
+            -2,
+            -1,
+            0,
+            1,
+            2,
+            3,
+            62,
+            63,
+            64,
+            65,
+            3_037_000_499,
+            3_037_000_500,
+            i64::MAX - 1,
+            i64::MAX,
+        ];
+        let cases: Vec<Vec<i64>> = values
+            .iter()
+            .flat_map(|&a| values.iter().map(move |&b| vec![a, b]))
+            .collect();
+
+        let (mut asm, mut calls, mut expected) = (String::new(), vec![], vec![]);
+        for mode in MODES {
+            for op in [
+                BinOp::Add,
+                BinOp::Sub,
+                BinOp::Mul,
+                BinOp::Div,
+                BinOp::Rem,
+                BinOp::Pow,
+                BinOp::Eq,
+                BinOp::Ne,
+                BinOp::Lt,
+                BinOp::Le,
+                BinOp::Gt,
+                BinOp::Ge,
+            ] {
+                let symbol = format!("op{}", calls.len());
+                let ast = Ast::bin_op(op, Ast::arg(0), Ast::arg(1));
+                asm.push_str(&single(&symbol, &ast, 2, mode));
+                calls.push(Calls {
+                    symbol,
+                    arity: 2,
+                    cases: cases.clone(),
+                });
+                expected.extend(cases.iter().map(|args| {
+                    (
+                        format!("{} {op} {} in {mode:?}", args[0], args[1]),
+                        mode.apply(op, args[0], args[1]).ok(),
+                    )
+                }));
+            }
+        }
+
+        let results = run_native("operators", &asm, &calls);
+        for (result, (case, expected)) in results.into_iter().zip(expected) {
+            assert_eq!(result, expected, "{case}");
+        }
+    }
+
+    #[test]
+    fn random_programs() {
+        // Assembles all programs at once, since running the
+        // assembler for each of them would take too long.
+        let (mut asm, mut calls, mut expected) = (String::new(), vec![], vec![]);
+
+        for (ast, mode, cases) in programs(21) {
+            let symbol = format!("random{}", calls.len());
+            asm.push_str(&single(&symbol, &ast, 3, mode));
+            expected.extend(cases.iter().map(|args| {
+                (
+                    format!("{ast} for {args:?}"),
+                    ast.eval_with(args, mode).ok(),
+                )
+            }));
+            calls.push(Calls {
+                symbol,
+                arity: 3,
+                cases,
+            });
+        }
+
+        let results = run_native("random_programs", &asm, &calls);
+        for (result, (case, expected)) in results.into_iter().zip(expected) {
+            assert_eq!(result, expected, "{case}");
+        }
+    }
+
+    #[test]
+    fn calls() {
+        let mut c = Compiler::new();
+        let program = c
+            .pass1_program(
+                "def fact [ n ] if n <= 1 then 1 else n * fact(n - 1);
+                 def sq [ a ] a * a;
+                 def sum [ a b c d e f g ] a - b + c - d + e - f + g * 2;
+                 def run [ x y ] sq(x + 1) - fact(y) + sum(x, y, 3, 4, 5, 6, x * y)",
+            )
+            .unwrap();
+
+        let asm = c.link_x86(&program).unwrap();
+        assert!(asm.contains("\t.globl\trun\n\t.type\trun, @function\nrun:\n.Lrun.run:\n"));
+        assert!(asm.contains("\n.Lrun.fact:\n"));
+        assert!(asm.contains("\tcall\t.Lrun.fact\n"));
+        assert!(asm.contains("\tsubq\t$8, %rsp\n"));
+        assert!(asm.contains("\taddq\t$16, %rsp\n"));
+
+        let code = c.link(&program);
+        let cases = vec![vec![3, 5], vec![-4, 0], vec![10, 20]];
+        let expected: Vec<Option<i64>> = cases
+            .iter()
+            .map(|args| c.vm().run(&code, args).ok())
+            .collect();
+
+        let results = run_native(
+            "calls",
+            &asm,
+            &[Calls {
+                symbol: "run".to_string(),
+                arity: 2,
+                cases,
+            }],
+        );
+        assert_eq!(results, expected);
+        assert_eq!(results[0], Some(-104 + 26));
+    }
+}